@@ -0,0 +1,77 @@
+// actor is anything that lives by a Routine: thinks, walks around and carries things,
+// the simulation and the updaters handle every actor type the same way
+
+use comfy::{hecs::Component, Entity};
+use core::fmt::Debug;
+use serde_json::value::RawValue;
+
+use crate::{
+    core::{position::Ps, Initializable},
+    gameplay::ent::officeworker::OfficeWorker,
+    persistence::PersistentCell,
+    savegame::{self, SaveError, SavedActor},
+    simulation::{self, Simulation},
+    state::{Reality, WorldState},
+    updaters,
+};
+
+use super::{
+    dog::Dog,
+    sanity::{Routine, Sanity, SelfAware},
+};
+
+/// Every actor type, in the order they are initialized, think and are saved in.
+/// A new type is added here and nowhere else.
+pub const ACTOR_TYPES: [ActorType; 2] = [ActorType::of::<Dog>(), ActorType::of::<OfficeWorker>()];
+
+pub trait Actor: Initializable + Component + Debug {
+    type R: Routine;
+
+    /// Class the actor is known by in persistence.
+    const CLASS: &'static str;
+
+    fn sa(&self) -> &SelfAware<Self::R>;
+
+    fn sa_mut(&mut self) -> &mut SelfAware<Self::R>;
+
+    /// Called every frame after the simulation, for animations, statusbars and the like.
+    fn update_view(&self, _entity: Entity, _paused: bool) {}
+
+    /// Extra debug drawing for the selected actor while paused.
+    fn draw_debug(&self) {}
+}
+
+/// Called with every actor of a type and its mind.
+pub type Visitor<'a> = dyn FnMut(Entity, &mut Sanity) + 'a;
+
+/// What the simulation, saves and the window do with the actors of one type.
+pub struct ActorType {
+    /// Class the actors are known by in persistence and in saves.
+    pub class: &'static str,
+    pub initialize: fn(&mut Reality),
+    pub update: fn(&mut Simulation, f32),
+    /// Initialized actors with the cell each of them stands on.
+    pub cells: fn() -> Vec<(Ps, PersistentCell)>,
+    /// Calls back with every actor in entity order and its mind unlocked.
+    pub visit: fn(&mut Visitor<'_>),
+    pub save: fn() -> Box<RawValue>,
+    pub respawn: fn(&RawValue) -> Result<(), SaveError>,
+    pub reregister: fn(&Reality),
+    pub view: fn(&WorldState),
+}
+
+impl ActorType {
+    const fn of<A: SavedActor>() -> Self {
+        Self {
+            class: A::CLASS,
+            initialize: simulation::update_init::<A>,
+            update: simulation::update_actors::<A>,
+            cells: simulation::actor_cells::<A>,
+            visit: simulation::visit_actors::<A>,
+            save: savegame::save_actors::<A>,
+            respawn: savegame::respawn_actors::<A>,
+            reregister: savegame::reregister_actors::<A>,
+            view: updaters::update_actor_views::<A>,
+        }
+    }
+}
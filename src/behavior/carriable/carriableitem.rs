@@ -1,21 +1,27 @@
-use comfy::{Entity, HashMap, Mutex, Vec2, RandomRange};
+use comfy::{Entity, HashMap, Mutex, Vec2};
+use serde::{Deserialize, Serialize};
 
-use crate::{behavior::creatures::PsOffsetProvider, core::position::Ps};
+use crate::{behavior::{creatures::PsOffsetProvider, item_types::ItemType}, core::{position::Ps, rng::WorldRng}};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct CarriableItemHandle {
+    #[serde(with = "crate::core::serialization::entity")]
     pub item_id: Entity,
-    pub item_type: &'static str,
+    #[serde(with = "crate::core::serialization::item_type")]
+    pub item_type: ItemType,
+    #[serde(with = "crate::core::serialization::option_entity")]
     pub carried_by: Option<Entity>,
     pub position: Ps,
+    #[serde(with = "crate::core::serialization::vec2")]
     pub offset: Vec2,
+    #[serde(with = "crate::core::serialization::vec2")]
     pub personal_offset: Vec2,
     pub consumed: bool
 }
 
 impl CarriableItemHandle {
-    pub fn new(item_type: &'static str, entity: Entity, position: Ps) -> Self {
-        let off = Vec2::new(f32::gen_range(-0.2, 0.2), f32::gen_range(0.01, 0.2));
+    pub fn new(item_type: &'static str, entity: Entity, position: Ps, rng: &mut WorldRng) -> Self {
+        let off = Vec2::new(rng.gen_range(-0.2, 0.2), rng.gen_range(0.01, 0.2));
         Self {
             item_id: entity,
             carried_by: None,
@@ -1,4 +1,6 @@
-use crate::{behavior::creatures::PsOffsetProvider, core::position::Ps};
+use comfy::{Entity, Mutex};
+
+use crate::{behavior::creatures::PsOffsetProvider, core::position::Ps, persistence::{Persistence, CARRIABLE}};
 
 use super::carriableitem::{CarriableItemHandle, CarriableItems};
 
@@ -40,7 +42,28 @@ pub fn find_all_available_with_type(carriables: &CarriableItems, item_type: Opti
 
 pub fn find_closest_available_with_type(carriables: &CarriableItems, item_type: Option<&'static str>, close_to: Ps) -> Option<CarriableItemHandle> {
     let mut all = find_all_available_with_type(carriables, item_type);
-    all.sort_unstable_by_key(|val| close_to.manhattan_distance(&val.get_ps()));
+    // tie-break on entity, hashmap order is not stable between runs
+    all.sort_unstable_by_key(|val| (close_to.manhattan_distance(&val.get_ps()), val.item_id));
     // println!("Found: {:?}", all);
     all.first().map(|handle| handle.to_owned())
-}
\ No newline at end of file
+}
+
+/// Closest available item of `item_type` within `radius` cells of `close_to` on its floor,
+/// looked up in the persistence index around it instead of going over every carriable.
+/// The index is let go before the items are looked at, whoever holds the items may lock it.
+pub fn find_closest_around(persistence: &Mutex<Persistence>, carriables: &CarriableItems, item_type: &'static str, close_to: Ps, radius: usize) -> Option<CarriableItemHandle> {
+    // closest first, ties by entity like the other searches
+    let around: Vec<Entity> = persistence
+        .lock()
+        .query_radius(close_to, radius)
+        .into_iter()
+        .filter(|(_, cell)| cell.item_subclass == CARRIABLE && cell.item_class == item_type)
+        .map(|(_, cell)| cell.entity)
+        .collect();
+    let items = carriables.lock();
+    around
+        .iter()
+        .filter_map(|entity| items.get(entity))
+        .find(|item| item.available())
+        .copied()
+}
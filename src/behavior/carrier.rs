@@ -1,8 +1,11 @@
 use comfy::{Entity, HashSet};
+use serde::{Deserialize, Serialize};
+
 use super::{carriable::carriableitem::{CarriableItemHandle, CarriableItems}, creatures::PsOffsetProvider};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Carrier {
+    #[serde(with = "crate::core::serialization::entity_set")]
     items_carried: HashSet<Entity>
 }
 
@@ -21,6 +24,10 @@ impl Carrier {
         return !self.items_carried.is_empty()
     }
 
+    pub fn items(&self) -> impl Iterator<Item = &Entity> {
+        self.items_carried.iter()
+    }
+
     pub fn has_item(&self, entity: &Entity) -> bool {
         return self.items_carried.contains(entity);
     }
@@ -1,22 +1,69 @@
-use std::collections::LinkedList;
+use std::collections::{LinkedList, VecDeque};
 
+use crate::building::Movement;
 use crate::core::position::{Ps, PsProvider, PsSigned};
-use comfy::RandomRange;
+use crate::core::rng::WorldRng;
 use comfy::{num_traits::ToPrimitive, Vec2};
+use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Direction {
     Up,
     Down,
     Left,
     Right,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
 }
 
-#[derive(Debug)]
+impl Direction {
+    /// Change of the cell, y goes up.
+    pub fn delta(&self) -> (isize, isize) {
+        match self {
+            Direction::Up => (0, 1),
+            Direction::Down => (0, -1),
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+            Direction::UpLeft => (-1, 1),
+            Direction::UpRight => (1, 1),
+            Direction::DownLeft => (-1, -1),
+            Direction::DownRight => (1, -1),
+        }
+    }
+
+    pub fn from_delta(dx: isize, dy: isize) -> Option<Direction> {
+        match (dx.signum(), dy.signum()) {
+            (0, 1) => Some(Direction::Up),
+            (0, -1) => Some(Direction::Down),
+            (-1, 0) => Some(Direction::Left),
+            (1, 0) => Some(Direction::Right),
+            (-1, 1) => Some(Direction::UpLeft),
+            (1, 1) => Some(Direction::UpRight),
+            (-1, -1) => Some(Direction::DownLeft),
+            (1, -1) => Some(Direction::DownRight),
+            _ => None,
+        }
+    }
+
+    pub fn is_diagonal(&self) -> bool {
+        let (dx, dy) = self.delta();
+        dx != 0 && dy != 0
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MovementIntention {
     pub target: Option<Ps>,
     pub calculated_steps: LinkedList<Ps>,
     pub stuck: bool,
+    /// The target is shared, every step is read off its flow field when the last one is taken.
+    pub over_field: bool,
+    /// Ticks the first steps were planned to start on, the rest of the path isn't planned yet.
+    pub schedule: VecDeque<u64>,
+    /// Planning failed for these steps, they are walked as they are until the path changes.
+    pub unplanned: bool,
 }
 
 impl MovementIntention {
@@ -51,13 +98,16 @@ impl MovementIntention {
         // println!("replace_front ({}:{:?}, {})\nPath: {:?}", path.len(), path, remove_all_including, self.calculated_steps);
         self.remove_front(remove_all_including);
         self.prepend(path);
+        self.schedule.clear();
+        self.unplanned = false;
         // println!("Result: {:?}", self.calculated_steps);
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MovingObjectData {
     pub pos: Ps,
+    #[serde(with = "crate::core::serialization::vec2")]
     pub offset: Vec2,
     pub direction: Option<Direction>,
 }
@@ -112,28 +162,33 @@ impl MovingObjectData {
                 self.offset.x += ds;
                 self.check_movement_stop(self.offset.x, false)
             }
+            Some(diagonal) => {
+                // the step is √2 long, both sides of the cell are crossed at once
+                let (dx, dy) = diagonal.delta();
+                let ds = ds / std::f32::consts::SQRT_2;
+                self.offset.x += dx as f32 * ds;
+                self.offset.y += dy as f32 * ds;
+                self.check_movement_stop(self.offset.x, dx < 0)
+            }
             None => None,
         }
     }
 
     pub fn start_move_direction(&mut self, dir: Direction) {
-        match dir {
-            Direction::Down => self.pos.y -= 1,
-            Direction::Up => self.pos.y += 1,
-            Direction::Left => self.pos.x -= 1,
-            Direction::Right => self.pos.x += 1,
+        let (dx, dy) = dir.delta();
+        self.pos.x = (self.pos.x as isize + dx) as usize;
+        self.pos.y = (self.pos.y as isize + dy) as usize;
+        if dx != 0 {
+            self.offset.x = -dx as f32;
         }
-        match dir {
-            Direction::Down => self.offset.y = 1.0,
-            Direction::Up => self.offset.y = -1.0,
-            Direction::Left => self.offset.x = 1.0,
-            Direction::Right => self.offset.x = -1.0,
+        if dy != 0 {
+            self.offset.y = -dy as f32;
         }
         self.direction = Some(dir)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SelfMovingThingData {
     pub loc: MovingObjectData,
     pub speed: f32,
@@ -160,12 +215,15 @@ impl SelfMovingThingData {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SelfRoutingData {
     pub movement: SelfMovingThingData,
     pub current_move_path: MovementIntention,
     pub reliable_steps_left: isize,
     reached_cell: bool,
+    /// Destination of a path that was asked for and isn't found yet. A path already heading
+    /// there is walked on meanwhile.
+    waiting_for: Option<Ps>,
 }
 
 impl crate::core::position::PsProvider for SelfRoutingData {
@@ -186,27 +244,15 @@ impl PsOffsetProvider for SelfRoutingData {
 
 pub fn try_get_direction_from_to(from: Ps, to: Ps) -> Option<Direction> {
     match to - from {
-        PsSigned { x: 0, y } => {
-            if y > 0 {
-                Some(Direction::Up)
-            } else {
-                Some(Direction::Down)
-            }
-        }
-        PsSigned { y: 0, x } => {
-            if x > 0 {
-                Some(Direction::Right)
-            } else {
-                Some(Direction::Left)
-            }
-        }
+        // not moving at all, any direction will do
+        PsSigned { x: 0, y: 0 } => Some(Direction::Down),
+        PsSigned { x, y } if x.abs() <= 1 && y.abs() <= 1 => Direction::from_delta(x, y),
         PsSigned { x, y } => {
             println!(
                 "ERROR: Direction difference is {:?}, {:?}, sad but true [ from:{:?} to:{:?} ]",
                 x, y, from, to
             );
-            panic!("Direction difference");
-            // None
+            None
         }
     }
 }
@@ -215,11 +261,14 @@ pub fn get_direction_from_to(from: Ps, to: Ps) -> Direction {
     try_get_direction_from_to(from, to).unwrap()
 }
 
-pub fn validate_path(path: &LinkedList<Ps>) -> Option<(Ps, Ps, usize)> {
+/// First step of `path` that doesn't go to a cell next to the one before it, across a corner
+/// only when `movement` has diagonals.
+pub fn validate_path(path: &LinkedList<Ps>, movement: Movement) -> Option<(Ps, Ps, usize)> {
     for i in 1..path.len() {
         let prev = path.iter().nth(i - 1).unwrap();
         let current = path.iter().nth(i).unwrap();
-        if prev.manhattan_distance(current) != 1 {
+        let diagonal = prev.x != current.x && prev.y != current.y;
+        if !prev.is_next_to(current) || (diagonal && !movement.diagonal) {
             return Some((prev.to_owned(), current.to_owned(), i));
         }
     }
@@ -253,9 +302,53 @@ impl SelfRoutingData {
     pub fn stop_moving(&mut self) {
         self.current_move_path.target = None;
         self.current_move_path.calculated_steps = LinkedList::new();
+        self.current_move_path.schedule.clear();
+        self.current_move_path.unplanned = false;
         self.reached_cell = false
     }
 
+    /// Waits until a path to `destination` is found, a path heading anywhere else is dropped.
+    pub fn wait_for_path(&mut self, destination: Ps) {
+        if self.current_move_path.target != Some(destination) {
+            self.stop_moving();
+        }
+        self.waiting_for = Some(destination);
+    }
+
+    /// Destination of the path being waited for.
+    pub fn waiting_for_path(&self) -> Option<Ps> {
+        self.waiting_for
+    }
+
+    pub fn is_waiting_for_path(&self) -> bool {
+        self.waiting_for.is_some()
+    }
+
+    /// The path came or isn't needed anymore.
+    pub fn stop_waiting_for_path(&mut self) {
+        self.waiting_for = None;
+    }
+
+    /// Keeps the target but forgets the steps, the path is found again before the next step.
+    pub fn invalidate_path(&mut self) {
+        self.reliable_steps_left = 0;
+    }
+
+    /// Forgets when the steps were to be taken, they are planned again before the next one.
+    pub fn forget_schedule(&mut self) {
+        self.current_move_path.schedule.clear();
+        self.current_move_path.unplanned = false;
+    }
+
+    /// Puts the thing right in the middle of `pos` with nowhere to go.
+    pub fn relocate(&mut self, pos: Ps) {
+        self.movement.loc.pos = pos;
+        self.movement.loc.offset = Vec2::new(0.0, 0.0);
+        self.movement.loc.direction = None;
+        self.reliable_steps_left = 0;
+        self.stop_moving();
+    }
+
     pub fn stuck(&mut self) {
         self.current_move_path.stuck()
     }
@@ -272,6 +365,7 @@ impl SelfRoutingData {
         if self.current_move_path.exists() {
             self.reached_cell = false;
             self.unstuck();
+            self.current_move_path.schedule.pop_front();
             let popd = self.current_move_path.calculated_steps.pop_front();
             // println!("Pop next loc, left: {}", self.current_move_path.calculated_steps.len());
             popd
@@ -283,6 +377,12 @@ impl SelfRoutingData {
     pub fn step_next_direction(&mut self) -> Option<Direction> {
         match self.consume_next_loc() {
             None => None,
+            Some(loc) if loc.floor != self.movement.loc.pos.floor => {
+                // stairs and elevators take you up or down without leaving the cell
+                self.reliable_steps_left -= 1;
+                self.movement.loc.pos = loc;
+                None
+            }
             Some(loc) => {
                 if let Some(direction) = self.try_get_next_direction(loc) {
                     // we have next direction to move
@@ -292,10 +392,12 @@ impl SelfRoutingData {
                     self.movement.loc.pos = loc;
                     Some(direction)
                 } else {
+                    // the path skips cells, the agent stays put and finds a new one
                     println!(
                         "Cannot find direction from {:?} to {:?}",
                         self.movement.loc.pos, loc
                     );
+                    self.stop_moving();
                     None
                 }
             }
@@ -334,22 +436,26 @@ impl SelfRoutingData {
                 target: None,
                 calculated_steps: LinkedList::new(),
                 stuck: false,
+                over_field: false,
+                schedule: VecDeque::new(),
+                unplanned: false,
             },
             reliable_steps_left: 0,
             reached_cell: true,
+            waiting_for: None,
         }
     }
 
-    fn calculate_reliable_steps(&self, max: usize) -> isize {
+    fn calculate_reliable_steps(&self, max: usize, rng: &mut WorldRng) -> isize {
         if max < 4 {
             max.to_isize().unwrap()
         } else {
             isize::min(
-                (((max as f64) / 2.0) * f64::gen_range(1.0, 2.0))
+                (((max as f64) / 2.0) * rng.gen_range(1.0, 2.0))
                     .floor()
                     .to_isize()
                     .unwrap_or(max.to_isize().unwrap()),
-                i32::gen_range(20, 80).to_isize().unwrap(),
+                rng.gen_range(20, 80),
             )
         }
     }
@@ -372,13 +478,33 @@ impl SelfRoutingData {
         }
     }
 
-    pub fn set_movement_path(&mut self, path: LinkedList<Ps>, target: Ps) {
-        self.reliable_steps_left = self.calculate_reliable_steps(path.len());
+    pub fn set_movement_path(&mut self, path: LinkedList<Ps>, target: Ps, rng: &mut WorldRng) {
+        self.reliable_steps_left = self.calculate_reliable_steps(path.len(), rng);
         self.reached_cell = false;
+        self.waiting_for = None;
         self.current_move_path = MovementIntention {
             calculated_steps: path,
             target: Some(target),
             stuck: false,
+            over_field: false,
+            schedule: VecDeque::new(),
+            unplanned: false,
+        }
+    }
+
+    /// Heads to the shared `target` with `next` as the only step, the one after it is read off
+    /// the target's flow field again.
+    pub fn step_down_field(&mut self, target: Ps, next: Ps) {
+        self.reliable_steps_left = 1;
+        self.reached_cell = false;
+        self.waiting_for = None;
+        self.current_move_path = MovementIntention {
+            calculated_steps: LinkedList::from([next]),
+            target: Some(target),
+            stuck: false,
+            over_field: true,
+            schedule: VecDeque::new(),
+            unplanned: false,
         }
     }
 
@@ -386,12 +512,38 @@ impl SelfRoutingData {
         &mut self,
         path: &LinkedList<Ps>,
         remove_all_including: usize,
+        rng: &mut WorldRng,
     ) {
         self.reached_cell = false;
         self.current_move_path
             .replace_front(path, remove_all_including);
         self.reliable_steps_left =
-            self.calculate_reliable_steps(self.current_move_path.calculated_steps.len());
+            self.calculate_reliable_steps(self.current_move_path.calculated_steps.len(), rng);
+    }
+
+    /// Walks `planned` steps on their ticks instead of the first `replaced` steps of the path.
+    pub fn follow_schedule(&mut self, planned: Vec<(Ps, u64)>, replaced: usize) {
+        let (steps, ticks): (LinkedList<Ps>, VecDeque<u64>) = planned.into_iter().unzip();
+        // walking around someone takes more steps than the path had there
+        self.reliable_steps_left += steps.len().saturating_sub(replaced) as isize;
+        self.current_move_path.replace_front(&steps, replaced);
+        self.current_move_path.schedule = ticks;
+    }
+
+    /// The next step may be taken on `tick`, it is either planned for now or not planned at all.
+    pub fn is_step_due(&self, tick: u64) -> bool {
+        self.current_move_path
+            .schedule
+            .front()
+            .is_none_or(|start| *start <= tick)
+    }
+
+    /// The next step should have been taken a while before `tick`.
+    pub fn is_late(&self, tick: u64, step: u64) -> bool {
+        self.current_move_path
+            .schedule
+            .front()
+            .is_some_and(|start| start + step < tick)
     }
 
     pub fn is_no_reliabler_steps_left(&self) -> bool {
@@ -422,12 +574,9 @@ impl SelfRoutingData {
 
     pub fn predict_next_pos(&self, dir: Direction) -> PsSigned {
         let mut pos: PsSigned = self.movement.loc.pos.into();
-        match dir {
-            Direction::Down => pos.y -= 1,
-            Direction::Up => pos.y += 1,
-            Direction::Left => pos.x -= 1,
-            Direction::Right => pos.x += 1,
-        }
+        let (dx, dy) = dir.delta();
+        pos.x += dx;
+        pos.y += dy;
         pos
     }
 
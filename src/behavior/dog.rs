@@ -1,17 +1,19 @@
-use comfy::Entity;
+use comfy::{world, AnimatedSprite, Entity};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     behavior::messaging::MessagingHost, core::{
         position::{Ps, PsProvider},
+        rng::WorldRng,
         Initializable,
-    }, state::Reality
+    }, persistence::CREATURE, state::Reality, worldmap::Walker
 };
 
 use super::{
-    carriable::carriablesearch::find_closest_available_with_type, creatures::PsOffsetProvider, item_types::{BONE, TRASHCAN}, mental::{IntentionClass, IntentionCompleted}, messaging::communication::Communicator, routine::entitytypehunter::EntityTypeHunter, sanity::{Sanity, SelfAware}
+    actor::Actor, carriable::carriablesearch::find_closest_around, creatures::PsOffsetProvider, item_types::{BONE, DOG, TRASHCAN}, mental::{IntentionClass, IntentionCompleted}, messaging::communication::Communicator, routine::entitytypehunter::EntityTypeHunter, sanity::{Sanity, SelfAware}
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DogRoutine {
     hunter: EntityTypeHunter,
 }
@@ -32,17 +34,22 @@ impl super::sanity::Routine for DogRoutine {
             | IntentionCompleted::Undefined => {
                 if sanity.carrier.has_anything() && sanity.no_intentions_left() {
                     // drop that to the nearest trash can!
-                    let target_opt = find_closest_available_with_type(
+                    let here = sanity.get_current_ps();
+                    let (width, height) = map.building.floor(here.floor).wh_usize();
+                    let target_opt = find_closest_around(
+                        &map.persistence,
                         &map.carriables,
-                        Some(TRASHCAN),
-                        sanity.get_current_ps(),
+                        TRASHCAN,
+                        here,
+                        width + height,
                     );
                     match target_opt {
                         Some(target) => {
-                            sanity.intend_go_to(target.position);
+                            sanity.intend_go_to_shared(target.position);
                             sanity
                                 .intend_with_priority(-1, IntentionClass::ConsumeAnyCarriedItem());
-                            let cell = map.cellmap.pick_random_passable_ps();
+                            let floor = map.building.floor(sanity.get_current_ps().floor);
+                            let cell = floor.pick_random_passable_ps(&mut sanity.rng);
                             // go away
                             sanity.intend_with_priority(-2, IntentionClass::MoveToDestination(cell))
                         }
@@ -66,16 +73,18 @@ pub struct Dog {
 }
 
 impl Dog {
-    pub fn new(name: String, speed: f32, pos: Ps) -> Self {
+    pub fn new(name: String, speed: f32, pos: Ps, rng: &mut WorldRng) -> Self {
         Self {
             name,
             initialized: false,
             sa: SelfAware::new(
                 speed,
                 pos,
+                Walker::Dog,
                 Box::new(DogRoutine {
                     hunter: EntityTypeHunter::new(BONE, true),
                 }),
+                rng.fork(),
             ),
         }
     }
@@ -92,6 +101,7 @@ impl Initializable for Dog {
         let handle = MessagingHost::new();
         println!("Initialized doge {:?}: {:?}", entity, handle);
         msg.insert(*entity, handle);
+        reality.persistence.lock().register(*entity, DOG, CREATURE, &self.sa);
         self.initialized = true;
     }
 
@@ -109,3 +119,32 @@ impl PsOffsetProvider for Dog {
         self.sa.get_offset()
     }
 }
+
+impl Actor for Dog {
+    type R = DogRoutine;
+
+    const CLASS: &'static str = DOG;
+
+    fn sa(&self) -> &SelfAware<DogRoutine> {
+        &self.sa
+    }
+
+    fn sa_mut(&mut self) -> &mut SelfAware<DogRoutine> {
+        &mut self.sa
+    }
+
+    fn update_view(&self, entity: Entity, paused: bool) {
+        if paused {
+            return;
+        }
+        if let Ok(mut anim) = world().get::<&mut AnimatedSprite>(entity) {
+            let direction = self.sa.sanity.lock().mv.movement.loc.direction;
+            let (dx, _) = direction.map_or((0, 0), |direction| direction.delta());
+            if dx < 0 {
+                anim.play("idle_left")
+            } else if dx > 0 {
+                anim.play("idle")
+            }
+        }
+    }
+}
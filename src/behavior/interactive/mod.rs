@@ -30,7 +30,7 @@ impl InteractiveObjectHandle {
 
     pub fn get_interactive_ps(&self) -> Ps {
         match self.interaction_ps_offset {
-            Some(offset) => (self.position + offset).into(),
+            Some(offset) => Into::<Ps>::into(self.position + offset).with_floor(self.position.floor),
             None => self.position
         }
     }
@@ -2,3 +2,14 @@ pub static BONE: &str = "bone";
 pub static TRASHCAN: &str = "trash_can";
 pub static CONPUTER: &str = "conputer";
 pub static BED: &str = "bed";
+pub static DOG: &str = "dog";
+pub static OFFICE_WORKER: &str = "office_worker";
+
+// spelled as an alias so serde doesn't try to borrow it from the input
+pub type ItemType = &'static str;
+
+pub fn from_name(name: &str) -> Option<ItemType> {
+    [BONE, TRASHCAN, CONPUTER, BED, DOG, OFFICE_WORKER]
+        .into_iter()
+        .find(|item_type| *item_type == name)
+}
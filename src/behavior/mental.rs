@@ -1,5 +1,7 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    core::position::Ps, gameplay::gametime::{Time, TimeSpan}, utils::anyhashmap::{create_primitive_hashmap, PrimitiveHashMap, PrimitiveValue}
+    behavior::item_types::ItemType, core::position::Ps, gameplay::gametime::{Time, TimeSpan}, utils::anyhashmap::{create_primitive_hashmap, PrimitiveHashMap, PrimitiveValue}
 };
 
 const MIN_PRIORITY: i32 = -1000;
@@ -22,14 +24,15 @@ pub enum IntentionCompleted {
     Undefined,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MemoryEvent {
     pub name: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Memory {
     pub events: Vec<MemoryEvent>,
+    #[serde(with = "crate::core::serialization::primitive_map")]
     pub values: PrimitiveHashMap,
     _limit: usize,
     pub last_intention: Option<IntentionClass>,
@@ -70,25 +73,27 @@ impl Memory {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum IntentionClass {
     MoveToDestination(Ps),
     MoveToPs(Ps),
+    /// Like `MoveToPs`, for a place many others head to as well.
+    MoveToSharedPs(Ps),
     WaitCycles(isize),
-    PickItemOfType(&'static str),
-    ConsumeItemOfType(&'static str),
+    PickItemOfType(#[serde(with = "crate::core::serialization::item_type")] ItemType),
+    ConsumeItemOfType(#[serde(with = "crate::core::serialization::item_type")] ItemType),
     ConsumeAnyItem(),
     PickAnyItem(),
-    DropCarriedItemOfType(&'static str),
+    DropCarriedItemOfType(#[serde(with = "crate::core::serialization::item_type")] ItemType),
     DropAnyCarriedItem(),
-    ConsumeCarriedItemOfType(&'static str),
+    ConsumeCarriedItemOfType(#[serde(with = "crate::core::serialization::item_type")] ItemType),
     ConsumeAnyCarriedItem(),
     UseInteractiveOnce(),
     UseInteractiveCycles(isize),
     UseInteractiveMinutes(isize)
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Intention {
     pub priority: i32,
     pub value: IntentionClass,
@@ -100,7 +105,7 @@ impl Intention {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IntentionsCortex {
     queue: Vec<Intention>,
     current: Option<Intention>,
@@ -197,7 +202,7 @@ impl IntentionsCortex {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Brains {
     cycles_counter: isize,
     pub mem: Memory,
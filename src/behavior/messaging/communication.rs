@@ -3,9 +3,9 @@ use std::cmp;
 use comfy::{world, Entity, HashSet};
 
 use crate::{
+    building::Building,
     core::{anycellmap::AnyCellmap, position::Ps},
     state::Reality,
-    worldmap::Cellmap,
 };
 
 #[derive(Copy, Clone, PartialEq, Eq, comfy::Hash)]
@@ -22,22 +22,23 @@ impl Communicator {
         };
     }
 
-    pub fn mark_position_on_map(&self, entity: Entity, map: &mut AnyCellmap<HashSet<Entity>>) {
-        map.get_xy_mut(self.ps.x, self.ps.y).insert(entity);
+    pub fn mark_position_on_map(&self, entity: Entity, floors: &mut [AnyCellmap<HashSet<Entity>>]) {
+        floors[self.ps.floor].get_xy_mut(self.ps.x, self.ps.y).insert(entity);
     }
 
     pub fn find_visible_entities(&self, map: &Reality) -> Vec<Entity> {
         let mut found: Vec<Entity> = Vec::new();
         let lock = &map.comm_map.lock();
-        for (ps, entity) in query_all_max_distance(self.vision_limit, self, lock).into_iter() {
-            if self.is_ps_visible_from(&ps, &map.cellmap) {
+        let floor = &lock[self.ps.floor];
+        for (ps, entity) in query_all_max_distance(self.vision_limit, self, floor).into_iter() {
+            if self.is_ps_visible_from(&ps, &map.building) {
                 found.push(entity)
             }
         }
         return found;
     }
 
-    pub fn is_ps_visible_from(&self, other: &Ps, map: &Cellmap) -> bool {
+    pub fn is_ps_visible_from(&self, other: &Ps, map: &Building) -> bool {
         return calculate_vision_ray(&self.ps, other, map);
     }
 }
@@ -56,7 +57,7 @@ fn query_all_max_distance(
 
     for i in from_x..to_x {
         for j in from_y..to_y {
-            let pos = Ps::from((i, j));
+            let pos = Ps::from((i, j)).with_floor(from.ps.floor);
             if pos.manhattan_distance(&from.ps) > (distance as i32) {
                 continue;
             }
@@ -69,7 +70,7 @@ fn query_all_max_distance(
     return found;
 }
 
-fn calculate_vision_ray(from: &Ps, to: &Ps, map: &Cellmap) -> bool {
+fn calculate_vision_ray(from: &Ps, to: &Ps, map: &Building) -> bool {
     // println!("Raycasting: {:?} ---> {:?}", from, to);
     let mut reached = false;
     let mut next: Ps = from.clone();
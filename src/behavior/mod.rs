@@ -1,3 +1,4 @@
+pub mod actor;
 pub mod creatures;
 pub mod routing;
 pub mod sanity;
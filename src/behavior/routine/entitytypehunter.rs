@@ -1,6 +1,7 @@
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EntityTypeHunter {
-    item_type: &'static str,
+    #[serde(with = "crate::core::serialization::item_type")]
+    item_type: ItemType,
     pick: bool,
 }
 
@@ -11,11 +12,13 @@ impl EntityTypeHunter {
 }
 
 use comfy::Entity;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     behavior::{
+        item_types::ItemType,
         carriable::
-            carriablesearch::find_closest_available_with_type, mental::{IntentionClass, IntentionCompleted}, messaging::communication::Communicator, sanity::*
+            carriablesearch::find_closest_around, mental::{IntentionClass, IntentionCompleted}, messaging::communication::Communicator, sanity::*
     }, core::position::PsProvider, state::Reality
 };
 
@@ -35,18 +38,25 @@ impl Routine for EntityTypeHunter {
             IntentionCompleted::Success
             | IntentionCompleted::Failure
             | IntentionCompleted::Undefined => {
-                let target_opt = find_closest_available_with_type(
+                // the whole floor is in sight, the index only walks the cells something is on
+                let here = sanity.get_current_ps();
+                let (width, height) = map.building.floor(here.floor).wh_usize();
+                let target_opt = find_closest_around(
+                    &map.persistence,
                     &map.carriables,
-                    Some(self.item_type),
-                    sanity.get_current_ps(),
+                    self.item_type,
+                    here,
+                    width + height,
                 );
                 match target_opt {
                     Some(target) => {
                         if sanity.no_intentions_left() {
                             // go and take
                             // println!("New intention: take item {:?} at {:?}", self.item_type, target.position);
-                            sanity
-                                .intend_with_priority(2, IntentionClass::MoveToPs(target.position));
+                            sanity.intend_with_priority(
+                                2,
+                                IntentionClass::MoveToSharedPs(target.position),
+                            );
                             sanity.intend_with_priority(
                                 1,
                                 if self.pick {
@@ -1,10 +1,11 @@
 use comfy::Entity;
+use serde::{Deserialize, Serialize};
 
 use crate::{behavior::{mental::IntentionCompleted, messaging::communication::Communicator, sanity::{Routine, Sanity}}, core::position::Ps, state::Reality};
 
 use super::randomwalk::RandomStepRoutine;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GoToRoutine {
     pub target: Ps
 }
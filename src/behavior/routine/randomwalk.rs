@@ -1,7 +1,7 @@
 use comfy::Entity;
 
 use crate::{
-    behavior::{mental::{IntentionCompleted, PRIORITY_BASE}, messaging::communication::Communicator, routing::SUCCESSORS, sanity::*}, core::position::{PsProvider, PsSigned}, state::Reality
+    behavior::{mental::{IntentionCompleted, PRIORITY_BASE}, messaging::communication::Communicator, sanity::*}, core::position::PsProvider, state::Reality
 };
 
 
@@ -23,7 +23,8 @@ impl Routine for RandomWalkRoutine {
             IntentionCompleted::Success
             | IntentionCompleted::Undefined => {
                 if sanity.no_intentions_left() {
-                    let cell = map.cellmap.pick_random_passable_ps();
+                    let floor = map.building.floor(sanity.get_current_ps().floor);
+                    let cell = floor.pick_random_passable_ps(&mut sanity.rng);
                     // println!("New intention: move randomly at {:?}", cell);
                     sanity.intend_go_to(cell)
                 }
@@ -55,12 +56,11 @@ impl Routine for RandomStepRoutine {
             | IntentionCompleted::Failure
             | IntentionCompleted::Undefined => {
                 if sanity.no_intentions_left() {
-                    let succ: PsSigned = comfy::ChooseRandom::choose(&SUCCESSORS.to_vec()).unwrap().to_owned().into();
-                    let cell = sanity.get_current_ps() + succ;
-                    if map.cellmap.xy_within_bounds(&cell) && map.cellmap.get_pos(&cell).is_passable(true) {
-                        sanity.intend_go_to(cell.into());
-                    } else {
-                        sanity.mind.intend_cycles_count(10, PRIORITY_BASE);
+                    // steps the building allows, diagonal ones included when it moves that way
+                    let steps = map.building.steps_around(&sanity.get_current_ps(), sanity.walker, true);
+                    match sanity.rng.choose(&steps).copied() {
+                        Some(cell) => sanity.intend_go_to(cell),
+                        None => sanity.mind.intend_cycles_count(10, PRIORITY_BASE),
                     }
                     
                 }
@@ -1,50 +1,70 @@
 use std::collections::LinkedList;
 
-use comfy::ChooseRandom;
-use pathfinding::directed::astar::astar;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    core::position::{Ps, PsProvider, PsSigned},
-    worldmap::Cellmap,
+    building::Building,
+    core::{
+        position::{Ps, PsProvider},
+        rng::WorldRng,
+    },
+    hierarchy::{self, ClusterSearch},
+    search::{Budget, Progress, Search},
+    worldmap::Walker,
 };
 
 pub trait PathfindRouter: PsProvider {
-    fn move_to_ps_or_around_1(&mut self, cellmap: &Cellmap, target: Ps) -> bool {
-        let first_try = self.move_to_ps(cellmap, target);
+    fn move_to_ps_or_around_1(&mut self, building: &Building, target: Ps) -> bool {
+        let first_try = self.move_to_ps(building, target);
         match first_try {
-            false => self.move_around_ps(cellmap, target),
+            false => self.move_around_ps(building, target),
             true => true,
         }
     }
 
-    fn move_to_ps(&mut self, cellmap: &Cellmap, target: Ps) -> bool {
-        let path = try_find_route_from_to(cellmap, true, self.get_current_ps(), target);
+    fn move_to_ps(&mut self, building: &Building, target: Ps) -> bool {
+        let walker = self.walker();
+        let path = try_find_route_toward(
+            building,
+            walker,
+            true,
+            self.get_current_ps(),
+            target,
+            self.rng_mut(),
+        );
         match path {
             Some(p) => {
-                self.follow_steps(target, p);
+                self.follow_steps(building, target, p);
                 true
             }
             None => false,
         }
     }
 
-    fn follow_steps(&mut self, target: Ps, steps: LinkedList<Ps>);
+    fn follow_steps(&mut self, building: &Building, target: Ps, steps: LinkedList<Ps>);
 
-    fn move_around_ps(&mut self, cellmap: &Cellmap, target: Ps) -> bool {
+    fn rng_mut(&mut self) -> &mut WorldRng;
+
+    fn walker(&self) -> Walker;
+
+    fn move_around_ps(&mut self, building: &Building, target: Ps) -> bool {
         // println!("Finding path around {:?}", target);
-        let around = target.successors(cellmap, true);
+        let walker = self.walker();
+        let around = target.successors(building, walker, true, self.rng_mut());
         if around.is_empty() {
             false
         } else {
-            let mut routes: Vec<LinkedList<Ps>> = around
+            let start = self.get_current_ps();
+            let mut routes: Vec<(Ps, LinkedList<Ps>)> = around
                 .into_iter()
-                .filter_map(|tgt| try_find_route_from_to(cellmap, true, self.get_current_ps(), tgt))
+                .filter_map(|tgt| {
+                    try_find_route_toward(building, walker, true, start, tgt, self.rng_mut())
+                        .map(|route| (tgt, route))
+                })
                 .collect();
-            if let Some(found_path) = routes.choose_mut() {
-                self.follow_steps(
-                    found_path.back().unwrap_or(&self.get_current_ps()).clone(),
-                    std::mem::take(found_path),
-                );
+            let chosen = self.rng_mut().below(routes.len());
+            if let Some((tgt, found_path)) = routes.get_mut(chosen) {
+                self.follow_steps(building, *tgt, std::mem::take(found_path));
                 return true;
             }
             false
@@ -53,56 +73,217 @@ pub trait PathfindRouter: PsProvider {
 }
 
 pub trait PathfindPoint {
-    fn successors(&self, cellmap: &Cellmap, skip_ocuppied: bool) -> Vec<Ps>;
-    fn successors_weighted(&self, cellmap: &Cellmap, skip_ocuppied: bool) -> Vec<(Ps, i32)>;
+    fn successors(&self, building: &Building, walker: Walker, skip_ocuppied: bool, rng: &mut WorldRng) -> Vec<Ps>;
+    /// Successors with what stepping into each of them costs.
+    fn successors_weighted(&self, building: &Building, walker: Walker, skip_ocuppied: bool, rng: &mut WorldRng) -> Vec<(Ps, u32)>;
 }
 
-pub const SUCCESSORS: [(isize, isize); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
-
 impl PathfindPoint for Ps {
-    fn successors(&self, cellmap: &Cellmap, skip_ocuppied: bool) -> Vec<Ps> {
-        let mut successors: Vec<Ps> = Vec::with_capacity(4);
-        for (x, y) in SUCCESSORS {
-            let possible_place = PsSigned {
-                x: x + self.x as isize,
-                y: y + self.y as isize,
-            };
-            if cellmap.xy_within_bounds(&possible_place) {
-                let possible_cell = cellmap.get_xy(possible_place.x, possible_place.y);
-                if possible_cell.is_passable(skip_ocuppied) {
-                    successors.push(possible_cell.position)
-                }
-            }
-        }
-        successors.shuffle();
+    fn successors(&self, building: &Building, walker: Walker, skip_ocuppied: bool, rng: &mut WorldRng) -> Vec<Ps> {
+        let mut successors = building.steps_around(self, walker, skip_ocuppied);
+        rng.shuffle(&mut successors);
+        // stairs and elevators go after the cells around, so one-floor maps shuffle as before
+        successors.extend(
+            building
+                .connections(self, walker, skip_ocuppied)
+                .into_iter()
+                .map(|(other, _)| other),
+        );
         successors
     }
 
-    fn successors_weighted(&self, cellmap: &Cellmap, skip_ocuppied: bool) -> Vec<(Ps, i32)> {
-        let successors = self.successors(cellmap, skip_ocuppied);
-        successors.into_iter().map(|p| (p, 1)).collect()
+    fn successors_weighted(&self, building: &Building, walker: Walker, skip_ocuppied: bool, rng: &mut WorldRng) -> Vec<(Ps, u32)> {
+        let successors = self.successors(building, walker, skip_ocuppied, rng);
+        let connections = building.connections(self, walker, skip_ocuppied);
+        successors
+            .into_iter()
+            .map(|p| match connections.iter().find(|(other, _)| *other == p) {
+                Some((_, cost)) => (p, *cost),
+                None => (p, building.step_cost(self, &p)),
+            })
+            .collect()
     }
 }
 
+/// Route to `target`, or to somewhere on the way to it when it is far: the rest of a long
+/// route is found when the agent runs out of steps.
+pub fn try_find_route_toward(
+    building: &Building,
+    walker: Walker,
+    skip_occupied: bool,
+    start: Ps,
+    target: Ps,
+    rng: &mut WorldRng,
+) -> Option<LinkedList<Ps>> {
+    RouteSearch::new(building, start, target, skip_occupied)
+        .run(building, walker, rng, &Budget::unlimited())
+        .finished()
+}
+
+/// Whole route to `target` over the cells.
 pub fn try_find_route_from_to(
-    cellmap: &Cellmap,
+    building: &Building,
+    walker: Walker,
     skip_occupied: bool,
     start: Ps,
     target: Ps,
+    rng: &mut WorldRng,
 ) -> Option<LinkedList<Ps>> {
-    // let dist = start.manhattan_distance(&target);
-    // if dist > 10 {
-    //     print!("[{}]", dist);
-    // }
-    let result = astar(
-        &start,
-        |p| p.successors_weighted(cellmap, skip_occupied),
-        |p| p.manhattan_distance(&target),
-        |p| *p == target,
-    );
-    result.map(|vc| {
-        let mut list = LinkedList::from_iter(vc.0.into_iter());
-        list.pop_front(); // first step we never need, it's our current position
-        list
-    })
+    CellSearch::new(start, target, skip_occupied)
+        .run(building, walker, rng, &Budget::unlimited())
+        .finished()
+}
+
+/// Whole route to `target` over the cells, found a budget at a time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CellSearch {
+    target: Ps,
+    skip_occupied: bool,
+    search: Search,
+}
+
+impl CellSearch {
+    pub fn new(start: Ps, target: Ps, skip_occupied: bool) -> Self {
+        Self {
+            target,
+            skip_occupied,
+            search: Search::new(start),
+        }
+    }
+
+    /// Steps to the target without the start, `None` if it can't be reached.
+    pub fn run(
+        &mut self,
+        building: &Building,
+        walker: Walker,
+        rng: &mut WorldRng,
+        budget: &Budget,
+    ) -> Progress<Option<LinkedList<Ps>>> {
+        let (target, skip_occupied) = (self.target, self.skip_occupied);
+        self.search
+            .run(
+                budget,
+                |p| p.successors_weighted(building, walker, skip_occupied, rng),
+                |p| building.estimate(p, &target),
+                |p| *p == target,
+            )
+            // first step we never need, it's our current position
+            .map(|found| found.map(|(route, _)| route.into_iter().skip(1).collect()))
+    }
+}
+
+/// Route to `target` or toward it (see `try_find_route_toward`), found a budget at a time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RouteSearch {
+    Cells(CellSearch),
+    Clusters(ClusterSearch),
+}
+
+impl RouteSearch {
+    pub fn new(building: &Building, start: Ps, target: Ps, skip_occupied: bool) -> Self {
+        let far = start.floor != target.floor
+            || building.steps_between(&start, &target) > hierarchy::MIN_DISTANCE;
+        if far {
+            RouteSearch::Clusters(ClusterSearch::new(start, target, skip_occupied))
+        } else {
+            RouteSearch::Cells(CellSearch::new(start, target, skip_occupied))
+        }
+    }
+
+    pub fn run(
+        &mut self,
+        building: &Building,
+        walker: Walker,
+        rng: &mut WorldRng,
+        budget: &Budget,
+    ) -> Progress<Option<LinkedList<Ps>>> {
+        match self {
+            RouteSearch::Cells(search) => search.run(building, walker, rng, budget),
+            RouteSearch::Clusters(search) => search.run(building, walker, rng, budget),
+        }
+    }
+}
+
+/// Route onto `target`, or next to it when that fails, found a budget at a time. Like
+/// `PathfindRouter::move_to_ps_or_around_1` it picks one of the cells around at random.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AroundSearch {
+    start: Ps,
+    target: Ps,
+    stage: Around,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Around {
+    Onto(RouteSearch),
+    // routes to the cells around the target, one after another
+    Next {
+        cells: Vec<Ps>,
+        searching: Option<RouteSearch>,
+        found: Vec<(Ps, LinkedList<Ps>)>,
+    },
+}
+
+impl AroundSearch {
+    pub fn new(building: &Building, start: Ps, target: Ps) -> Self {
+        Self {
+            start,
+            target,
+            stage: Around::Onto(RouteSearch::new(building, start, target, true)),
+        }
+    }
+
+    /// Cell the route ends on with the steps there, `None` if none of them can be reached.
+    pub fn run(
+        &mut self,
+        building: &Building,
+        walker: Walker,
+        rng: &mut WorldRng,
+        budget: &Budget,
+    ) -> Progress<Option<(Ps, LinkedList<Ps>)>> {
+        loop {
+            match &mut self.stage {
+                Around::Onto(search) => match search.run(building, walker, rng, budget) {
+                    Progress::Paused => return Progress::Paused,
+                    Progress::Done(Some(steps)) => {
+                        return Progress::Done(Some((self.target, steps)))
+                    }
+                    Progress::Done(None) => {
+                        let mut cells = self.target.successors(building, walker, true, rng);
+                        // taken from the back, in the order they came
+                        cells.reverse();
+                        self.stage = Around::Next {
+                            cells,
+                            searching: None,
+                            found: Vec::new(),
+                        };
+                    }
+                },
+                Around::Next {
+                    cells,
+                    searching,
+                    found,
+                } => {
+                    let Some(cell) = cells.last().copied() else {
+                        let chosen = rng.below(found.len());
+                        return Progress::Done(
+                            found
+                                .get_mut(chosen)
+                                .map(|(cell, steps)| (*cell, std::mem::take(steps))),
+                        );
+                    };
+                    let search = searching
+                        .get_or_insert_with(|| RouteSearch::new(building, self.start, cell, true));
+                    match search.run(building, walker, rng, budget) {
+                        Progress::Paused => return Progress::Paused,
+                        Progress::Done(route) => {
+                            found.extend(route.map(|steps| (cell, steps)));
+                            cells.pop();
+                            *searching = None;
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
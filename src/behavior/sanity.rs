@@ -1,9 +1,10 @@
-use comfy::{Entity, Mutex, RandomRange};
+use comfy::{Entity, Mutex};
+use serde::{Deserialize, Serialize};
 use core::fmt::Debug;
 use std::{borrow::BorrowMut, collections::LinkedList};
 
 use crate::{
-    core::position::{Ps, PsProvider}, gameplay::gametime::{Time, TimeSpan}, state::Reality, worldmap::Cellmap
+    building::Building, core::{position::{Ps, PsProvider}, rng::WorldRng}, flowfield::UNREACHABLE, gameplay::gametime::{Time, TimeSpan}, pathqueue::{PathKind, PathRequest, PathResult, PathStatus, QUICK_BUDGET}, persistence::Persistence, reservation::Reservations, search::{Budget, Progress}, spacetime::{plan_window, step_ticks, Timetable, REPLAN_AT, WINDOW}, state::Reality, worldmap::Walker
 };
 
 use super::{
@@ -24,8 +25,8 @@ pub struct SelfAware<T: Routine> {
 }
 
 impl<T: Routine> SelfAware<T> {
-    pub fn new(speed: f32, pos: Ps, routine: Box<T>) -> Self {
-        let sanity = Sanity::new(speed, pos);
+    pub fn new(speed: f32, pos: Ps, walker: Walker, routine: Box<T>, rng: WorldRng) -> Self {
+        let sanity = Sanity::new(speed, pos, walker, rng);
         Self {
             routine,
             sanity: Mutex::new(sanity),
@@ -56,11 +57,13 @@ impl<T: Routine> PsOffsetProvider for SelfAware<T> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Sanity {
     pub mind: Brains,
     pub mv: SelfRoutingData,
     pub carrier: Carrier,
+    pub rng: WorldRng,
+    pub walker: Walker,
     // routine: Arc<Mutex<Box<dyn Routine<T>>>>,
 }
 
@@ -97,11 +100,13 @@ impl Routine for NoRoutine {
 pub struct GoToRandomFreePsRoutine;
 
 impl Sanity {
-    pub fn new(speed: f32, pos: Ps) -> Self {
+    pub fn new(speed: f32, pos: Ps, walker: Walker, rng: WorldRng) -> Self {
         Self {
             mv: SelfRoutingData::new(speed, pos),
             mind: Brains::new(),
             carrier: Carrier::new(),
+            rng,
+            walker,
             // routine: Arc::new(Mutex::new(routine)),
         }
     }
@@ -172,12 +177,18 @@ impl Sanity {
         self.intend(IntentionClass::MoveToPs(target))
     }
 
+    /// Go to a place many others are heading to, down its flow field.
+    pub fn intend_go_to_shared(&mut self, target: Ps) {
+        self.intend(IntentionClass::MoveToSharedPs(target))
+    }
+
     fn follow_direction_until_next_cell(&mut self, dt: f32) -> Option<Ps> {
         self.mv.follow_direction_until_stop(dt)
     }
 
-    pub fn start_move_direction(&mut self, dir: Direction, cellmap: &Cellmap) {
-        if cellmap.xy_within_bounds(&self.mv.predict_next_pos(dir)) {
+    pub fn start_move_direction(&mut self, dir: Direction, building: &Building) {
+        let floor = building.floor(self.get_current_ps().floor);
+        if floor.xy_within_bounds(&self.mv.predict_next_pos(dir)) {
             self.mv.start_move_direction(dir)
         }
     }
@@ -190,14 +201,16 @@ impl Sanity {
     ) -> IntentionCompleted {
         let position = self.get_current_ps();
         let mut items = carriables.lock();
-        for (_, item) in items.iter_mut() {
-            if item.get_ps() == position && item.available() {
-                if item_type.is_none() || item_type.is_some_and(|tp| item.item_type == tp) {
-                    self.carrier.pick_up(entity, item);
-                    // println!("Item {:?} taken by {:?}", item_entity, entity);
-                    return IntentionCompleted::Success;
-                }
-            }
+        // lowest entity first, hashmap order is not stable between runs
+        let found = items
+            .iter_mut()
+            .filter(|(_, item)| item.get_ps() == position && item.available())
+            .filter(|(_, item)| item_type.is_none() || item_type.is_some_and(|tp| item.item_type == tp))
+            .min_by_key(|(item_entity, _)| **item_entity);
+        if let Some((_, item)) = found {
+            self.carrier.pick_up(entity, item);
+            // println!("Item {:?} taken by {:?}", item_entity, entity);
+            return IntentionCompleted::Success;
         }
         // println!("{:?} failed to take an item", entity);
         IntentionCompleted::Failure
@@ -206,20 +219,22 @@ impl Sanity {
     fn try_consume_item(
         &mut self,
         entity: Entity,
-        carriables: &CarriableItems,
+        reality: &Reality,
         item_type: Option<&'static str>,
     ) -> IntentionCompleted {
         let position = self.get_current_ps();
-        let mut items = carriables.lock();
+        let mut items = reality.carriables.lock();
         // println!("Trying to consume item at {:?}", position);
-        for (_, item) in items.iter_mut() {
-            if item.get_ps() == position && item.available() {
-                if item_type.is_none() || item_type.is_some_and(|tp| item.item_type == tp) {
-                    item.consume();
-                    // println!("Item {:?} consumed by {:?}", item_entity, entity);
-                    return IntentionCompleted::Success;
-                }
-            }
+        let found = items
+            .iter_mut()
+            .filter(|(_, item)| item.get_ps() == position && item.available())
+            .filter(|(_, item)| item_type.is_none() || item_type.is_some_and(|tp| item.item_type == tp))
+            .min_by_key(|(item_entity, _)| **item_entity);
+        if let Some((item_entity, item)) = found {
+            item.consume();
+            reality.persistence.lock().remove(*item_entity);
+            // println!("Item {:?} consumed by {:?}", item_entity, entity);
+            return IntentionCompleted::Success;
         }
         println!("{:?} failed to take an item", entity);
         IntentionCompleted::Failure
@@ -249,16 +264,17 @@ impl Sanity {
     fn try_consume_carried_item(
         &mut self,
         entity: Entity,
-        carriables: &CarriableItems,
+        reality: &Reality,
         item_type: Option<&'static str>,
     ) -> IntentionCompleted {
         // let position = self.get_current_ps();
-        let mut items = carriables.lock();
+        let mut items = reality.carriables.lock();
         // println!("Trying to drop item at {:?}", position);
-        for (_, item) in items.iter_mut() {
+        for (item_entity, item) in items.iter_mut() {
             if item_type.is_none() || item_type.is_some_and(|tp| item.item_type == tp) {
                 let done = self.carrier.try_consume(item);
                 if done {
+                    reality.persistence.lock().remove(*item_entity);
                     return IntentionCompleted::Success;
                 }
             }
@@ -269,13 +285,29 @@ impl Sanity {
 
     fn think_intentions_level(&mut self, entity: Entity, reality: &Reality) -> IntentionCompleted {
         // println!("Processing intentions:\n   {:?}\n   {:?}", self.mind.intentions, self.get_current_ps());
+        let moving = self.mind.intentions.get_current().is_some_and(|intention| {
+            matches!(
+                intention.value,
+                IntentionClass::MoveToDestination(_)
+                    | IntentionClass::MoveToPs(_)
+                    | IntentionClass::MoveToSharedPs(_)
+            )
+        });
+        if !moving && self.mv.is_waiting_for_path() {
+            // the path isn't needed anymore
+            reality.paths.cancel(entity);
+            self.mv.stop_waiting_for_path();
+        }
         if let Some(current_intention) = self.mind.intentions.get_current() {
             match current_intention.value {
                 IntentionClass::MoveToDestination(dest) => {
-                    return self.process_destination_intention(dest, &reality.cellmap, false);
+                    return self.process_destination_intention(entity, dest, reality, false);
                 }
                 IntentionClass::MoveToPs(dest) => {
-                    return self.process_destination_intention(dest, &reality.cellmap, true);
+                    return self.process_destination_intention(entity, dest, reality, true);
+                }
+                IntentionClass::MoveToSharedPs(dest) => {
+                    return self.process_shared_intention(entity, dest, reality);
                 }
                 IntentionClass::WaitCycles(_) => {
                     // print!(">");
@@ -303,7 +335,7 @@ impl Sanity {
                 IntentionClass::ConsumeItemOfType(item_type) => {
                     let result = self.try_consume_item(
                         entity,
-                        &reality.carriables,
+                        reality,
                         Some(item_type),
                     );
                     return self.finish_current_intention_with(result);
@@ -319,7 +351,7 @@ impl Sanity {
                 IntentionClass::ConsumeAnyItem() => {
                     let result =self.try_consume_item(
                         entity,
-                        &reality.carriables,
+                        reality,
                         None,
                     );
                     return self.finish_current_intention_with(result);
@@ -335,7 +367,7 @@ impl Sanity {
                 IntentionClass::ConsumeCarriedItemOfType(item_type) => {
                     let result = self.try_consume_carried_item(
                         entity,
-                        &reality.carriables,
+                        reality,
                         Some(item_type),
                     );
                     return self.finish_current_intention_with(result);
@@ -343,7 +375,7 @@ impl Sanity {
                 IntentionClass::ConsumeAnyCarriedItem() => {
                     let result = self.try_consume_carried_item(
                         entity,
-                        &reality.carriables,
+                        reality,
                         None,
                     );
                     return self.finish_current_intention_with(result);
@@ -427,39 +459,58 @@ impl Sanity {
 
     fn process_destination_intention(
         &mut self,
+        entity: Entity,
         dest: Ps,
-        cellmap: &Cellmap,
+        reality: &Reality,
         exact: bool,
     ) -> IntentionCompleted {
+        let building = &reality.building;
         let required_distance = if exact { 0 } else { 1 };
+        let within = |ps: &Ps, distance: u32| building.steps_between(&dest, ps) <= distance;
+        if let Some(asked) = self.mv.waiting_for_path() {
+            if within(&asked, required_distance) {
+                match reality.paths.poll(entity) {
+                    PathStatus::Pending => {
+                        // the path there is walked on until the new one comes
+                        if !self.is_next_step_walkable(building) {
+                            self.mv.stop_moving();
+                        }
+                        return IntentionCompleted::None;
+                    }
+                    PathStatus::Ready(result) => {
+                        self.mv.stop_waiting_for_path();
+                        if !self.take_path(building, result) {
+                            // destination is unreachable
+                            self.finish_current_intention(false);
+                            self.mv.stop_moving();
+                            return IntentionCompleted::Failure;
+                        }
+                    }
+                    PathStatus::Unknown => self.mv.stop_waiting_for_path(),
+                }
+            }
+        }
         let restart_intention = match self.mv.current_move_path.target {
-            Some(tgt) => dest.manhattan_distance(&tgt) > required_distance,
+            Some(tgt) => !within(&tgt, required_distance),
             None => true,
         };
         if restart_intention {
-            // print!("!");
-            // println!("Intention procesing: move to {:?}", dest);
-            let possible = if !exact {
-                self.move_to_ps_or_around_1(cellmap, dest)
-            } else {
-                self.move_to_ps(cellmap, dest)
-            };
-            if !possible {
-                // print!("X");
+            if within(&self.get_current_ps(), required_distance) {
+                self.finish_current_intention(true);
+                return IntentionCompleted::Success;
+            }
+            let kind = if exact { PathKind::Exact } else { PathKind::Around };
+            if !self.ask_for_path(entity, reality, dest, kind) {
                 // destination is unreachable
-                // println!("Cannot move to destination");
                 self.finish_current_intention(false);
                 self.mv.stop_moving();
                 return IntentionCompleted::Failure;
             }
-            // println!(
-            //     "FULL PTHFIND APPLIED from {:?} to {:?}: {:?}",
-            //     self.get_current_ps(),
-            //     dest,
-            //     self.mv.current_move_path
-            // );
-        }
-        let reached = dest.manhattan_distance(&self.get_current_ps()) <= required_distance
+            if self.mv.is_waiting_for_path() {
+                return IntentionCompleted::None;
+            }
+        }
+        let reached = within(&self.get_current_ps(), required_distance)
             || self.mv.is_reached_cell_flag_set();
         if reached {
             self.finish_current_intention(true);
@@ -468,29 +519,24 @@ impl Sanity {
         }
         // println!(
         //     "\n\n{:?} ({:?}) -> {:?}\n",
-        //     self.is_next_step_valid(cellmap),
+        //     self.is_next_step_valid(building),
         //     self.mv.reliable_steps_left,
         //     self.mv.current_move_path
         // );
-        if !self.is_next_step_valid(cellmap) {
-            let detour_successful: bool;
+        if !self.is_next_step_valid(building) {
             if self.mv.is_no_reliabler_steps_left() {
                 // Do NOT do detours if just reliable steps ended
-                // force full path recalculation
-                detour_successful = self.force_recalculate_path_to_target(cellmap);
-                // and immediately stop intention if failed
-                if !detour_successful {
+                // find the full path again, and immediately stop intention if failed
+                if !self.recalculate_path_to_target(entity, reality) {
                     self.mv.stop_moving();
-                    return IntentionCompleted::None;
                 }
-            } else {
-                detour_successful = self.try_multiple_detours(cellmap);
+                return IntentionCompleted::None;
             }
             // no detours or possible path
-            if !detour_successful {
+            if !self.try_multiple_detours(building) {
                 self.mv.stop_moving();
                 self.mind
-                    .intend_cycles_count(i32::gen_range(10, 60) as isize, 100)
+                    .intend_cycles_count(self.rng.gen_range(10, 60), 100)
             }
 
             return IntentionCompleted::None;
@@ -498,34 +544,148 @@ impl Sanity {
         return IntentionCompleted::None;
     }
 
-    fn try_multiple_detours(&mut self, cellmap: &Cellmap) -> bool {
-        for detour_len in DETOURS {
-            if self.try_small_detour(cellmap, detour_len) {
-                return true;
+    // shared destinations are walked down their flow field, the next step is read off the field
+    // every time the agent stands in a cell
+    fn process_shared_intention(
+        &mut self,
+        entity: Entity,
+        dest: Ps,
+        reality: &Reality,
+    ) -> IntentionCompleted {
+        let building = &reality.building;
+        let here = self.get_current_ps();
+        if here == dest {
+            self.mv.stop_moving();
+            self.finish_current_intention(true);
+            return IntentionCompleted::Success;
+        }
+        if self.mv.waiting_for_path() == Some(dest) {
+            match reality.paths.poll(entity) {
+                PathStatus::Pending => return IntentionCompleted::None,
+                PathStatus::Ready(result) if result.found.is_none() => {
+                    // destination is unreachable
+                    self.mv.stop_waiting_for_path();
+                    self.finish_current_intention(false);
+                    self.mv.stop_moving();
+                    return IntentionCompleted::Failure;
+                }
+                PathStatus::Ready(_) | PathStatus::Unknown => self.mv.stop_waiting_for_path(),
             }
         }
-        false
+        let Some(field) = building.flow_fields().cached(building, self.walker, dest) else {
+            // nobody went there since the building changed, the queue finds the field
+            let request =
+                PathRequest::new(entity, self.walker, here, dest, PathKind::Shared, self.rng.fork());
+            reality.paths.submit(request);
+            self.mv.wait_for_path(dest);
+            return IntentionCompleted::None;
+        };
+        let cost = field.cost(&here);
+        if cost == UNREACHABLE {
+            self.finish_current_intention(false);
+            self.mv.stop_moving();
+            return IntentionCompleted::Failure;
+        }
+        // the step picked last time stays while it still leads downhill and is free
+        let path = &self.mv.current_move_path;
+        let planned = !path.schedule.is_empty();
+        let keep = path.over_field
+            && path.target == Some(dest)
+            && self.mv.peek_next_loc().is_some_and(|next| {
+                field.cost(next) < cost
+                    && building.get_pos(next).is_passable_for(self.walker, !planned)
+            });
+        if keep {
+            return IntentionCompleted::None;
+        }
+        match field.next_step(building, self.walker, true, here, &mut self.rng) {
+            Some(next) => self.mv.step_down_field(dest, next),
+            None => {
+                // everybody closer is in the way, the routine picks another target
+                self.finish_current_intention(false);
+                self.mv.stop_moving();
+                return IntentionCompleted::Failure;
+            }
+        }
+        IntentionCompleted::None
     }
 
-    fn force_recalculate_path_to_target(&mut self, cellmap: &Cellmap) -> bool {
-        if self.mv.current_move_path.target.is_none() {
-            return false;
-        }
-        match try_find_route_from_to(
-            cellmap,
-            true,
+    // a path found within a few nodes is taken right away, otherwise the queue searches on and
+    // hands it out on a later tick; false when the destination can't be reached
+    fn ask_for_path(
+        &mut self,
+        entity: Entity,
+        reality: &Reality,
+        dest: Ps,
+        kind: PathKind,
+    ) -> bool {
+        let mut request = PathRequest::new(
+            entity,
+            self.walker,
             self.get_current_ps(),
-            self.mv.current_move_path.target.unwrap(),
-        ) {
-            Some(path) => {
-                self.follow_steps(self.mv.current_move_path.target.unwrap(), path);
+            dest,
+            kind,
+            self.rng.fork(),
+        );
+        if self.mv.is_waiting_for_path() {
+            reality.paths.cancel(entity);
+        }
+        match request.search(&reality.building, &Budget::new(QUICK_BUDGET)) {
+            Progress::Done(result) => {
+                self.mv.stop_waiting_for_path();
+                self.take_path(&reality.building, result)
+            }
+            Progress::Paused => {
+                reality.paths.submit(request);
+                self.mv.wait_for_path(dest);
                 true
             }
-            None => false,
         }
     }
 
-    fn try_small_detour(&mut self, cellmap: &Cellmap, detour_dist: usize) -> bool {
+    // follows a found path, false if there is none
+    fn take_path(&mut self, building: &Building, result: PathResult) -> bool {
+        let here = self.get_current_ps();
+        match result.found {
+            Some((target, steps)) if result.start != here => {
+                // the agent walked on meanwhile, it joins the path at the furthest cell it can
+                // step onto
+                let around = building.steps_around(&here, self.walker, false);
+                let path: Vec<Ps> = std::iter::once(result.start).chain(steps).collect();
+                let joined = match path.iter().rposition(|ps| *ps == here || around.contains(ps)) {
+                    Some(at) if path[at] == here => &path[at + 1..],
+                    Some(at) => &path[at..],
+                    // a path from where the agent isn't anymore is no use, it asks again
+                    None => return true,
+                };
+                self.follow_steps(building, target, joined.iter().copied().collect());
+                true
+            }
+            Some((target, steps)) => {
+                self.follow_steps(building, target, steps);
+                true
+            }
+            None => result.start != here,
+        }
+    }
+
+    fn try_multiple_detours(&mut self, building: &Building) -> bool {
+        for detour_len in DETOURS {
+            if self.try_small_detour(building, detour_len) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn recalculate_path_to_target(&mut self, entity: Entity, reality: &Reality) -> bool {
+        let Some(target) = self.mv.current_move_path.target else {
+            return false;
+        };
+        self.ask_for_path(entity, reality, target, PathKind::Exact)
+    }
+
+    fn try_small_detour(&mut self, building: &Building, detour_dist: usize) -> bool {
         if let Some(following_step) = self.mv.peek_close_step_or_final_step(detour_dist) {
             let (position_target, position_index) = following_step;
             if position_target == self.get_current_ps() {
@@ -533,10 +693,12 @@ impl Sanity {
                 return false;
             }
             return match try_find_route_from_to(
-                cellmap,
+                building,
+                self.walker,
                 true,
                 self.get_current_ps(),
                 position_target,
+                &mut self.rng,
             ) {
                 Some(path) => {
                     if path.len() == 0 {
@@ -561,16 +723,17 @@ impl Sanity {
                             target, position_target
                         );
                     }
-                    if let Some(invalid) = validate_path(&path) {
+                    if let Some(invalid) = validate_path(&path, building.movement()) {
                         println!("\n\nnvalid path part detected: {:?}", invalid);
                         println!("\nFull path: {:?}", &path);
                     }
                     // println!("--------> Calculated subpath: {:?}", path);
                     // println!("Full path was  : {:?}", self.mv.current_move_path.calculated_steps);
-                    self.mv.change_near_movement_path(&path, position_index);
-                    if let Some(_invalid) =
-                        validate_path(&self.mv.current_move_path.calculated_steps)
-                    {
+                    self.mv.change_near_movement_path(&path, position_index, &mut self.rng);
+                    if let Some(_invalid) = validate_path(
+                        &self.mv.current_move_path.calculated_steps,
+                        building.movement(),
+                    ) {
                         // println!("\n\nnvalid path detected: {:?}", invalid);
                         println!(
                             "\nFull path: {:?}",
@@ -592,30 +755,113 @@ impl Sanity {
         }
     }
 
-    fn is_next_step_valid(&self, cellmap: &Cellmap) -> bool {
+    // there is a next step and nobody stands in the way
+    fn is_next_step_walkable(&self, building: &Building) -> bool {
+        let planned = !self.mv.current_move_path.schedule.is_empty();
+        self.mv
+            .peek_next_loc()
+            .is_some_and(|loc| building.get_pos(loc).is_passable_for(self.walker, !planned))
+    }
+
+    fn is_next_step_valid(&self, building: &Building) -> bool {
         if self.mv.is_no_reliabler_steps_left() {
             return false;
         }
+        // whoever stands on a planned step was planned to be gone by then
+        let planned = !self.mv.current_move_path.schedule.is_empty();
         let next_possible_step = self
             .mv
             .peek_next_loc()
-            .map(|loc| cellmap.get_pos(loc).is_passable(true))
+            .map(|loc| building.get_pos(loc).is_passable_for(self.walker, !planned))
             .unwrap_or(true);
         return next_possible_step;
     }
 
-    fn think_movement_level(&mut self, cellmap: &mut Cellmap) -> bool {
+    fn think_movement_level(
+        &mut self,
+        entity: Entity,
+        building: &mut Building,
+        reservations: &mut Reservations,
+        timetable: &mut Timetable,
+        persistence: &mut Persistence,
+    ) -> bool {
+        self.plan_ahead(entity, building, timetable);
         let mut movement_dest_reached = false;
-        // assume this is valid step now
         let prev_position = self.get_current_ps();
-        let next = self.mv.step_next_direction();
-        if let Some(_found) = next {
-            movement_dest_reached = self.mv.stop_if_destination_cell_reached();
+        let now = timetable.now();
+        // the step was checked while thinking, but others may have taken the cell since,
+        // and those who planned it go first
+        let granted = match self.mv.peek_next_loc() {
+            Some(next) => {
+                self.mv.is_step_due(now)
+                    && timetable.is_free(entity, *next, now, now)
+                    && reservations
+                        .try_reserve(entity, prev_position, *next, building)
+                        .is_ok()
+            }
+            None => true,
+        };
+        if !granted && self.mv.is_late(now, step_ticks(self.mv.movement.speed)) {
+            // the plan didn't work out, the steps are walked the usual way
+            self.mv.current_move_path.schedule.clear();
+            self.mv.current_move_path.unplanned = true;
+            timetable.release(entity);
+        }
+        if granted {
+            let next = self.mv.step_next_direction();
+            if next.is_some() || self.get_current_ps() != prev_position {
+                movement_dest_reached = self.mv.stop_if_destination_cell_reached();
+            }
+        }
+        let position = self.get_current_ps();
+        building.move_occupant(entity, &prev_position, &position);
+        if position != prev_position {
+            persistence.move_to(entity, position);
+            for item in self.carrier.items() {
+                persistence.move_to(*item, position);
+            }
         }
-        cellmap.move_occupy_ps(&prev_position, &self.get_current_ps());
         movement_dest_reached
     }
 
+    // plans the next window of steps in cells and ticks once the planned ones run low
+    fn plan_ahead(&mut self, entity: Entity, building: &Building, timetable: &mut Timetable) {
+        let path = &self.mv.current_move_path;
+        if path.schedule.is_empty() {
+            // whatever was held belongs to steps that are walked or gone
+            timetable.release(entity);
+        }
+        if path.unplanned
+            || path.schedule.len() > REPLAN_AT
+            || path.calculated_steps.len() <= path.schedule.len()
+        {
+            return;
+        }
+        // the window ends before stairs, changing floors takes no ticks to plan
+        let start = self.get_current_ps();
+        let mut goal = start;
+        let mut replaced = 0;
+        for ps in path.calculated_steps.iter().take(WINDOW) {
+            if !ps.is_next_to(&goal) {
+                break;
+            }
+            goal = *ps;
+            replaced += 1;
+        }
+        if replaced == 0 {
+            return;
+        }
+        let step = step_ticks(self.mv.movement.speed);
+        match plan_window(building, timetable, entity, self.walker, start, goal, step) {
+            Some(planned) if !planned.is_empty() => {
+                timetable.release(entity);
+                timetable.reserve_plan(entity, start, &planned, step);
+                self.mv.follow_schedule(planned, replaced);
+            }
+            _ => self.mv.current_move_path.unplanned = true,
+        }
+    }
+
     pub fn move_direction_if_can(&mut self, dt: f32) -> bool {
         match self.mv.movement.loc.direction {
             Some(_direction) => {
@@ -649,13 +895,20 @@ impl Sanity {
         }
     }
 
-    pub fn think_movement_level_if_not_moving(&mut self, cellmap: &mut Cellmap) -> bool {
+    pub fn think_movement_level_if_not_moving(
+        &mut self,
+        entity: Entity,
+        building: &mut Building,
+        reservations: &mut Reservations,
+        timetable: &mut Timetable,
+        persistence: &mut Persistence,
+    ) -> bool {
         match self.mv.movement.loc.direction {
             Some(_) => false,
             None =>
             // Not moving, so we have a frame to think
             {
-                self.think_movement_level(cellmap)
+                self.think_movement_level(entity, building, reservations, timetable, persistence)
             }
         }
     }
@@ -668,11 +921,19 @@ impl PsProvider for Sanity {
 }
 
 impl PathfindRouter for Sanity {
-    fn follow_steps(&mut self, target: Ps, steps: LinkedList<Ps>) {
-        if let Some(invalid) = validate_path(&steps) {
+    fn follow_steps(&mut self, building: &Building, target: Ps, steps: LinkedList<Ps>) {
+        if let Some(invalid) = validate_path(&steps, building.movement()) {
             println!("\n\nInvalid FULL path part detected: {:?}", invalid);
             println!("\nFull path: {:?}", &steps);
         }
-        self.mv.set_movement_path(steps, target);
+        self.mv.set_movement_path(steps, target, &mut self.rng);
+    }
+
+    fn rng_mut(&mut self) -> &mut WorldRng {
+        &mut self.rng
+    }
+
+    fn walker(&self) -> Walker {
+        self.walker
     }
 }
@@ -0,0 +1,168 @@
+use std::{path::PathBuf, time::Instant};
+
+use crowdx::{
+    core::rng::WorldRng,
+    generator::OfficeLayout,
+    replay::Replay,
+    savegame,
+    scenario::{Scenario, GENERATE_ARG, MAP_ARG, SCENARIO_ARG},
+    simulation::{Simulation, TICK_DT},
+};
+
+const DEFAULT_TICKS: u64 = 1000;
+const DEFAULT_DT: f32 = TICK_DT;
+
+fn usage() -> ! {
+    eprintln!("Usage: crowdx-headless [--ticks N] [--dt SECONDS] [--seed N] [--scenario FILE] [--map TMX | --generate SEED] [--export-map TMX] [--load SAVE | --replay REPLAY] [--save SAVE]");
+    std::process::exit(2);
+}
+
+fn main() {
+    let mut ticks = DEFAULT_TICKS;
+    let mut dt = DEFAULT_DT;
+    let mut seed = None;
+    let mut load: Option<PathBuf> = None;
+    let mut save: Option<PathBuf> = None;
+    let mut replay: Option<PathBuf> = None;
+    let mut scenario = Scenario::default();
+    let mut map: Option<PathBuf> = None;
+    let mut generate: Option<u64> = None;
+    let mut export_map: Option<PathBuf> = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ticks" => {
+                ticks = args
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--dt" => {
+                dt = args
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--seed" => {
+                seed = Some(
+                    args.next()
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--load" => load = Some(args.next().map(PathBuf::from).unwrap_or_else(|| usage())),
+            "--replay" => replay = Some(args.next().map(PathBuf::from).unwrap_or_else(|| usage())),
+            SCENARIO_ARG => {
+                let path = args.next().map(PathBuf::from).unwrap_or_else(|| usage());
+                scenario = Scenario::load(&path).unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                });
+            }
+            MAP_ARG => map = Some(args.next().map(PathBuf::from).unwrap_or_else(|| usage())),
+            GENERATE_ARG => {
+                generate = Some(
+                    args.next()
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--export-map" => {
+                export_map = Some(args.next().map(PathBuf::from).unwrap_or_else(|| usage()))
+            }
+            "--save" => save = Some(args.next().map(PathBuf::from).unwrap_or_else(|| usage())),
+            "-h" | "--help" => usage(),
+            other => {
+                eprintln!("Unknown argument: {}", other);
+                usage()
+            }
+        }
+    }
+
+    if let Some(map) = map {
+        scenario.map = map;
+        scenario.generate = None;
+    }
+    if let Some(seed) = generate {
+        scenario.generate_with_seed(seed);
+    }
+    if let Some(path) = export_map {
+        let Some(params) = &scenario.generate else {
+            eprintln!("Nothing to export, the map is not generated");
+            std::process::exit(2);
+        };
+        let exported = OfficeLayout::generate(params)
+            .map_err(|e| e.to_string())
+            .and_then(|layout| layout.export(&path).map_err(|e| e.to_string()));
+        match exported {
+            Ok(()) => println!("Exported the generated map to {}", path.display()),
+            Err(e) => {
+                eprintln!("Cannot export {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let (_, building) = scenario.load_building().unwrap_or_else(|e| {
+        eprintln!("Cannot load map {}: {}", scenario.map_name(), e);
+        std::process::exit(1);
+    });
+
+    if let Some(path) = replay {
+        let started = Instant::now();
+        let sim = Replay::read(&path)
+            .and_then(|replay| replay.play(&scenario.name, building))
+            .unwrap_or_else(|e| {
+                eprintln!("Cannot replay {}: {}", path.display(), e);
+                std::process::exit(1);
+            });
+        println!(
+            "Replayed {} to tick {} in {:.2?}, world time: {}, fingerprint: {:016x}",
+            path.display(),
+            sim.tick,
+            started.elapsed(),
+            sim.reality.time,
+            sim.fingerprint()
+        );
+        return;
+    }
+
+    let mut sim = match &load {
+        Some(path) => savegame::load(path, building).unwrap_or_else(|e| {
+            eprintln!("Cannot load {}: {}", path.display(), e);
+            std::process::exit(1);
+        }),
+        None => {
+            let seed = seed
+                .or(scenario.seed)
+                .unwrap_or_else(WorldRng::seed_from_env);
+            Simulation::from_scenario(&scenario, building, seed)
+        }
+    };
+    // an explicit seed or a save means the run should be reproducible
+    sim.deterministic = seed.is_some() || scenario.seed.is_some() || load.is_some();
+
+    let started = Instant::now();
+    sim.run(ticks, dt);
+    let elapsed = started.elapsed();
+
+    println!(
+        "Simulated {} ticks (dt = {}, seed = {}) in {:.2?}, world time: {}, fingerprint: {:016x}",
+        sim.tick,
+        dt,
+        sim.seed(),
+        elapsed,
+        sim.reality.time,
+        sim.fingerprint()
+    );
+
+    if let Some(path) = save {
+        match savegame::save(&sim, &path) {
+            Ok(()) => println!("Saved to {}", path.display()),
+            Err(e) => {
+                eprintln!("Cannot save {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,385 @@
+// building is the whole walkable world: floor cellmaps stacked on each other, stairs and
+// elevators connect a cell to the same cell on the floor above and below
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use comfy::{world, Entity};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::{position::Ps, rng::WorldRng},
+    flowfield::FlowFields,
+    worldmap::{diagonal_cost, Cell, Cellmap, Walker, BASE_COST},
+};
+
+pub const STAIRS_CLASS: &str = "stairs";
+pub const ELEVATOR_CLASS: &str = "elevator";
+
+/// Cost of going one floor up or down the stairs.
+pub const STAIRS_COST: u32 = 3 * BASE_COST;
+/// Cost of riding the elevator one floor, waiting for it is the "cost" of the elevator tile.
+pub const ELEVATOR_COST: u32 = BASE_COST;
+
+// every building made gets the next one, nothing found over one is taken for another
+static GENERATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Tile that leads to other floors, it connects to the same kind of tile right above or below.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Connector {
+    Stairs,
+    Elevator,
+}
+
+impl Connector {
+    pub fn from_class(class: &str) -> Option<Self> {
+        match class {
+            STAIRS_CLASS => Some(Connector::Stairs),
+            ELEVATOR_CLASS => Some(Connector::Elevator),
+            _ => None,
+        }
+    }
+
+    /// What going one floor costs.
+    pub fn cost(&self) -> u32 {
+        match self {
+            Connector::Stairs => STAIRS_COST,
+            Connector::Elevator => ELEVATOR_COST,
+        }
+    }
+}
+
+/// Cells around a cell, the diagonal ones after the ones sharing a side.
+pub const STEPS: [(i32, i32); 8] = [
+    (1, 0),
+    (0, 1),
+    (-1, 0),
+    (0, -1),
+    (1, 1),
+    (-1, 1),
+    (-1, -1),
+    (1, -1),
+];
+
+/// Whether a diagonal step may pass the corner of a wall. The cells beside the step are the
+/// two it cuts between.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CornerRule {
+    /// Always, even squeezing between two walls touching at the corners.
+    Cut,
+    /// Past one wall, never between two.
+    #[default]
+    NoSqueeze,
+    /// Only when neither cell beside the step is a wall.
+    NoCutting,
+}
+
+/// How agents step from cell to cell, straight only unless diagonals are on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Movement {
+    pub diagonal: bool,
+    pub corners: CornerRule,
+}
+
+impl Movement {
+    /// Cells on the floor of `pos` that `walker` can step to from it, in the order of `STEPS`.
+    /// Walls decide the corners, agents standing beside a step don't.
+    pub fn steps(
+        &self,
+        cellmap: &Cellmap,
+        walker: Walker,
+        skip_occupied: bool,
+        pos: &Ps,
+    ) -> Vec<Ps> {
+        let (x, y) = (pos.x as i32, pos.y as i32);
+        let count = if self.diagonal { 8 } else { 4 };
+        let open = |x: i32, y: i32| {
+            cellmap.within_bounds(x, y) && cellmap.get_xy(x, y).is_passable_for(walker, false)
+        };
+        STEPS[..count]
+            .iter()
+            .filter(|(dx, dy)| {
+                if *dx == 0 || *dy == 0 {
+                    return true;
+                }
+                let beside = [open(x + dx, y), open(x, y + dy)];
+                match self.corners {
+                    CornerRule::Cut => true,
+                    CornerRule::NoSqueeze => beside.contains(&true),
+                    CornerRule::NoCutting => !beside.contains(&false),
+                }
+            })
+            .map(|(dx, dy)| (x + dx, y + dy))
+            .filter(|(x, y)| cellmap.within_bounds(*x, *y))
+            .map(|(x, y)| cellmap.get_xy(x, y))
+            .filter(|cell| cell.is_passable_for(walker, skip_occupied))
+            .map(|cell| cell.position)
+            .collect()
+    }
+}
+
+/// Floor an entity is on, entities without it are on the ground floor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Floor(pub usize);
+
+/// Floor of an entity that doesn't know its own `Ps` yet, like a map object being initialized.
+pub fn floor_of(entity: Entity) -> usize {
+    world()
+        .get::<&Floor>(entity)
+        .map(|floor| floor.0)
+        .unwrap_or(0)
+}
+
+#[derive(Debug)]
+pub struct Building {
+    floors: Vec<Cellmap>,
+    // fields toward shared destinations, found again once the cells change
+    flow_fields: FlowFields,
+    movement: Movement,
+    generation: u64,
+}
+
+impl Building {
+    /// Floors from the ground up, each one learns its number.
+    pub fn new(floors: Vec<Cellmap>) -> Self {
+        let floors = floors
+            .into_iter()
+            .enumerate()
+            .map(|(floor, cellmap)| cellmap.with_floor(floor))
+            .collect();
+        Self {
+            floors,
+            flow_fields: FlowFields::new(),
+            movement: Movement::default(),
+            generation: GENERATIONS.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Same building walked by `movement`. Set before any route is found, graphs and fields
+    /// found over the building remember how it was walked then.
+    pub fn with_movement(mut self, movement: Movement) -> Self {
+        self.movement = movement;
+        self
+    }
+
+    pub fn movement(&self) -> Movement {
+        self.movement
+    }
+
+    pub fn floors(&self) -> &[Cellmap] {
+        &self.floors
+    }
+
+    pub fn floor_count(&self) -> usize {
+        self.floors.len()
+    }
+
+    pub fn floor(&self, floor: usize) -> &Cellmap {
+        &self.floors[floor]
+    }
+
+    pub fn floor_mut(&mut self, floor: usize) -> &mut Cellmap {
+        &mut self.floors[floor]
+    }
+
+    pub fn ground(&self) -> &Cellmap {
+        &self.floors[0]
+    }
+
+    /// Grows every time walking over a cell of any floor changes.
+    pub fn revision(&self) -> u64 {
+        self.floors.iter().map(|floor| floor.revision()).sum()
+    }
+
+    /// Never the same for two buildings, unlike the revision that starts over with each one.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn flow_fields(&self) -> &FlowFields {
+        &self.flow_fields
+    }
+
+    /// Size of every floor, from the ground up.
+    pub fn sizes(&self) -> Vec<(usize, usize)> {
+        self.floors.iter().map(|floor| floor.wh_usize()).collect()
+    }
+
+    /// Hash of everything walking the building depends on: the floors, what every cell lets
+    /// through and costs, stairs and elevators, and how steps are taken.
+    pub fn walk_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.movement.hash(&mut hasher);
+        for floor in self.floors.iter() {
+            floor.wh_usize().hash(&mut hasher);
+            for cell in floor.map.iter() {
+                cell.hash_walking(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    pub fn pos_within_bounds(&self, pos: Ps) -> bool {
+        self.floors
+            .get(pos.floor)
+            .is_some_and(|floor| floor.pos_within_bounds(pos))
+    }
+
+    pub fn get_pos(&self, pos: &Ps) -> &Cell {
+        self.floors[pos.floor].get_pos(pos)
+    }
+
+    pub fn get_pos_mut(&mut self, pos: &Ps) -> &mut Cell {
+        self.floors[pos.floor].get_pos_mut(pos)
+    }
+
+    pub fn replace_cell(&mut self, pos: &Ps, cell: Cell) {
+        self.floors[pos.floor].replace_cell(pos, cell)
+    }
+
+    pub fn occupy_ps(&mut self, pos: &Ps) {
+        self.floors[pos.floor].occupy_ps(pos)
+    }
+
+    pub fn deoccupy_ps(&mut self, pos: &Ps) {
+        self.floors[pos.floor].deoccupy_ps(pos)
+    }
+
+    /// Moves the agent from one cell to another, on the same floor or not.
+    pub fn move_occupant(&mut self, entity: Entity, pos_from: &Ps, pos_to: &Ps) {
+        let from = &mut self.get_pos_mut(pos_from).status;
+        if from.occupant == Some(entity) {
+            from.occupant = None;
+        }
+        self.get_pos_mut(pos_to).status.occupant = Some(entity);
+    }
+
+    /// Cheapest step there is, keeps the A* heuristic from overestimating.
+    pub fn min_cost(&self) -> u32 {
+        self.floors
+            .iter()
+            .map(|floor| floor.min_cost())
+            .chain([STAIRS_COST, ELEVATOR_COST])
+            .min()
+            .unwrap_or(BASE_COST)
+    }
+
+    /// Cells on the floor of `pos` that `walker` can step to from it.
+    pub fn steps_around(&self, pos: &Ps, walker: Walker, skip_occupied: bool) -> Vec<Ps> {
+        self.movement
+            .steps(self.floor(pos.floor), walker, skip_occupied, pos)
+    }
+
+    /// What stepping from `from` into the cell next to it on the same floor costs.
+    pub fn step_cost(&self, from: &Ps, to: &Ps) -> u32 {
+        self.floors[to.floor].step_cost(from, to)
+    }
+
+    /// Fewest steps between two cells with nothing in the way, a floor up or down is one.
+    pub fn steps_between(&self, from: &Ps, to: &Ps) -> u32 {
+        let diagonal = if self.movement.diagonal { 1 } else { 2 };
+        from.octile_distance(to, 1, diagonal)
+    }
+
+    /// Lower bound of what getting from `from` to `to` costs, for A*: every step costs at least
+    /// the cheapest cell and a floor up or down counts as a step. Without diagonals that is
+    /// the manhattan distance, with them the octile one.
+    pub fn estimate(&self, from: &Ps, to: &Ps) -> u32 {
+        let min = self.min_cost();
+        let diagonal = if self.movement.diagonal {
+            diagonal_cost(min)
+        } else {
+            2 * min
+        };
+        from.octile_distance(to, min, diagonal)
+    }
+
+    /// Cells on other floors reachable from `pos` in one step, with what the step costs.
+    pub fn connections(&self, pos: &Ps, walker: Walker, skip_occupied: bool) -> Vec<(Ps, u32)> {
+        let Some(connector) = self.get_pos(pos).connector else {
+            return Vec::new();
+        };
+        let below = pos.floor.checked_sub(1).map(|floor| pos.with_floor(floor));
+        let above = Some(pos.with_floor(pos.floor + 1));
+        [below, above]
+            .into_iter()
+            .flatten()
+            .filter(|other| self.pos_within_bounds(*other))
+            .filter(|other| {
+                let cell = self.get_pos(other);
+                cell.connector == Some(connector) && cell.is_passable_for(walker, skip_occupied)
+            })
+            .map(|other| (other, connector.cost()))
+            .collect()
+    }
+
+    /// Free cell closest to `from` that `walker` can stand on, on its floor or the top one.
+    pub fn nearest_free_ps(&self, from: Ps, walker: Walker) -> Option<Ps> {
+        let floor = from.floor.min(self.floors.len() - 1);
+        self.floors[floor].nearest_free_ps(from, walker)
+    }
+
+    /// Whether walking over `pos` works differently in `other`, cells only one of them has count too.
+    pub fn changed_at(&self, other: &Building, pos: &Ps) -> bool {
+        match (self.pos_within_bounds(*pos), other.pos_within_bounds(*pos)) {
+            (true, true) => !self.get_pos(pos).walks_like(other.get_pos(pos)),
+            (false, false) => false,
+            _ => true,
+        }
+    }
+
+    /// Random free cell on the ground floor.
+    pub fn pick_random_passable_ps(&self, rng: &mut WorldRng) -> Ps {
+        self.ground().pick_random_passable_ps(rng)
+    }
+
+    /// Random passable cell near `center`, on its floor.
+    pub fn pick_passable_ps_near(&self, center: Ps, radius: usize, rng: &mut WorldRng) -> Ps {
+        self.floors[center.floor].pick_passable_ps_near(center, radius, rng)
+    }
+}
+
+impl From<Cellmap> for Building {
+    fn from(cellmap: Cellmap) -> Self {
+        Building::new(vec![cellmap])
+    }
+}
+
+#[cfg(test)]
+impl Building {
+    /// One floor drawn row by row from y 0 down, `#` is a wall and anything else floor.
+    pub(crate) fn from_rows(rows: &[&str]) -> Self {
+        let (width, height) = (rows[0].len() as i32, rows.len() as i32);
+        let cells = rows
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.chars()
+                    .enumerate()
+                    .map(move |(x, c)| Some(Cell::new((x as i32, y as i32), c != '#', None)))
+            })
+            .collect();
+        Cellmap::new(cells, width, height).into()
+    }
+}
+
+/// Fixtures the unit tests share.
+#[cfg(test)]
+pub(crate) mod testing {
+    use comfy::Entity;
+
+    use crate::core::position::Ps;
+
+    /// Cell of the ground floor.
+    pub fn ps(x: usize, y: usize) -> Ps {
+        Ps { x, y, floor: 0 }
+    }
+
+    /// Stands in for an agent that was never spawned.
+    pub fn agent(id: u64) -> Entity {
+        Entity::from_bits(1 << 32 | id).unwrap()
+    }
+}
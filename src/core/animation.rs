@@ -18,6 +18,7 @@ impl BasicTileAnimation {
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct AdditionalAnimationDescr {
     pub animation_name: String,
     pub atlas_name: String,
@@ -35,3 +36,68 @@ impl AdditionalAnimationDescr {
         }
     }
 }
+
+/// One frame of a Tiled animation: image of the frame tile and how long it stays on screen.
+#[derive(Debug, Clone)]
+pub struct TileFrame {
+    pub image: String,
+    pub duration_ms: u32,
+}
+
+/// How a tile animates on the map.
+#[derive(Debug, Clone)]
+pub enum TileAnimation {
+    /// Old style: `steps` frames side by side in the tile's own image, all of the same length.
+    Atlas(BasicTileAnimation),
+    /// Animation made in the Tiled animation editor, frames can come from any tile of the tileset
+    /// and each has its own duration. `key` names the animation among the others on the map.
+    Frames { key: String, frames: Vec<TileFrame> },
+}
+
+/// Frame durations are rounded to this, it is also the shortest frame there can be.
+pub const FRAME_SLICE_MS: u32 = 10;
+
+impl TileAnimation {
+    /// How many frames are laid out in the tile image, its width is that many tiles.
+    pub fn frames_in_image(&self) -> i32 {
+        match self {
+            TileAnimation::Atlas(anim) => anim.steps,
+            TileAnimation::Frames { .. } => 1,
+        }
+    }
+
+    /// Comfy shows every frame of an animation for the same time, so a frame lasting longer
+    /// is repeated: the animation is cut into equal slices and this is the image of every slice
+    /// along with the slice length in seconds.
+    pub fn slices(&self) -> (Vec<&str>, f32) {
+        match self {
+            TileAnimation::Atlas(_) => (Vec::new(), 0.0),
+            TileAnimation::Frames { frames, .. } => {
+                let rounded = |frame: &TileFrame| {
+                    let slices = (frame.duration_ms + FRAME_SLICE_MS / 2) / FRAME_SLICE_MS;
+                    slices.max(1) * FRAME_SLICE_MS
+                };
+                let slice = frames
+                    .iter()
+                    .map(rounded)
+                    .reduce(gcd)
+                    .unwrap_or(FRAME_SLICE_MS);
+                let images = frames
+                    .iter()
+                    .flat_map(|frame| {
+                        std::iter::repeat_n(frame.image.as_str(), (rounded(frame) / slice) as usize)
+                    })
+                    .collect();
+                (images, slice as f32 / 1000.0)
+            }
+        }
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
@@ -1,7 +1,9 @@
 use comfy::num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize};
 
 use super::position::{Ps, XYprovider};
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AnyCellmap<T: Clone> {
     pub map: Vec<T>,
     width: usize,
@@ -5,6 +5,8 @@ use crate::state::Reality;
 pub mod position;
 pub mod anycellmap;
 pub mod animation;
+pub mod rng;
+pub mod serialization;
 
 pub trait Initializable {
     fn initialize(&mut self, entity: &Entity, transform: &mut Transform, reality: &mut Reality);
@@ -1,11 +1,14 @@
 use core::fmt;
 
 use comfy::{num_traits::ToPrimitive, vec2, IVec2, Vec2};
+use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, PartialEq, Eq, comfy::Hash)]
+#[derive(Copy, Clone, PartialEq, Eq, comfy::Hash, Serialize, Deserialize)]
 pub struct Ps {
     pub x: usize,
     pub y: usize,
+    /// Storey of the building, 0 is the ground floor.
+    pub floor: usize,
 }
 
 impl From<(i32, i32)> for Ps {
@@ -13,6 +16,7 @@ impl From<(i32, i32)> for Ps {
         Self {
             x: value.0.to_usize().unwrap(),
             y: value.1.to_usize().unwrap(),
+            floor: 0,
         }
     }
 }
@@ -20,7 +24,10 @@ impl From<(i32, i32)> for Ps {
 impl fmt::Debug for Ps {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // Customize the output format
-        write!(f, "({}, {})", self.x, self.y)
+        match self.floor {
+            0 => write!(f, "({}, {})", self.x, self.y),
+            floor => write!(f, "({}, {} @{})", self.x, self.y, floor),
+        }
     }
 }
 
@@ -29,6 +36,7 @@ impl From<Vec2> for Ps {
         Self {
             x: value.x.floor().to_usize().unwrap(),
             y: value.y.floor().to_usize().unwrap(),
+            floor: 0,
         }
     }
 }
@@ -38,6 +46,7 @@ impl From<(usize, usize)> for Ps {
         Self {
             x: value.0,
             y: value.1,
+            floor: 0,
         }
     }
 }
@@ -58,10 +67,32 @@ impl Into<PsSigned> for Ps {
 }
 
 impl Ps {
+    /// Every floor between the two counts as one more step.
     pub fn manhattan_distance(&self, other: &Ps) -> i32 {
         let diffx = self.x.abs_diff(other.x);
         let diffy = self.y.abs_diff(other.y);
-        return (diffx + diffy).to_i32().unwrap();
+        let difff = self.floor.abs_diff(other.floor);
+        (diffx + diffy + difff).to_i32().unwrap()
+    }
+
+    /// Distance walked with `straight` for a step along a side and `diagonal` for one across
+    /// a corner, every floor between counting as one straight step.
+    pub fn octile_distance(&self, other: &Ps, straight: u32, diagonal: u32) -> u32 {
+        let diffx = self.x.abs_diff(other.x) as u32;
+        let diffy = self.y.abs_diff(other.y) as u32;
+        let difff = self.floor.abs_diff(other.floor) as u32;
+        let across = diffx.min(diffy);
+        (diffx.max(diffy) - across + difff) * straight + across * diagonal
+    }
+
+    /// The cells touch by a side or a corner, on the same floor.
+    pub fn is_next_to(&self, other: &Ps) -> bool {
+        self.floor == other.floor && self.x.abs_diff(other.x).max(self.y.abs_diff(other.y)) == 1
+    }
+
+    /// The same cell on another floor.
+    pub fn with_floor(self, floor: usize) -> Ps {
+        Ps { floor, ..self }
     }
 
     pub fn distance_to(&self, other: &dyn PsProvider) -> Vec2 {
@@ -194,6 +225,7 @@ impl Into<Ps> for PsSigned {
         Ps {
             x: self.x as usize,
             y: self.y as usize,
+            floor: 0,
         }
     }
 }
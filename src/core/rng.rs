@@ -0,0 +1,142 @@
+// seedable random numbers: every random decision in the simulation should come from a WorldRng,
+// so that the same seed, map and inputs always produce the same world
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+pub const SEED_ENV: &str = "CROWDX_SEED";
+
+const MULTIPLIER: u64 = 6364136223846793005;
+const INCREMENT: u64 = 1442695040888963407;
+
+/// Small PCG32 generator. `Reality` owns the world one, and every agent gets its own
+/// generator forked from it, so agents can think in parallel without sharing state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldRng {
+    seed: u64,
+    state: u64,
+}
+
+pub trait RngRange: Sized {
+    fn sample(rng: &mut WorldRng, low: Self, high: Self) -> Self;
+}
+
+impl WorldRng {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self { seed, state: 0 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn from_entropy() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self::new(nanos)
+    }
+
+    /// Seed from the `CROWDX_SEED` environment variable, or a fresh one if it is not set.
+    pub fn seed_from_env() -> u64 {
+        Self::env_seed().unwrap_or_else(|| Self::from_entropy().seed())
+    }
+
+    /// Seed set in the `CROWDX_SEED` environment variable, if any.
+    pub fn env_seed() -> Option<u64> {
+        std::env::var(SEED_ENV).ok().and_then(|v| v.parse().ok())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(INCREMENT);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        ((self.next_u32() as u64) << 32) | self.next_u32() as u64
+    }
+
+    // uniform value in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        self.next_u32() as f64 / (u32::MAX as f64 + 1.0)
+    }
+
+    // uniform value in [0, bound), 0 if bound is 0
+    pub fn below(&mut self, bound: usize) -> usize {
+        if bound == 0 {
+            return 0;
+        }
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    // half-open range [low, high), just like comfy's gen_range
+    pub fn gen_range<T: RngRange>(&mut self, low: T, high: T) -> T {
+        T::sample(self, low, high)
+    }
+
+    pub fn shuffle<T>(&mut self, data: &mut [T]) {
+        for i in (1..data.len()).rev() {
+            let j = self.below(i + 1);
+            data.swap(i, j);
+        }
+    }
+
+    pub fn choose<'a, T>(&mut self, data: &'a [T]) -> Option<&'a T> {
+        if data.is_empty() {
+            return None;
+        }
+        data.get(self.below(data.len()))
+    }
+
+    pub fn choose_mut<'a, T>(&mut self, data: &'a mut [T]) -> Option<&'a mut T> {
+        if data.is_empty() {
+            return None;
+        }
+        let index = self.below(data.len());
+        data.get_mut(index)
+    }
+
+    /// Independent generator derived from this one, used to give every agent its own stream.
+    pub fn fork(&mut self) -> WorldRng {
+        WorldRng::new(self.next_u64())
+    }
+}
+
+impl RngRange for usize {
+    fn sample(rng: &mut WorldRng, low: Self, high: Self) -> Self {
+        low + rng.below(high.saturating_sub(low))
+    }
+}
+
+impl RngRange for isize {
+    fn sample(rng: &mut WorldRng, low: Self, high: Self) -> Self {
+        low + rng.below(high.saturating_sub(low).max(0) as usize) as isize
+    }
+}
+
+impl RngRange for i32 {
+    fn sample(rng: &mut WorldRng, low: Self, high: Self) -> Self {
+        low + rng.below(high.saturating_sub(low).max(0) as usize) as i32
+    }
+}
+
+impl RngRange for f64 {
+    fn sample(rng: &mut WorldRng, low: Self, high: Self) -> Self {
+        low + (high - low) * rng.next_f64()
+    }
+}
+
+impl RngRange for f32 {
+    fn sample(rng: &mut WorldRng, low: Self, high: Self) -> Self {
+        low + (high - low) * rng.next_f64() as f32
+    }
+}
@@ -0,0 +1,134 @@
+// serde helpers for types we don't own or that can't be derived directly,
+// use them with #[serde(with = "crate::core::serialization::...")]
+
+use comfy::{Arc, Entity, HashMap, HashSet, IVec2, Mutex, Vec2};
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+
+use crate::utils::anyhashmap::{PrimitiveHashMap, PrimitiveValue};
+
+fn entity_from_bits<E: Error>(bits: u64) -> Result<Entity, E> {
+    Entity::from_bits(bits).ok_or_else(|| E::custom(format!("invalid entity id {}", bits)))
+}
+
+/// Entities are stored by their raw bits, so a snapshot keeps the same ids when respawned
+pub mod entity {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(entity: &Entity, s: S) -> Result<S::Ok, S::Error> {
+        entity.to_bits().get().serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Entity, D::Error> {
+        entity_from_bits(u64::deserialize(d)?)
+    }
+}
+
+pub mod option_entity {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(entity: &Option<Entity>, s: S) -> Result<S::Ok, S::Error> {
+        entity.map(|e| e.to_bits().get()).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Entity>, D::Error> {
+        Option::<u64>::deserialize(d)?
+            .map(entity_from_bits)
+            .transpose()
+    }
+}
+
+pub mod entity_set {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(set: &HashSet<Entity>, s: S) -> Result<S::Ok, S::Error> {
+        let mut bits: Vec<u64> = set.iter().map(|e| e.to_bits().get()).collect();
+        bits.sort();
+        bits.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<HashSet<Entity>, D::Error> {
+        Vec::<u64>::deserialize(d)?
+            .into_iter()
+            .map(entity_from_bits)
+            .collect()
+    }
+}
+
+pub mod vec2 {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &Vec2, s: S) -> Result<S::Ok, S::Error> {
+        (value.x, value.y).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec2, D::Error> {
+        let (x, y) = <(f32, f32)>::deserialize(d)?;
+        Ok(Vec2::new(x, y))
+    }
+}
+
+pub mod ivec2 {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &IVec2, s: S) -> Result<S::Ok, S::Error> {
+        (value.x, value.y).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<IVec2, D::Error> {
+        let (x, y) = <(i32, i32)>::deserialize(d)?;
+        Ok(IVec2::new(x, y))
+    }
+}
+
+/// Maps keyed by cells are stored as a list of pairs sorted by cell, json keys are strings only
+pub mod ps_map {
+    use super::*;
+    use crate::core::position::Ps;
+
+    pub fn serialize<S: Serializer, V: Serialize>(
+        map: &HashMap<Ps, V>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut pairs: Vec<(&Ps, &V)> = map.iter().collect();
+        pairs.sort_by_key(|(ps, _)| (ps.floor, ps.y, ps.x));
+        pairs.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, V: Deserialize<'de>>(
+        d: D,
+    ) -> Result<HashMap<Ps, V>, D::Error> {
+        Ok(Vec::<(Ps, V)>::deserialize(d)?.into_iter().collect())
+    }
+}
+
+/// Item types are `&'static str` constants, so they are looked up by name on load
+pub mod item_type {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &&'static str, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<&'static str, D::Error> {
+        let name = String::deserialize(d)?;
+        crate::behavior::item_types::from_name(&name)
+            .ok_or_else(|| D::Error::custom(format!("unknown item type '{}'", name)))
+    }
+}
+
+pub mod primitive_map {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(map: &PrimitiveHashMap, s: S) -> Result<S::Ok, S::Error> {
+        // sorted, so the same memory always looks the same in a save file
+        let locked = map.lock();
+        let sorted: BTreeMap<&String, &PrimitiveValue> = locked.iter().collect();
+        sorted.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<PrimitiveHashMap, D::Error> {
+        let map = HashMap::<String, PrimitiveValue>::deserialize(d)?;
+        Ok(Arc::new(Mutex::new(map)))
+    }
+}
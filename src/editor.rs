@@ -0,0 +1,251 @@
+// editor changes the map under a running simulation one cell at a time, the same way a
+// reload does for the whole map: the cell is made again from its tile and the world settles on it
+
+use std::{fmt, io, path::Path};
+
+use comfy::{commands, world, HashSet, IVec2};
+use serde::{Deserialize, Serialize};
+use tiled::PropertyValue;
+
+use crate::{
+    behavior::item_types,
+    core::{anycellmap::AnyCellmap, position::Ps},
+    gameplay::ent::MapTile,
+    hotreload::{self, ReloadReport},
+    initializers::{bone_components, trashcan_components},
+    replay::Command,
+    simulation::{self, Simulation},
+    tiledreader::{tile_cell, DecorTile, MapLoadError},
+    tiledwriter::building_writer,
+    worldmap::{ItemSpawner, TileReference},
+    Bone, TrashCan,
+};
+
+/// What a click puts on the map.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Brush {
+    Wall,
+    /// Clears the cell's tile, the ground drawn under it stays.
+    Floor,
+    /// Places a conputer, on a conputer it moves the workplace instead.
+    Conputer,
+    Bed,
+    TrashCan,
+    Bone,
+}
+
+impl Brush {
+    pub const ALL: [Brush; 6] = [
+        Brush::Wall,
+        Brush::Floor,
+        Brush::Conputer,
+        Brush::Bed,
+        Brush::TrashCan,
+        Brush::Bone,
+    ];
+
+    // Tiled class of the tile the brush paints
+    fn class(&self) -> Option<&'static str> {
+        match self {
+            Brush::Wall => Some("wall"),
+            Brush::Conputer => Some(item_types::CONPUTER),
+            Brush::Bed => Some(item_types::BED),
+            Brush::Floor | Brush::TrashCan | Brush::Bone => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EditError {
+    /// The map has no tile of this class to paint with.
+    NoTile(&'static str),
+    /// Items only go onto cells that can be walked on.
+    Blocked(Ps),
+    Tile(MapLoadError),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditError::NoTile(class) => write!(f, "the map has no {} tile to paint with", class),
+            EditError::Blocked(ps) => write!(f, "cell {:?} can't be walked on", ps),
+            EditError::Tile(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+impl From<MapLoadError> for EditError {
+    fn from(e: MapLoadError) -> Self {
+        EditError::Tile(e)
+    }
+}
+
+/// Edit mode of the window: the picked brush and where new conputers are used from.
+pub struct MapEditor {
+    pub active: bool,
+    pub brush: Brush,
+    /// Workplace of conputers relative to them, y goes up.
+    pub workplace: IVec2,
+}
+
+impl MapEditor {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            brush: Brush::Wall,
+            workplace: IVec2::new(0, 1),
+        }
+    }
+
+    /// Command to use the brush on the cell at `ps`, edits are player input like any other
+    /// and get recorded with it.
+    pub fn command(&self, ps: Ps) -> Command {
+        Command::Edit {
+            brush: self.brush,
+            ps,
+            workplace: self.workplace,
+        }
+    }
+}
+
+/// Uses `brush` on the cell at `ps`, new conputers are used from `workplace`.
+pub fn edit(
+    sim: &mut Simulation,
+    brush: Brush,
+    ps: Ps,
+    workplace: IVec2,
+) -> Result<ReloadReport, EditError> {
+    match brush {
+        Brush::Floor => set_tile(sim, ps, None),
+        Brush::Conputer => {
+            let cell = sim.reality.building.get_pos(&ps);
+            let tile = match &cell.reference {
+                Some(tile) if tile.klass == item_types::CONPUTER => tile.clone(),
+                _ => palette_tile(sim, brush)?,
+            };
+            set_tile(sim, ps, Some(with_workplace(tile, workplace)))
+        }
+        Brush::Wall | Brush::Bed => {
+            let tile = palette_tile(sim, brush)?;
+            set_tile(sim, ps, Some(tile))
+        }
+        Brush::TrashCan => place_item(sim, ps, item_types::TRASHCAN),
+        Brush::Bone => place_item(sim, ps, item_types::BONE),
+    }
+}
+
+impl Default for MapEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the map's own tiles are the palette, the first tile of the brush's class on any floor
+fn palette_tile(sim: &Simulation, brush: Brush) -> Result<TileReference, EditError> {
+    let class = brush.class().expect("brush paints tiles");
+    sim.reality
+        .building
+        .floors()
+        .iter()
+        .flat_map(|floor| floor.map.iter())
+        .filter_map(|cell| cell.reference.as_ref())
+        .find(|tile| tile.klass == class)
+        .cloned()
+        .ok_or(EditError::NoTile(class))
+}
+
+fn with_workplace(mut tile: TileReference, workplace: IVec2) -> TileReference {
+    tile.props
+        .insert("x".to_string(), PropertyValue::IntValue(workplace.x));
+    tile.props
+        .insert("y".to_string(), PropertyValue::IntValue(workplace.y));
+    tile
+}
+
+/// Puts `tile` on the cell at `ps` while the simulation keeps running, `None` leaves bare floor.
+///
+/// The entity of the old tile goes away together with its interactive object and whoever was
+/// assigned to it, agents that can't stand on the cell anymore are moved off it.
+pub fn set_tile(
+    sim: &mut Simulation,
+    ps: Ps,
+    tile: Option<TileReference>,
+) -> Result<ReloadReport, EditError> {
+    let cell = tile_cell((ps.x as i32, ps.y as i32), tile)?;
+    sim.flush_commands();
+
+    let gone: HashSet<_> = world()
+        .query::<&MapTile>()
+        .iter()
+        .filter(|(_, tile)| tile.0 == ps)
+        .map(|(entity, _)| entity)
+        .collect();
+    for entity in gone.iter() {
+        commands().despawn(*entity);
+    }
+    let removed = {
+        let mut interactive = sim.reality.interactive.lock();
+        let mut removed: Vec<_> = interactive
+            .iter()
+            .filter(|(_, handle)| gone.contains(&handle.item_id))
+            .map(|(handle_ps, handle)| (*handle_ps, *handle))
+            .collect();
+        removed.sort_by_key(|(ps, _)| (ps.floor, ps.x, ps.y));
+        for (handle_ps, _) in removed.iter() {
+            interactive.remove(handle_ps);
+        }
+        removed
+    };
+
+    if let Some(tile) = &cell.reference {
+        sim.registry.spawn_tile_object(ps, tile);
+    }
+    sim.reality.building.replace_cell(&ps, cell);
+    // the old entity is gone and the new one known before anybody looks for them
+    sim.flush_commands();
+    sim.registry.initialize_map_objects(&mut sim.reality);
+    hotreload::release_removed(&removed);
+
+    let mut report = hotreload::settle_agents(&mut sim.reality.building, &HashSet::from([ps]));
+    report.removed = removed.len();
+    simulation::update_communication(&mut sim.reality);
+    simulation::rebuild_persistence(&mut sim.reality);
+    Ok(report)
+}
+
+/// Drops an item on the cell at `ps` and keeps a spawner for it, so a saved map has it too.
+pub fn place_item(
+    sim: &mut Simulation,
+    ps: Ps,
+    item_type: &'static str,
+) -> Result<ReloadReport, EditError> {
+    if !sim.reality.building.get_pos(&ps).passable {
+        return Err(EditError::Blocked(ps));
+    }
+    if item_type == item_types::BONE {
+        let bone = Bone { initialized: false };
+        commands().spawn(bone_components(ps.into(), ps.floor, bone));
+    } else if item_type == item_types::TRASHCAN {
+        let trashcan = TrashCan { initialized: false };
+        commands().spawn(trashcan_components(ps.into(), ps.floor, trashcan));
+    }
+    sim.reality
+        .building
+        .floor_mut(ps.floor)
+        .objects
+        .item_spawners
+        .push(ItemSpawner {
+            item_type,
+            ps,
+            count: 1,
+            radius: 0,
+        });
+    Ok(ReloadReport::default())
+}
+
+/// Writes the edited building with its decorations as one map.
+pub fn save(sim: &Simulation, decor: &[AnyCellmap<DecorTile>], path: &Path) -> io::Result<()> {
+    building_writer(&sim.reality.building, decor)?.save(path)
+}
@@ -0,0 +1,325 @@
+// flowfield serves destinations many agents head to at once, like the closest bone or a
+// player's order: what getting to the target costs is found once for every cell of the
+// building, and each agent walks downhill from where it stands instead of searching
+
+use std::{cmp::Reverse, collections::BinaryHeap, fmt};
+
+use comfy::{Arc, HashMap, Mutex};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    building::Building,
+    core::{anycellmap::AnyCellmap, position::Ps, rng::WorldRng},
+    search::{Budget, Progress},
+    worldmap::Walker,
+};
+
+/// Cost of the cells the target can't be reached from.
+pub const UNREACHABLE: u32 = u32::MAX;
+/// Fields kept at once, the least recently used one goes first.
+pub const MAX_FIELDS: usize = 32;
+
+/// What getting to `target` costs from every cell of every floor.
+pub struct FlowField {
+    pub target: Ps,
+    // building it was found on and its revision then
+    generation: u64,
+    revision: u64,
+    costs: Vec<AnyCellmap<u32>>,
+}
+
+impl FlowField {
+    /// Finds the field going back from `target` over every cell `walker` can stand on,
+    /// agents are not in the way. Without a budget, the path queue builds fields a budget at
+    /// a time.
+    #[cfg(test)]
+    pub fn new(building: &Building, walker: Walker, target: Ps) -> Self {
+        FieldBuild::new(building, target)
+            .run(building, walker, &Budget::unlimited())
+            .finished()
+    }
+
+    /// Found on `building` as it is now.
+    pub fn is_current(&self, building: &Building) -> bool {
+        self.generation == building.generation() && self.revision == building.revision()
+    }
+
+    /// What getting to the target from `ps` costs, `UNREACHABLE` if it can't be done.
+    pub fn cost(&self, ps: &Ps) -> u32 {
+        *self.costs[ps.floor].get_pos(ps)
+    }
+
+    /// Next step downhill from `here`, `None` on the target or when agents stand on every cell
+    /// closer to it. Of the best cells a free one is taken, when agents stand on all of them any
+    /// free cell closer to the target will do.
+    pub fn next_step(
+        &self,
+        building: &Building,
+        walker: Walker,
+        skip_occupied: bool,
+        here: Ps,
+        rng: &mut WorldRng,
+    ) -> Option<Ps> {
+        if here == self.target {
+            return None;
+        }
+        let cost = self.cost(&here);
+        let mut downhill: Vec<(Ps, u32)> = building
+            .steps_around(&here, walker, false)
+            .into_iter()
+            .map(|p| (p, building.step_cost(&here, &p)))
+            .chain(building.connections(&here, walker, false))
+            .filter(|(p, _)| building.get_pos(p).is_passable_for(walker, skip_occupied))
+            .filter(|(p, _)| self.cost(p) < cost)
+            .collect();
+        rng.shuffle(&mut downhill);
+        downhill
+            .iter()
+            .find(|(p, step)| self.cost(p).saturating_add(*step) == cost)
+            .or_else(|| downhill.first())
+            .map(|(next, _)| *next)
+    }
+}
+
+/// A field being found, a budget at a time.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FieldBuild {
+    target: Ps,
+    // a saved build is given the building it is restored with
+    #[serde(skip)]
+    generation: u64,
+    revision: u64,
+    costs: Vec<AnyCellmap<u32>>,
+    open: BinaryHeap<Reverse<(u32, usize, usize, usize)>>,
+}
+
+impl FieldBuild {
+    pub fn new(building: &Building, target: Ps) -> Self {
+        let mut costs: Vec<AnyCellmap<u32>> = building
+            .sizes()
+            .iter()
+            .map(|(w, h)| AnyCellmap::new(&UNREACHABLE, *w as i32, *h as i32))
+            .collect();
+        let mut open = BinaryHeap::new();
+        *costs[target.floor].get_pos_mut(&target) = 0;
+        open.push(Reverse((0, target.floor, target.y, target.x)));
+        Self {
+            target,
+            generation: building.generation(),
+            revision: building.revision(),
+            costs,
+            open,
+        }
+    }
+
+    /// Goes on from where the last run stopped, from the target again when the building
+    /// changed meanwhile.
+    pub fn run(
+        &mut self,
+        building: &Building,
+        walker: Walker,
+        budget: &Budget,
+    ) -> Progress<FlowField> {
+        if self.generation != building.generation() || self.revision != building.revision() {
+            *self = Self::new(building, self.target);
+        }
+        while !budget.is_spent() {
+            let Some(Reverse((cost, floor, y, x))) = self.open.pop() else {
+                return Progress::Done(FlowField {
+                    target: self.target,
+                    generation: self.generation,
+                    revision: self.revision,
+                    costs: std::mem::take(&mut self.costs),
+                });
+            };
+            let here = Ps { x, y, floor };
+            if cost > *self.costs[floor].get_pos(&here) {
+                continue;
+            }
+            budget.spend(1);
+            // whoever steps from a cell around into this one pays for entering it
+            let around = building
+                .steps_around(&here, walker, false)
+                .into_iter()
+                .map(|p| (p, building.step_cost(&p, &here)))
+                .chain(building.connections(&here, walker, false));
+            for (p, step) in around {
+                let through = cost.saturating_add(step);
+                let known = self.costs[p.floor].get_pos_mut(&p);
+                if through < *known {
+                    *known = through;
+                    self.open.push(Reverse((through, p.floor, p.y, p.x)));
+                }
+            }
+        }
+        Progress::Paused
+    }
+
+    /// Goes on over `building`, the one it was saved with.
+    pub fn adopt(&mut self, building: &Building) {
+        self.generation = building.generation();
+    }
+}
+
+impl fmt::Debug for FieldBuild {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FieldBuild({:?}, {} open)", self.target, self.open.len())
+    }
+}
+
+/// Fields of the building by target, the path queue finds them and they are kept until the
+/// building changes.
+#[derive(Default)]
+pub struct FlowFields {
+    cache: Mutex<FieldCache>,
+}
+
+#[derive(Default)]
+struct FieldCache {
+    fields: HashMap<(Ps, Walker), (Arc<FlowField>, u64)>,
+    // grows with every lookup, marks when a field was used last
+    clock: u64,
+}
+
+impl FlowFields {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Field toward `target` for `walker` if one was found on the building as it is now.
+    pub fn cached(
+        &self,
+        building: &Building,
+        walker: Walker,
+        target: Ps,
+    ) -> Option<Arc<FlowField>> {
+        let mut cache = self.cache.lock();
+        cache.clock += 1;
+        let now = cache.clock;
+        match cache.fields.get_mut(&(target, walker)) {
+            Some((field, used)) if field.is_current(building) => {
+                *used = now;
+                Some(field.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Keeps the field, unless the same one was found meanwhile, then that one is given back.
+    pub fn insert(&self, walker: Walker, field: Arc<FlowField>) -> Arc<FlowField> {
+        let mut cache = self.cache.lock();
+        cache.clock += 1;
+        let now = cache.clock;
+        let key = (field.target, walker);
+        if let Some((known, used)) = cache.fields.get_mut(&key) {
+            if known.generation == field.generation && known.revision == field.revision {
+                *used = now;
+                return known.clone();
+            }
+        }
+        if !cache.fields.contains_key(&key) && cache.fields.len() >= MAX_FIELDS {
+            let oldest = cache
+                .fields
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                cache.fields.remove(&oldest);
+            }
+        }
+        cache.fields.insert(key, (field.clone(), now));
+        field
+    }
+}
+
+impl fmt::Debug for FlowFields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FlowFields({} cached)", self.cache.lock().fields.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::building::testing::ps;
+
+    // the way from the left to the right winds around the walls, the corner room is shut
+    fn maze() -> Building {
+        Building::from_rows(&[
+            "..#.....#...",
+            "..#..#..#...",
+            "..#..#..#...",
+            "..........##",
+            "#####.#...#.",
+            ".......#..#.",
+        ])
+    }
+
+    #[test]
+    fn next_steps_go_downhill_to_the_target() {
+        let building = maze();
+        let target = ps(9, 5);
+        let field = FlowField::new(&building, Walker::Dog, target);
+        let mut rng = WorldRng::new(1);
+        for start in [ps(0, 0), ps(0, 5), ps(11, 0)] {
+            let mut here = start;
+            while let Some(next) = field.next_step(&building, Walker::Dog, false, here, &mut rng) {
+                assert!(
+                    field.cost(&next) < field.cost(&here),
+                    "{:?} to {:?}",
+                    here,
+                    next
+                );
+                here = next;
+            }
+            assert_eq!(here, target, "from {:?}", start);
+        }
+        assert_eq!(field.cost(&ps(11, 5)), UNREACHABLE);
+        assert_eq!(
+            field.next_step(&building, Walker::Dog, false, ps(11, 5), &mut rng),
+            None
+        );
+    }
+
+    #[test]
+    fn paused_build_finds_the_same_field() {
+        let building = maze();
+        let target = ps(9, 5);
+        let whole = FlowField::new(&building, Walker::Dog, target);
+        let mut build = FieldBuild::new(&building, target);
+        let field = loop {
+            if let Progress::Done(field) = build.run(&building, Walker::Dog, &Budget::new(3)) {
+                break field;
+            }
+        };
+        for y in 0..6 {
+            for x in 0..12 {
+                assert_eq!(field.cost(&ps(x, y)), whole.cost(&ps(x, y)));
+            }
+        }
+    }
+
+    #[test]
+    fn paused_build_starts_over_on_another_building() {
+        let maze = maze();
+        // as unchanged as the maze, smaller and without walls
+        let open = Building::from_rows(&["..........", "..........", ".........."]);
+        assert_eq!(open.revision(), maze.revision());
+        let target = ps(9, 2);
+        let mut build = FieldBuild::new(&maze, target);
+        assert!(matches!(
+            build.run(&maze, Walker::Dog, &Budget::new(3)),
+            Progress::Paused
+        ));
+        let Progress::Done(field) = build.run(&open, Walker::Dog, &Budget::unlimited()) else {
+            panic!("paused without a budget");
+        };
+        assert!(field.is_current(&open));
+        let whole = FlowField::new(&open, Walker::Dog, target);
+        for y in 0..3 {
+            for x in 0..10 {
+                assert_eq!(field.cost(&ps(x, y)), whole.cost(&ps(x, y)));
+            }
+        }
+    }
+}
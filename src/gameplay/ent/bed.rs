@@ -1,6 +1,6 @@
 use comfy::{Entity, Transform};
 
-use crate::{behavior::interactive::InteractiveObjectHandle, core::position::Ps, state::Reality};
+use crate::{behavior::interactive::InteractiveObjectHandle, building::floor_of, core::position::Ps, persistence::INTERACTIVE, state::Reality};
 
 use super::MapEntityObject;
 
@@ -21,10 +21,12 @@ impl Bed {
 impl crate::core::Initializable for Bed {
     fn initialize(&mut self, entity: &Entity, transform: &mut Transform, reality: &mut Reality) {
         self.initialized = true;
-        let position: Ps = transform.position.into();
+        let position = Ps::from(transform.position).with_floor(floor_of(*entity));
         // println!("Bed {:?} initialized", entity);
         let mut lock = reality.interactive.lock();
-        lock.insert(position, InteractiveObjectHandle::new(crate::behavior::item_types::BED, entity.to_owned(), transform.position.into(), None));
+        let handle = InteractiveObjectHandle::new(crate::behavior::item_types::BED, entity.to_owned(), position, None);
+        reality.persistence.lock().register(*entity, handle.item_type, INTERACTIVE, &handle);
+        lock.insert(position, handle);
     }
     
     fn is_initialized(&self) -> bool {
@@ -1,6 +1,6 @@
 use comfy::{Entity, IVec2, Transform};
 
-use crate::{behavior::interactive::InteractiveObjectHandle, core::position::Ps, state::Reality};
+use crate::{behavior::interactive::InteractiveObjectHandle, building::floor_of, core::position::Ps, persistence::INTERACTIVE, state::Reality};
 
 use super::MapEntityObject;
 
@@ -18,7 +18,7 @@ impl Conputer {
             workplace,
             initialized: false,
             use_animation_playing: true,
-            handle_position: Ps {x: 0, y: 0}
+            handle_position: Ps {x: 0, y: 0, floor: 0}
         }
     }
 }
@@ -28,7 +28,7 @@ impl crate::core::Initializable for Conputer {
         self.initialized = true;
         // println!("Conputer {:?} initialized:: {:?}", entity, transform);
         let mut lock = reality.interactive.lock();
-        let ps: Ps = transform.position.into();
+        let ps = Ps::from(transform.position).with_floor(floor_of(*entity));
         let handle = InteractiveObjectHandle::new(
             crate::behavior::item_types::CONPUTER,
             entity.to_owned(),
@@ -36,6 +36,7 @@ impl crate::core::Initializable for Conputer {
             Some(self.workplace),
         );
         self.handle_position = handle.get_interactive_ps();
+        reality.persistence.lock().register(*entity, handle.item_type, INTERACTIVE, &handle);
         lock.insert(
             self.handle_position,
             handle,
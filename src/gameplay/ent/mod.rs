@@ -4,8 +4,15 @@ pub mod officeworker;
 
 use std::fmt::Debug;
 
+use crate::core::position::Ps;
+
 pub trait MapEntityObject: Debug + Send + Sync {}
 
+/// Entity spawned from the map tile of a cell, it goes away when the map is reloaded
+/// or the cell is edited.
+#[derive(Debug, Copy, Clone)]
+pub struct MapTile(pub Ps);
+
 #[derive(Debug, Copy, Clone)]
 pub struct Grass;
 
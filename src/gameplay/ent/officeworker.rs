@@ -1,23 +1,28 @@
 use crate::{
     behavior::{
-        item_types::*, mental::{IntentionClass, IntentionCompleted, PRIORITY_BASE}, messaging::communication::Communicator, routine::{gotoroutine::GoToRoutine, randomwalk::RandomStepRoutine}, sanity::{Routine, Sanity, SelfAware}
+        actor::Actor, item_types::*, mental::{IntentionClass, IntentionCompleted, PRIORITY_BASE}, messaging::communication::Communicator, routine::{gotoroutine::GoToRoutine, randomwalk::RandomStepRoutine}, sanity::{Routine, Sanity, SelfAware}
     },
     core::{
         position::{Ps, PsProvider},
+        rng::WorldRng,
         Initializable,
     },
     gameplay::{gametime::Time, humanclothes::Look},
+    persistence::CREATURE,
     state::Reality,
+    ui::statusbar::Statusbar,
+    worldmap::Walker,
 };
-use comfy::ChooseRandom;
-use comfy::Entity;
-use comfy::RandomRange;
+use comfy::{draw_rect_outline, vec2, world, Entity, Transform, RED};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OfficeWorkerRoutine {
     walker_routine: GoToRoutine,
     pub assigned_bed: Option<Ps>,
     pub assigned_office: Option<Ps>,
+    // looked up again on every think, not worth saving
+    #[serde(skip)]
     pub visible_entities: Vec<Entity> 
 }
 
@@ -45,7 +50,8 @@ impl Initializable for OfficeWorker {
         reality: &mut Reality,
     ) {
         let mut locked = reality.interactive.lock();
-        let possible: Vec<Ps> = locked
+        let mut rng = reality.rng.lock();
+        let mut possible: Vec<Ps> = locked
             .iter()
             .filter(|handle| handle.1.item_type == CONPUTER)
             .filter(|handle| handle.1.available())
@@ -54,11 +60,13 @@ impl Initializable for OfficeWorker {
         if possible.len() < 1 {
             println!("Not enough conputers for Office Worker {:?}", entity);
         }
-        let handle = locked.get_mut(possible.choose().unwrap()).unwrap();
+        // hashmap order is not stable between runs, so sort before picking
+        possible.sort_by_key(|ps| (ps.floor, ps.x, ps.y));
+        let handle = locked.get_mut(rng.choose(&possible).unwrap()).unwrap();
         self.assign_office(handle.get_interactive_ps());
         handle.assign();
 
-        let possible_bed: Vec<Ps> = locked
+        let mut possible_bed: Vec<Ps> = locked
             .iter()
             .filter(|handle| handle.1.item_type == BED)
             .filter(|handle| handle.1.available())
@@ -67,13 +75,15 @@ impl Initializable for OfficeWorker {
         if possible_bed.len() < 1 {
             println!("Not enough beds for Office Worker {:?}", entity);
         }
-        let handle_bed = locked.get_mut(possible_bed.choose().unwrap()).unwrap();
+        possible_bed.sort_by_key(|ps| (ps.floor, ps.x, ps.y));
+        let handle_bed = locked.get_mut(rng.choose(&possible_bed).unwrap()).unwrap();
         self.assign_bed(handle_bed.get_interactive_ps());
         handle_bed.assign();
 
         // init body parts
         self.look.spawn_for_entity(entity.clone());
 
+        reality.persistence.lock().register(*entity, OFFICE_WORKER, CREATURE, &self.sa);
         self.initialized = true;
 
         println!("{:?}", self);
@@ -119,7 +129,8 @@ impl OfficeWorkerRoutine {
                 if sanity.no_intentions_left()
                     && sanity.get_current_ps() == self.walker_routine.target
                 {
-                    do_chunk_of_sleep(sanity, usize::gen_range(1, 2) as isize, &map.time)
+                    let hours = sanity.rng.gen_range(1, 2);
+                    do_chunk_of_sleep(sanity, hours, &map.time)
                 }
                 self.walker_routine
                     .get_processing_fn(sanity, result, map, entity, communication, dt);
@@ -147,7 +158,8 @@ impl OfficeWorkerRoutine {
                 if sanity.no_intentions_left()
                     && sanity.get_current_ps() == self.walker_routine.target
                 {
-                    do_chunk_of_work(sanity, usize::gen_range(5, 15) as isize, &map.time)
+                    let minutes = sanity.rng.gen_range(5, 15);
+                    do_chunk_of_work(sanity, minutes, &map.time)
                 }
                 self.walker_routine
                     .get_processing_fn(sanity, result, map, entity, communication, dt);
@@ -189,22 +201,59 @@ impl Routine for OfficeWorkerRoutine {
     }
 }
 
+impl Actor for OfficeWorker {
+    type R = OfficeWorkerRoutine;
+
+    const CLASS: &'static str = OFFICE_WORKER;
+
+    fn sa(&self) -> &SelfAware<OfficeWorkerRoutine> {
+        &self.sa
+    }
+
+    fn sa_mut(&mut self) -> &mut SelfAware<OfficeWorkerRoutine> {
+        &mut self.sa
+    }
+
+    fn update_view(&self, entity: Entity, _paused: bool) {
+        if let Ok(mut statusbar) = world().get::<&mut Statusbar>(entity) {
+            let steps = self.sa.sanity.lock().mv.current_move_path.calculated_steps.len();
+            statusbar.show("steps".to_owned(), steps as f32, 100.0);
+            statusbar.show(
+                "visible".to_owned(),
+                self.sa.routine.visible_entities.len() as f32,
+                5.0,
+            )
+        }
+    }
+
+    fn draw_debug(&self) {
+        let wrld = world();
+        for item in self.sa.routine.visible_entities.iter() {
+            if let Ok(transform) = wrld.get::<&Transform>(*item) {
+                draw_rect_outline(transform.position, vec2(1.0, 1.0), 0.3, RED, 200);
+            }
+        }
+    }
+}
+
 impl OfficeWorker {
-    pub fn new(name: String, speed: f32, pos: Ps) -> Self {
+    pub fn new(name: String, speed: f32, pos: Ps, rng: &mut WorldRng) -> Self {
         Self {
             name,
             initialized: false,
             sa: SelfAware::new(
                 speed,
                 pos,
+                Walker::Human,
                 Box::new(OfficeWorkerRoutine {
                     walker_routine: GoToRoutine { target: pos },
                     assigned_bed: None,
                     assigned_office: None,
                     visible_entities: Vec::new()
                 }),
+                rng.fork(),
             ),
-            look: Look::new(),
+            look: Look::new(rng),
         }
     }
 
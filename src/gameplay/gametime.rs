@@ -1,6 +1,8 @@
 use std::{fmt, ops::Sub};
 
-#[derive(Clone, Debug, Eq, Copy)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Eq, Copy, Serialize, Deserialize)]
 pub struct TimeSpan {
     pub minutes: isize,
 }
@@ -60,7 +62,7 @@ impl Sub for TimeSpan {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Time {
     pub days: usize,
     pub hours: usize,
@@ -1,10 +1,11 @@
-use comfy::{commands, vec2, ChooseRandom, Entity, Sprite, Transform};
+use comfy::{commands, vec2, Entity, Sprite, Transform};
+use serde::{Deserialize, Serialize};
 
-use crate::{lazy_load_texture, utils::fileutils::list_assets_subdirectory, RES_I32};
+use crate::{core::rng::WorldRng, lazy_load_texture, utils::fileutils::list_assets_subdirectory, RES_I32};
 
 pub type ClothReference = String;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Look {
     pub hair: ClothReference,
     pub body: Option<ClothReference>,
@@ -13,7 +14,7 @@ pub struct Look {
 
 fn assets_sub_with_prefix(sub: &str, prefix: &str) -> Vec<String> {
     let all_files = list_assets_subdirectory(sub).unwrap();
-    let filtered_files: Vec<String> = all_files
+    let mut filtered_files: Vec<String> = all_files
         .into_iter()
         .filter(|p| p.starts_with(&(prefix.to_owned() + "_")))
         .map(|str| format!("{}/{}", sub, str))
@@ -21,34 +22,36 @@ fn assets_sub_with_prefix(sub: &str, prefix: &str) -> Vec<String> {
     if filtered_files.len() < 1 {
         panic!("Assets with prefix '{}' not found", prefix);
     }
+    // directory listing order depends on the filesystem
+    filtered_files.sort();
     return filtered_files;
 }
 
-fn pick_random_asset_with_prefix(prefix: String) -> ClothReference {
+fn pick_random_asset_with_prefix(prefix: String, rng: &mut WorldRng) -> ClothReference {
     let eyes = assets_sub_with_prefix("human", &prefix);
-    let chosen: ClothReference = eyes.choose().unwrap().to_owned();
+    let chosen: ClothReference = rng.choose(&eyes).unwrap().to_owned();
     chosen
 }
 
 impl Look {
-    pub fn new() -> Self {
+    pub fn new(rng: &mut WorldRng) -> Self {
         Self {
-            hair: Self::pick_hair(),
-            body: Some(Self::pick_clothes()),
-            eyes: Self::pick_eyes()
+            hair: Self::pick_hair(rng),
+            body: Some(Self::pick_clothes(rng)),
+            eyes: Self::pick_eyes(rng)
         }
     }
 
-    pub fn pick_eyes() -> ClothReference {
-        pick_random_asset_with_prefix("eyes".into())
+    pub fn pick_eyes(rng: &mut WorldRng) -> ClothReference {
+        pick_random_asset_with_prefix("eyes".into(), rng)
     }
 
-    pub fn pick_clothes() -> ClothReference {
-        pick_random_asset_with_prefix("clothes".into())
+    pub fn pick_clothes(rng: &mut WorldRng) -> ClothReference {
+        pick_random_asset_with_prefix("clothes".into(), rng)
     }
 
-    pub fn pick_hair() -> ClothReference {
-        pick_random_asset_with_prefix("hair".into())
+    pub fn pick_hair(rng: &mut WorldRng) -> ClothReference {
+        pick_random_asset_with_prefix("hair".into(), rng)
     }
 
     pub fn lazy_load_sprites(&self) {
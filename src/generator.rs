@@ -0,0 +1,467 @@
+// generator draws office floors from a seed: a corridor along one side, more corridors
+// going across with rooms on both sides, offices full of desks and bedrooms full of beds
+
+use std::{collections::VecDeque, fmt, io, ops::Range, path::Path};
+
+use comfy::{HashSet, IVec2};
+use serde::{Deserialize, Serialize};
+use tiled::PropertyValue;
+
+use crate::{
+    core::{anycellmap::AnyCellmap, rng::WorldRng},
+    tiledreader::{load_map_text, DecorTile, MapLoadError},
+    tiledwriter::{assets_prefix, TileDef, TiledWriter},
+    worldmap::Cellmap,
+    RES_I32,
+};
+
+// name the generated map goes by when it is loaded, it never touches the disk
+const GENERATED_NAME: &str = "generated.tmx";
+
+// inside of a room, not counting its walls
+const MIN_ROOM_WIDTH: usize = 3;
+const MIN_ROOM_DEPTH: usize = 3;
+
+#[derive(Debug)]
+pub enum GenerateError {
+    Invalid(String),
+    /// The map is too small for what was asked of it.
+    NoRoom(String),
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenerateError::Invalid(reason) => write!(f, "invalid layout parameters: {}", reason),
+            GenerateError::NoRoom(reason) => write!(f, "layout does not fit: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OfficeParams {
+    /// Same seed and parameters, same layout.
+    pub seed: u64,
+    /// Size in tiles, outer walls included.
+    pub width: usize,
+    pub height: usize,
+    pub offices: usize,
+    pub desks_per_office: usize,
+    pub bedrooms: usize,
+    pub beds_per_bedroom: usize,
+    pub corridor_width: usize,
+}
+
+impl Default for OfficeParams {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            width: 60,
+            height: 40,
+            offices: 4,
+            desks_per_office: 4,
+            bedrooms: 2,
+            beds_per_bedroom: 3,
+            corridor_width: 2,
+        }
+    }
+}
+
+impl OfficeParams {
+    pub fn validate(&self) -> Result<(), GenerateError> {
+        if self.corridor_width == 0 {
+            return Err(GenerateError::Invalid(
+                "corridor_width has to be at least 1".to_owned(),
+            ));
+        }
+        if self.offices + self.bedrooms == 0 {
+            return Err(GenerateError::Invalid("no rooms to lay out".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ground {
+    Corridor,
+    Office,
+    Bedroom,
+}
+
+/// Side of a desk the worker sits on, offsets are in game coordinates with y going up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Facing {
+    North,
+    South,
+    East,
+    West,
+}
+
+impl Facing {
+    const ALL: [Facing; 4] = [Facing::North, Facing::South, Facing::East, Facing::West];
+
+    fn workplace(&self) -> (i32, i32) {
+        match self {
+            Facing::North => (0, 1),
+            Facing::South => (0, -1),
+            Facing::East => (1, 0),
+            Facing::West => (-1, 0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Thing {
+    Nothing,
+    Wall,
+    Conputer(Facing),
+    Bed,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Spot {
+    ground: Ground,
+    thing: Thing,
+}
+
+#[derive(Debug)]
+struct Room {
+    ground: Ground,
+    xs: Range<usize>,
+    rows: Range<usize>,
+    /// Cell right inside the door, kept free so the room can't be shut.
+    entrance: (usize, usize),
+}
+
+/// Generated floor, in Tiled coordinates: rows go down from the top left corner.
+#[derive(Debug, Clone)]
+pub struct OfficeLayout {
+    width: usize,
+    height: usize,
+    spots: Vec<Spot>,
+}
+
+impl OfficeLayout {
+    pub fn generate(params: &OfficeParams) -> Result<Self, GenerateError> {
+        params.validate()?;
+        let mut rng = WorldRng::new(params.seed);
+        let (w, h, cw) = (params.width, params.height, params.corridor_width);
+        let too_small = || {
+            GenerateError::NoRoom(format!(
+                "{}x{} is too small for {} rooms with {} wide corridors",
+                w,
+                h,
+                params.offices + params.bedrooms,
+                cw
+            ))
+        };
+        let mut layout = Self {
+            width: w,
+            height: h,
+            spots: vec![
+                Spot {
+                    ground: Ground::Corridor,
+                    thing: Thing::Wall,
+                };
+                w * h
+            ],
+        };
+
+        // the corridor along one side, the rooms take the rest past a wall
+        let inner_w = w.checked_sub(3 + cw).ok_or_else(too_small)?;
+        let (spine, area) = if rng.below(2) == 0 {
+            (1..1 + cw, 2 + cw..w - 1)
+        } else {
+            (w - 1 - cw..w - 1, 1..1 + inner_w)
+        };
+        for row in 1..h.saturating_sub(1) {
+            layout.carve(spine.clone(), row..row + 1, Ground::Corridor);
+        }
+
+        let mut grounds = vec![Ground::Office; params.offices];
+        grounds.extend(vec![Ground::Bedroom; params.bedrooms]);
+        rng.shuffle(&mut grounds);
+        let per_row = (area.len() + 1) / (MIN_ROOM_WIDTH + 1);
+        if per_row == 0 {
+            return Err(too_small());
+        }
+        // every band is a row of rooms, a corridor and another row of rooms
+        let bands = grounds.len().div_ceil(per_row).div_ceil(2);
+        let room_rows = bands * 2;
+        let taken = bands * (cw + 2) + bands - 1;
+        let depth_total = h
+            .checked_sub(2 + taken)
+            .filter(|total| total / room_rows >= MIN_ROOM_DEPTH)
+            .ok_or_else(too_small)?;
+        let depths: Vec<usize> = (0..room_rows)
+            .map(|i| depth_total / room_rows + usize::from(i < depth_total % room_rows))
+            .collect();
+
+        let mut rooms = Vec::new();
+        let mut grounds = grounds.into_iter();
+        let mut row = 1;
+        for band in 0..bands {
+            let top = row..row + depths[band * 2];
+            let corridor = top.end + 1..top.end + 1 + cw;
+            let bottom = corridor.end + 1..corridor.end + 1 + depths[band * 2 + 1];
+            row = bottom.end + 1;
+            // the corridor goes through the wall next to the one along the side
+            let across = area.start.min(spine.start)..area.end.max(spine.end);
+            layout.carve(across, corridor.clone(), Ground::Corridor);
+            for (i, rows) in [top, bottom].into_iter().enumerate() {
+                let count = grounds.len().div_ceil(room_rows - band * 2 - i);
+                let door_row = if i == 0 { rows.end } else { rows.start - 1 };
+                let kinds: Vec<Ground> = grounds.by_ref().take(count).collect();
+                rooms.extend(layout.lay_rooms(&kinds, area.clone(), rows, door_row, &mut rng));
+            }
+        }
+
+        for (number, room) in rooms.iter().enumerate() {
+            let (wanted, placed) = match room.ground {
+                Ground::Office => (
+                    params.desks_per_office,
+                    layout.place_desks(room, params.desks_per_office, &mut rng),
+                ),
+                _ => (
+                    params.beds_per_bedroom,
+                    layout.place_beds(room, params.beds_per_bedroom, &mut rng),
+                ),
+            };
+            if placed < wanted {
+                return Err(GenerateError::NoRoom(format!(
+                    "room {} fits only {} of {} {}",
+                    number,
+                    placed,
+                    wanted,
+                    if room.ground == Ground::Office {
+                        "desks"
+                    } else {
+                        "beds"
+                    }
+                )));
+            }
+        }
+        Ok(layout)
+    }
+
+    fn index(&self, x: usize, row: usize) -> usize {
+        row * self.width + x
+    }
+
+    fn carve(&mut self, xs: Range<usize>, rows: Range<usize>, ground: Ground) {
+        for row in rows {
+            for x in xs.clone() {
+                let index = self.index(x, row);
+                self.spots[index] = Spot {
+                    ground,
+                    thing: Thing::Nothing,
+                };
+            }
+        }
+    }
+
+    // rooms side by side across `xs`, with a door in `door_row`
+    fn lay_rooms(
+        &mut self,
+        kinds: &[Ground],
+        xs: Range<usize>,
+        rows: Range<usize>,
+        door_row: usize,
+        rng: &mut WorldRng,
+    ) -> Vec<Room> {
+        if kinds.is_empty() {
+            return Vec::new();
+        }
+        let mut widths = vec![MIN_ROOM_WIDTH; kinds.len()];
+        let spare = xs.len() + 1 - kinds.len() * (MIN_ROOM_WIDTH + 1);
+        for _ in 0..spare {
+            widths[rng.below(kinds.len())] += 1;
+        }
+        let inside_row = if door_row < rows.start {
+            rows.start
+        } else {
+            rows.end - 1
+        };
+        let mut x = xs.start;
+        let mut rooms = Vec::new();
+        for (ground, width) in kinds.iter().zip(widths) {
+            let room_xs = x..x + width;
+            x += width + 1;
+            self.carve(room_xs.clone(), rows.clone(), *ground);
+            let door = rng.gen_range(room_xs.start, room_xs.end);
+            self.carve(door..door + 1, door_row..door_row + 1, *ground);
+            rooms.push(Room {
+                ground: *ground,
+                xs: room_xs,
+                rows: rows.clone(),
+                entrance: (door, inside_row),
+            });
+        }
+        rooms
+    }
+
+    // free cells of the room, in random order
+    fn free_cells(&self, room: &Room, rng: &mut WorldRng) -> Vec<(usize, usize)> {
+        let mut cells: Vec<(usize, usize)> = room
+            .rows
+            .clone()
+            .flat_map(|row| room.xs.clone().map(move |x| (x, row)))
+            .filter(|cell| *cell != room.entrance)
+            .collect();
+        rng.shuffle(&mut cells);
+        cells
+    }
+
+    fn place_desks(&mut self, room: &Room, count: usize, rng: &mut WorldRng) -> usize {
+        let mut seats = HashSet::new();
+        let mut placed = 0;
+        for (x, row) in self.free_cells(room, rng) {
+            if placed == count {
+                break;
+            }
+            if seats.contains(&(x, row)) {
+                continue;
+            }
+            let mut facings = Facing::ALL;
+            rng.shuffle(&mut facings);
+            for facing in facings {
+                let (dx, dy) = facing.workplace();
+                let seat = (x as i32 + dx, row as i32 - dy);
+                let seat = (seat.0 as usize, seat.1 as usize);
+                if !room.xs.contains(&seat.0) || !room.rows.contains(&seat.1) {
+                    continue;
+                }
+                if seats.contains(&seat)
+                    || self.spots[self.index(seat.0, seat.1)].thing != Thing::Nothing
+                {
+                    continue;
+                }
+                if self.try_place(x, row, Thing::Conputer(facing)) {
+                    seats.insert(seat);
+                    placed += 1;
+                    break;
+                }
+            }
+        }
+        placed
+    }
+
+    fn place_beds(&mut self, room: &Room, count: usize, rng: &mut WorldRng) -> usize {
+        let mut placed = 0;
+        for (x, row) in self.free_cells(room, rng) {
+            if placed == count {
+                break;
+            }
+            if self.try_place(x, row, Thing::Bed) {
+                placed += 1;
+            }
+        }
+        placed
+    }
+
+    // puts the thing down unless it cuts some part of the floor off
+    fn try_place(&mut self, x: usize, row: usize, thing: Thing) -> bool {
+        let index = self.index(x, row);
+        if self.spots[index].thing != Thing::Nothing {
+            return false;
+        }
+        self.spots[index].thing = thing;
+        if self.all_reachable() {
+            return true;
+        }
+        self.spots[index].thing = Thing::Nothing;
+        false
+    }
+
+    fn neighbours(&self, index: usize) -> impl Iterator<Item = usize> {
+        let (w, h) = (self.width, self.height);
+        let (x, row) = (index % w, index / w);
+        [
+            (x > 0).then(|| index - 1),
+            (x + 1 < w).then(|| index + 1),
+            (row > 0).then(|| index - w),
+            (row + 1 < h).then(|| index + w),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    // every free cell can be walked to from every other, and every bed from one of them;
+    // a sleeper blocks the bed, so beds don't count as a way through
+    fn all_reachable(&self) -> bool {
+        let free = |index: usize| self.spots[index].thing == Thing::Nothing;
+        let Some(start) = (0..self.spots.len()).find(|index| free(*index)) else {
+            return true;
+        };
+        let mut reached = vec![false; self.spots.len()];
+        reached[start] = true;
+        let mut queue = VecDeque::from([start]);
+        while let Some(index) = queue.pop_front() {
+            for next in self.neighbours(index) {
+                if free(next) && !reached[next] {
+                    reached[next] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+        (0..self.spots.len()).all(|index| match self.spots[index].thing {
+            Thing::Nothing => reached[index],
+            Thing::Bed => self.neighbours(index).any(|next| reached[next]),
+            _ => true,
+        })
+    }
+
+    /// The layout as a Tiled map with a tileset of its own.
+    pub fn writer(&self) -> TiledWriter {
+        let mut writer = TiledWriter::new(self.width, self.height);
+        let tile = |image: &str| TileDef::new(image, IVec2::splat(RES_I32));
+        let bg = self
+            .spots
+            .iter()
+            .map(|spot| {
+                writer.gid(Some(match spot.ground {
+                    Ground::Corridor => tile("floor_white.png"),
+                    Ground::Office => tile("floor_tiles_blue.png"),
+                    Ground::Bedroom => tile("floor_colorful.png"),
+                }))
+            })
+            .collect();
+        let cells = self
+            .spots
+            .iter()
+            .map(|spot| {
+                writer.gid(match spot.thing {
+                    Thing::Nothing => None,
+                    Thing::Wall => Some(tile("untitledhd.png").with_class("wall")),
+                    Thing::Conputer(facing) => {
+                        let (x, y) = facing.workplace();
+                        Some(
+                            TileDef::new("conputer.png", IVec2::new(11 * RES_I32, RES_I32))
+                                .with_class("conputer")
+                                .with_property("animated", PropertyValue::BoolValue(true))
+                                .with_property("frames", PropertyValue::IntValue(11))
+                                .with_property("x", PropertyValue::IntValue(x))
+                                .with_property("y", PropertyValue::IntValue(y)),
+                        )
+                    }
+                    Thing::Bed => Some(tile("bed16_hd.png").with_class("bed")),
+                })
+            })
+            .collect();
+        writer.add_floor(bg, cells, vec![0; self.spots.len()]);
+        writer
+    }
+
+    /// Saves the layout as a map Tiled can open.
+    pub fn export(&self, path: &Path) -> io::Result<()> {
+        self.writer().save(path)
+    }
+
+    /// Decor and cells, read the same way a map drawn in Tiled is.
+    pub fn load(&self) -> Result<Vec<(AnyCellmap<DecorTile>, Cellmap)>, MapLoadError> {
+        load_map_text(GENERATED_NAME, &self.writer().to_tmx(&assets_prefix()))
+    }
+}
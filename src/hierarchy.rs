@@ -0,0 +1,641 @@
+// hierarchy is the abstract graph long routes are found over (HPA*): every floor is cut into
+// square clusters, the cells where two clusters meet and the stairs become nodes, and what it
+// costs to walk between the nodes of one cluster is remembered until a cell of it changes.
+// A route is found over the nodes and only its first legs are walked out cell by cell
+
+use std::collections::LinkedList;
+
+use comfy::{Arc, HashMap, Mutex};
+use pathfinding::directed::{astar::astar, dijkstra::dijkstra_all};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    behavior::routing::{CellSearch, PathfindPoint},
+    building::{Building, Movement},
+    core::{position::Ps, rng::WorldRng},
+    search::{Budget, Progress, Search},
+    worldmap::{Cellmap, Walker},
+};
+
+/// Side of a cluster in cells.
+pub const CLUSTER_SIZE: usize = 10;
+/// Routes of fewer steps than this on one floor are found over the cells right away.
+pub const MIN_DISTANCE: u32 = 2 * CLUSTER_SIZE as u32;
+/// Steps a route is walked out ahead, the rest is found again when the agent gets there.
+pub const REFINE_AHEAD: usize = 2 * CLUSTER_SIZE;
+// openings between clusters this wide get a node at both ends instead of one in the middle
+const WIDE_ENTRANCE: usize = 6;
+
+// cell on the edge of a cluster and the one next to it in the cluster across
+type Facing = ((usize, usize), (usize, usize));
+
+/// Nodes of one cluster and what walking from each of them to the others costs.
+#[derive(Debug)]
+pub struct ClusterGraph {
+    edges: HashMap<Ps, Vec<(Ps, u32)>>,
+}
+
+impl ClusterGraph {
+    pub fn is_node(&self, ps: &Ps) -> bool {
+        self.edges.contains_key(ps)
+    }
+}
+
+/// Clusters of one floor, built when a route first needs them and dropped when a cell
+/// in them changes.
+#[derive(Debug)]
+pub struct Hierarchy {
+    columns: usize,
+    rows: usize,
+    dog: Mutex<Vec<Option<Arc<ClusterGraph>>>>,
+    human: Mutex<Vec<Option<Arc<ClusterGraph>>>>,
+}
+
+impl Hierarchy {
+    pub fn new(width: usize, height: usize) -> Self {
+        let columns = width.div_ceil(CLUSTER_SIZE);
+        let rows = height.div_ceil(CLUSTER_SIZE);
+        Self {
+            columns,
+            rows,
+            dog: Mutex::new(vec![None; columns * rows]),
+            human: Mutex::new(vec![None; columns * rows]),
+        }
+    }
+
+    pub fn cluster_of(&self, ps: &Ps) -> usize {
+        (ps.y / CLUSTER_SIZE) * self.columns + ps.x / CLUSTER_SIZE
+    }
+
+    /// Forgets the clusters walking over `ps` depends on: its own one and, for a cell on the
+    /// edge, the one across the edge, whose nodes face it.
+    pub fn invalidate(&self, ps: &Ps) {
+        let (column, row) = (ps.x / CLUSTER_SIZE, ps.y / CLUSTER_SIZE);
+        let mut stale = vec![(column, row)];
+        if ps.x.is_multiple_of(CLUSTER_SIZE) && column > 0 {
+            stale.push((column - 1, row));
+        }
+        if ps.x % CLUSTER_SIZE == CLUSTER_SIZE - 1 && column + 1 < self.columns {
+            stale.push((column + 1, row));
+        }
+        if ps.y.is_multiple_of(CLUSTER_SIZE) && row > 0 {
+            stale.push((column, row - 1));
+        }
+        if ps.y % CLUSTER_SIZE == CLUSTER_SIZE - 1 && row + 1 < self.rows {
+            stale.push((column, row + 1));
+        }
+        for clusters in [&self.dog, &self.human] {
+            let mut clusters = clusters.lock();
+            for (column, row) in stale.iter() {
+                clusters[row * self.columns + column] = None;
+            }
+        }
+    }
+
+    /// Graph of the cluster `id` of `cellmap` for `walker`, built if it is not known yet.
+    /// Building it is charged to `budget`.
+    pub fn cluster(
+        &self,
+        cellmap: &Cellmap,
+        walker: Walker,
+        movement: Movement,
+        id: usize,
+        budget: &Budget,
+    ) -> Arc<ClusterGraph> {
+        let clusters = match walker {
+            Walker::Dog => &self.dog,
+            Walker::Human => &self.human,
+        };
+        if let Some(graph) = &clusters.lock()[id] {
+            return graph.clone();
+        }
+        // built without holding the lock, other agents keep routing over the clusters they have;
+        // the cellmap can't change meanwhile, so whoever finishes first builds the same graph
+        let graph = Arc::new(self.build(cellmap, walker, movement, id, budget));
+        clusters.lock()[id].get_or_insert(graph).clone()
+    }
+
+    // corners of the cluster, the far one excluded
+    fn bounds(&self, id: usize) -> (Ps, Ps) {
+        let (column, row) = (id % self.columns, id / self.columns);
+        let from = Ps::from((column * CLUSTER_SIZE, row * CLUSTER_SIZE));
+        let to = Ps::from(((column + 1) * CLUSTER_SIZE, (row + 1) * CLUSTER_SIZE));
+        (from, to)
+    }
+
+    fn build(
+        &self,
+        cellmap: &Cellmap,
+        walker: Walker,
+        movement: Movement,
+        id: usize,
+        budget: &Budget,
+    ) -> ClusterGraph {
+        let nodes = self.nodes(cellmap, walker, id);
+        let edges = nodes
+            .iter()
+            .map(|node| {
+                let reached = dijkstra_all(node, |p| {
+                    budget.spend(1);
+                    steps_within(cellmap, walker, movement, self, id, p)
+                });
+                let costs = nodes
+                    .iter()
+                    .filter_map(|other| reached.get(other).map(|(_, cost)| (*other, *cost)))
+                    .collect();
+                (*node, costs)
+            })
+            .collect();
+        ClusterGraph { edges }
+    }
+
+    // cells on the edges that open into the next cluster, one or two per opening, and stairs
+    fn nodes(&self, cellmap: &Cellmap, walker: Walker, id: usize) -> Vec<Ps> {
+        let (from, to) = self.bounds(id);
+        let (width, height) = cellmap.wh_usize();
+        let (to_x, to_y) = (to.x.min(width), to.y.min(height));
+        let floor = cellmap.floor();
+        let open = |x: usize, y: usize| cellmap.get_xy(x, y).is_passable_for(walker, false);
+
+        let mut nodes = Vec::new();
+        // each edge as its cells inside and the cells across, when there is a cluster across
+        let mut edges: Vec<Vec<Facing>> = Vec::new();
+        if from.x > 0 {
+            edges.push(
+                (from.y..to_y)
+                    .map(|y| ((from.x, y), (from.x - 1, y)))
+                    .collect(),
+            );
+        }
+        if to_x < width {
+            edges.push((from.y..to_y).map(|y| ((to_x - 1, y), (to_x, y))).collect());
+        }
+        if from.y > 0 {
+            edges.push(
+                (from.x..to_x)
+                    .map(|x| ((x, from.y), (x, from.y - 1)))
+                    .collect(),
+            );
+        }
+        if to_y < height {
+            edges.push((from.x..to_x).map(|x| ((x, to_y - 1), (x, to_y))).collect());
+        }
+        // both clusters pick the same cells from an opening, so their nodes face each other
+        let mut close = |opening: &mut Vec<(usize, usize)>| {
+            if opening.len() >= WIDE_ENTRANCE {
+                nodes.push(opening[0]);
+                nodes.push(opening[opening.len() - 1]);
+            } else if !opening.is_empty() {
+                nodes.push(opening[opening.len() / 2]);
+            }
+            opening.clear();
+        };
+        for edge in edges {
+            let mut opening = Vec::new();
+            for (inside, across) in edge {
+                if open(inside.0, inside.1) && open(across.0, across.1) {
+                    opening.push(inside);
+                } else {
+                    close(&mut opening);
+                }
+            }
+            close(&mut opening);
+        }
+        for y in from.y..to_y {
+            for x in from.x..to_x {
+                if cellmap.get_xy(x, y).connector.is_some() && open(x, y) {
+                    nodes.push((x, y));
+                }
+            }
+        }
+        nodes.sort_by_key(|(x, y)| (*y, *x));
+        nodes.dedup();
+        nodes
+            .into_iter()
+            .map(|xy| Ps::from(xy).with_floor(floor))
+            .collect()
+    }
+
+    fn contains(&self, id: usize, ps: &Ps) -> bool {
+        let (from, to) = self.bounds(id);
+        ps.x >= from.x && ps.x < to.x && ps.y >= from.y && ps.y < to.y
+    }
+}
+
+// steps to the cells around `p` that stay in the cluster, with what entering them costs;
+// agents are not in the way
+fn steps_within(
+    cellmap: &Cellmap,
+    walker: Walker,
+    movement: Movement,
+    hierarchy: &Hierarchy,
+    id: usize,
+    p: &Ps,
+) -> Vec<(Ps, u32)> {
+    movement
+        .steps(cellmap, walker, false, p)
+        .into_iter()
+        .filter(|q| hierarchy.contains(id, q))
+        .map(|q| (q, cellmap.step_cost(p, &q)))
+        .collect()
+}
+
+/// Route from `start` toward `target` over the clusters, walked out only `REFINE_AHEAD` steps
+/// or a little more and found a budget at a time. Legs blocked by agents fall back to a route
+/// over the cells.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterSearch {
+    start: Ps,
+    target: Ps,
+    skip_occupied: bool,
+    stage: Stage,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Stage {
+    // how the start and the target connect to the nodes of their clusters isn't known yet
+    Ends,
+    // route over the nodes, `start` and `target` included
+    Nodes { ends: Ends, search: Search },
+    // a leg is blocked by agents, the route is found over the cells instead
+    Cells(CellSearch),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Ends {
+    // nodes of the start's cluster and what walking to them costs
+    start_edges: Vec<(Ps, u32)>,
+    // what getting from a cell of the target's cluster to the target costs
+    #[serde(with = "crate::core::serialization::ps_map")]
+    target_costs: HashMap<Ps, u32>,
+}
+
+impl ClusterSearch {
+    pub fn new(start: Ps, target: Ps, skip_occupied: bool) -> Self {
+        Self {
+            start,
+            target,
+            skip_occupied,
+            stage: Stage::Ends,
+        }
+    }
+
+    /// Steps toward the target without the start, `None` if it can't be reached.
+    pub fn run(
+        &mut self,
+        building: &Building,
+        walker: Walker,
+        rng: &mut WorldRng,
+        budget: &Budget,
+    ) -> Progress<Option<LinkedList<Ps>>> {
+        let (start, target, skip_occupied) = (self.start, self.target, self.skip_occupied);
+        loop {
+            match &mut self.stage {
+                Stage::Ends => {
+                    if !building
+                        .get_pos(&target)
+                        .is_passable_for(walker, skip_occupied)
+                    {
+                        return Progress::Done(None);
+                    }
+                    self.stage = Stage::Nodes {
+                        ends: Ends::new(building, walker, start, target, budget),
+                        search: Search::new(start),
+                    };
+                }
+                Stage::Nodes { ends, search } => {
+                    let found = search.run(
+                        budget,
+                        |p| ends.successors(building, walker, start, target, p, budget),
+                        |p| building.estimate(p, &target),
+                        |p| *p == target,
+                    );
+                    let waypoints = match found {
+                        Progress::Paused => return Progress::Paused,
+                        Progress::Done(None) => return Progress::Done(None),
+                        Progress::Done(Some((waypoints, _))) => waypoints,
+                    };
+                    match walk_legs(building, walker, skip_occupied, &waypoints, rng, budget) {
+                        Some(steps) => return Progress::Done(Some(steps)),
+                        None => {
+                            self.stage = Stage::Cells(CellSearch::new(start, target, skip_occupied))
+                        }
+                    }
+                }
+                Stage::Cells(search) => return search.run(building, walker, rng, budget),
+            }
+        }
+    }
+}
+
+// graph of the cluster `ps` is in
+fn cluster_at(
+    building: &Building,
+    walker: Walker,
+    ps: &Ps,
+    budget: &Budget,
+) -> (usize, Arc<ClusterGraph>) {
+    let cellmap = building.floor(ps.floor);
+    let hierarchy = cellmap.hierarchy();
+    let id = hierarchy.cluster_of(ps);
+    (
+        id,
+        hierarchy.cluster(cellmap, walker, building.movement(), id, budget),
+    )
+}
+
+impl Ends {
+    fn new(building: &Building, walker: Walker, start: Ps, target: Ps, budget: &Budget) -> Self {
+        let movement = building.movement();
+        let start_cellmap = building.floor(start.floor);
+        let (start_id, start_cluster) = cluster_at(building, walker, &start, budget);
+        let start_edges: Vec<(Ps, u32)> = {
+            let hierarchy = start_cellmap.hierarchy();
+            let reached = dijkstra_all(&start, |p| {
+                budget.spend(1);
+                steps_within(start_cellmap, walker, movement, hierarchy, start_id, p)
+            });
+            let mut edges: Vec<(Ps, u32)> = reached
+                .into_iter()
+                .filter(|(ps, _)| start_cluster.is_node(ps))
+                .map(|(ps, (_, cost))| (ps, cost))
+                .collect();
+            edges.sort_by_key(|(ps, _)| (ps.y, ps.x));
+            edges
+        };
+
+        // found backwards from the target
+        let target_cellmap = building.floor(target.floor);
+        let target_costs: HashMap<Ps, u32> = {
+            let hierarchy = target_cellmap.hierarchy();
+            let target_id = hierarchy.cluster_of(&target);
+            let reached = dijkstra_all(&target, |p| {
+                budget.spend(1);
+                steps_within(target_cellmap, walker, movement, hierarchy, target_id, p)
+                    .into_iter()
+                    .map(|(q, _)| (q, target_cellmap.step_cost(&q, p)))
+                    .collect::<Vec<_>>()
+            });
+            reached
+                .into_iter()
+                .map(|(ps, (_, cost))| (ps, cost))
+                .collect()
+        };
+
+        Self {
+            start_edges,
+            target_costs,
+        }
+    }
+
+    // nodes one step away from `p` on the abstract graph
+    fn successors(
+        &self,
+        building: &Building,
+        walker: Walker,
+        start: Ps,
+        target: Ps,
+        p: &Ps,
+        budget: &Budget,
+    ) -> Vec<(Ps, u32)> {
+        let mut next = if *p == start {
+            self.start_edges.clone()
+        } else {
+            let (_, graph) = cluster_at(building, walker, p, budget);
+            graph.edges.get(p).cloned().unwrap_or_default()
+        };
+        // into the next cluster, straight onto one of its nodes
+        let cellmap = building.floor(p.floor);
+        let own = cellmap.hierarchy().cluster_of(p);
+        for q in building.steps_around(p, walker, false) {
+            let (id, graph) = cluster_at(building, walker, &q, budget);
+            if id != own && graph.is_node(&q) {
+                next.push((q, cellmap.step_cost(p, &q)));
+            }
+        }
+        next.extend(building.connections(p, walker, false));
+        if let Some(cost) = self.target_costs.get(p) {
+            next.push((target, *cost));
+        }
+        next
+    }
+}
+
+// cells along the first legs between the nodes, `None` if agents block one of them
+fn walk_legs(
+    building: &Building,
+    walker: Walker,
+    skip_occupied: bool,
+    waypoints: &[Ps],
+    rng: &mut WorldRng,
+    budget: &Budget,
+) -> Option<LinkedList<Ps>> {
+    let mut steps = LinkedList::new();
+    for leg in waypoints.windows(2) {
+        let (from, to) = (leg[0], leg[1]);
+        let mut walked = walk_leg(building, walker, skip_occupied, from, to, rng, budget)?;
+        steps.append(&mut walked);
+        if steps.len() >= REFINE_AHEAD {
+            break;
+        }
+    }
+    Some(steps)
+}
+
+// cells from one node to the next, without `from`
+fn walk_leg(
+    building: &Building,
+    walker: Walker,
+    skip_occupied: bool,
+    from: Ps,
+    to: Ps,
+    rng: &mut WorldRng,
+    budget: &Budget,
+) -> Option<LinkedList<Ps>> {
+    let cellmap = building.floor(from.floor);
+    let hierarchy = cellmap.hierarchy();
+    let id = hierarchy.cluster_of(&from);
+    if from.floor != to.floor || !hierarchy.contains(id, &to) {
+        // a step over the edge of the cluster or up the stairs
+        return building
+            .get_pos(&to)
+            .is_passable_for(walker, skip_occupied)
+            .then(|| LinkedList::from([to]));
+    }
+    let (route, _) = astar(
+        &from,
+        |p| {
+            budget.spend(1);
+            let mut next = p.successors_weighted(building, walker, skip_occupied, rng);
+            next.retain(|(q, _)| q.floor == from.floor && hierarchy.contains(id, q));
+            next
+        },
+        |p| building.estimate(p, &to),
+        |p| *p == to,
+    )?;
+    Some(route.into_iter().skip(1).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        behavior::routing::try_find_route_from_to,
+        building::{testing::ps, CornerRule},
+    };
+
+    // rooms as big as the clusters, with a wall on their left and top and a door in each,
+    // every route goes through the doors and so through the nodes
+    fn rooms() -> Building {
+        let door = |wall: usize, room: usize| 10 * room + 1 + (3 * wall + 5 * room) % 9;
+        let rows: Vec<String> = (0..40)
+            .map(|y| {
+                (0..40)
+                    .map(|x| {
+                        let across = y > 0 && y % 10 == 0 && x != door(y / 10, x / 10);
+                        let along = x > 0 && x % 10 == 0 && y != door(x / 10, y / 10);
+                        if across || along {
+                            '#'
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let rows: Vec<&str> = rows.iter().map(String::as_str).collect();
+        Building::from_rows(&rows)
+    }
+
+    fn open() -> Building {
+        let rows = vec![".".repeat(40); 40];
+        let rows: Vec<&str> = rows.iter().map(String::as_str).collect();
+        Building::from_rows(&rows)
+    }
+
+    // walked diagonally, the top left cluster only leads to the bottom right one
+    // across the corner they share
+    fn corner() -> Building {
+        let rows: Vec<String> = (0..20)
+            .map(|y| {
+                (0..20)
+                    .map(|x| {
+                        let wall = (x == 10 && y < 9) || (y == 10 && x != 10);
+                        if wall {
+                            '#'
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let rows: Vec<&str> = rows.iter().map(String::as_str).collect();
+        Building::from_rows(&rows).with_movement(Movement {
+            diagonal: true,
+            corners: CornerRule::NoSqueeze,
+        })
+    }
+
+    // route over the clusters without a budget
+    fn find_route(building: &Building, start: Ps, target: Ps) -> Option<LinkedList<Ps>> {
+        ClusterSearch::new(start, target, false)
+            .run(
+                building,
+                Walker::Dog,
+                &mut WorldRng::new(1),
+                &Budget::unlimited(),
+            )
+            .finished()
+    }
+
+    // what the route over the nodes costs, before it is walked out
+    fn abstract_cost(building: &Building, start: Ps, target: Ps) -> Option<u32> {
+        let budget = Budget::unlimited();
+        let ends = Ends::new(building, Walker::Dog, start, target, &budget);
+        Search::new(start)
+            .run(
+                &budget,
+                |p| ends.successors(building, Walker::Dog, start, target, p, &budget),
+                |p| building.estimate(p, &target),
+                |p| *p == target,
+            )
+            .finished()
+            .map(|(_, cost)| cost)
+    }
+
+    // what walking the plain A* route over the cells costs
+    fn cell_cost(building: &Building, start: Ps, target: Ps) -> Option<u32> {
+        let mut rng = WorldRng::new(1);
+        let route = try_find_route_from_to(building, Walker::Dog, false, start, target, &mut rng)?;
+        let mut here = start;
+        let mut cost = 0;
+        for next in route {
+            cost += building.step_cost(&here, &next);
+            here = next;
+        }
+        Some(cost)
+    }
+
+    #[test]
+    fn abstract_route_costs_as_much_as_the_cells() {
+        let building = rooms();
+        let trips = [
+            (ps(1, 1), ps(38, 38)),
+            (ps(5, 35), ps(35, 5)),
+            (ps(23, 2), ps(24, 37)),
+            (ps(2, 24), ps(37, 21)),
+            (ps(14, 16), ps(16, 14)),
+        ];
+        for (start, target) in trips {
+            let cells = cell_cost(&building, start, target);
+            assert!(cells.is_some());
+            assert_eq!(
+                abstract_cost(&building, start, target),
+                cells,
+                "{:?} to {:?}",
+                start,
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn abstract_route_is_never_cheaper_than_the_cells() {
+        let building = open();
+        for (start, target) in [(ps(1, 1), ps(38, 1)), (ps(5, 35), ps(35, 5))] {
+            let cells = cell_cost(&building, start, target).unwrap();
+            let nodes = abstract_cost(&building, start, target).unwrap();
+            assert!(nodes >= cells, "{:?} to {:?}", start, target);
+        }
+    }
+
+    #[test]
+    fn diagonal_steps_cross_into_the_next_cluster() {
+        let building = corner();
+        let (start, target) = (ps(5, 5), ps(15, 15));
+        let cells = cell_cost(&building, start, target);
+        assert!(cells.is_some());
+        assert_eq!(abstract_cost(&building, start, target), cells);
+    }
+
+    #[test]
+    fn paused_search_finds_the_same_route() {
+        let building = rooms();
+        let (start, target) = (ps(2, 3), ps(37, 36));
+        let whole = find_route(&building, start, target);
+
+        let building = rooms();
+        let mut search = ClusterSearch::new(start, target, false);
+        let mut rng = WorldRng::new(1);
+        let mut pauses = 0;
+        let piecewise = loop {
+            match search.run(&building, Walker::Dog, &mut rng, &Budget::new(5)) {
+                Progress::Done(route) => break route,
+                Progress::Paused => pauses += 1,
+            }
+        };
+        assert!(pauses > 0);
+        assert_eq!(piecewise, whole);
+    }
+}
@@ -0,0 +1,227 @@
+// hotreload swaps the map under a running simulation: agents, items and the clock stay,
+// the building and everything spawned from its tiles is made again from the files on disk
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use comfy::{commands, world, Entity, HashSet};
+
+use crate::{
+    behavior::interactive::InteractiveObjectHandle,
+    building::Building,
+    core::position::{Ps, PsProvider},
+    gameplay::ent::{officeworker::OfficeWorker, MapTile},
+    simulation::{self, Simulation},
+    tiledreader::map_file,
+};
+
+/// How often the map files are looked at, in seconds.
+pub const CHECK_INTERVAL: f32 = 0.5;
+
+/// Notices when a map file is saved, by looking at the modification times now and then.
+pub struct MapWatcher {
+    files: Vec<PathBuf>,
+    stamps: Vec<Option<SystemTime>>,
+    since_check: f32,
+}
+
+impl MapWatcher {
+    pub fn new(maps: &[PathBuf]) -> Self {
+        let files: Vec<PathBuf> = maps.iter().map(|map| map_file(map)).collect();
+        let stamps = modified(&files);
+        Self {
+            files,
+            stamps,
+            since_check: 0.0,
+        }
+    }
+
+    /// True once for every save of any of the files.
+    pub fn poll(&mut self, dt: f32) -> bool {
+        self.since_check += dt;
+        if self.since_check < CHECK_INTERVAL {
+            return false;
+        }
+        self.since_check = 0.0;
+        let stamps = modified(&self.files);
+        if stamps == self.stamps {
+            return false;
+        }
+        self.stamps = stamps;
+        true
+    }
+}
+
+fn modified(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files.iter().map(|file| modified_at(file)).collect()
+}
+
+fn modified_at(file: &Path) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|meta| meta.modified()).ok()
+}
+
+/// What the reload had to do to fit the world onto the new map.
+#[derive(Debug, Default)]
+pub struct ReloadReport {
+    /// Agents moved off cells they can't stand on anymore.
+    pub relocated: usize,
+    /// Agents whose path went over a changed cell.
+    pub rerouted: usize,
+    /// Interactive objects whose tiles are gone.
+    pub removed: usize,
+}
+
+impl fmt::Display for ReloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} agents relocated, {} paths recalculated, {} interactive objects removed",
+            self.relocated, self.rerouted, self.removed
+        )
+    }
+}
+
+/// Puts `building` in place of the simulation's one while it keeps running.
+///
+/// Agents, items and the clock are kept. Agents on cells they can't stand on anymore go to
+/// the nearest free cell, paths over changed cells are found again and interactive objects
+/// that lost their tiles are forgotten together with whoever was assigned to them. Paths
+/// being searched and steps planned in time are dropped, agents ask and plan again.
+/// Decorations are not part of the simulation, the caller spawns them again.
+pub fn reload(sim: &mut Simulation, building: Building) -> ReloadReport {
+    sim.flush_commands();
+    let old = sim.reality.replace_building(building);
+    // searches and holds were made on the old cells
+    sim.reality.paths.clear();
+    sim.reality.timetable.clear();
+    simulation::for_each_actor(|_, sanity| sanity.mv.forget_schedule());
+    let new = &mut sim.reality.building;
+
+    // cells blocked by the player stay blocked wherever they still exist
+    for cell in old.floors().iter().flat_map(|floor| floor.map.iter()) {
+        if cell.status.occupied && new.pos_within_bounds(cell.position) {
+            new.occupy_ps(&cell.position);
+        }
+    }
+
+    let changed = changed_cells(&old, new);
+    let mut report = settle_agents(new, &changed);
+
+    let removed = respawn_map_objects(sim);
+    report.removed = removed.len();
+    release_removed(&removed);
+
+    simulation::update_communication(&mut sim.reality);
+    simulation::rebuild_persistence(&mut sim.reality);
+    report
+}
+
+// cells that walk differently on one building than on the other, on every floor of both
+fn changed_cells(old: &Building, new: &Building) -> HashSet<Ps> {
+    old.floors()
+        .iter()
+        .chain(new.floors())
+        .flat_map(|floor| floor.map.iter())
+        .map(|cell| cell.position)
+        .filter(|ps| old.changed_at(new, ps))
+        .collect()
+}
+
+/// Fits the agents onto `building` after the `changed` cells changed under them. Agents that
+/// can't stand where they are go to the nearest free cell, paths over changed cells are dropped.
+pub(crate) fn settle_agents(building: &mut Building, changed: &HashSet<Ps>) -> ReloadReport {
+    let mut report = ReloadReport::default();
+    let mut misplaced = HashSet::new();
+    // agents that can stay hold their cells first, so nobody is relocated onto them
+    simulation::for_each_actor(|entity, sanity| {
+        let ps = sanity.get_current_ps();
+        let stays = building.pos_within_bounds(ps) && {
+            let cell = building.get_pos(&ps);
+            cell.is_passable_for(sanity.walker, false)
+                && cell.status.occupant.is_none_or(|occupant| occupant == entity)
+        };
+        if !stays {
+            misplaced.insert(entity);
+            return;
+        }
+        building.move_occupant(entity, &ps, &ps);
+        let path = &sanity.mv.current_move_path;
+        let crosses_changed = path
+            .calculated_steps
+            .iter()
+            .chain(path.target.iter())
+            .any(|step| changed.contains(step));
+        if crosses_changed {
+            sanity.mv.invalidate_path();
+            report.rerouted += 1;
+        }
+    });
+    simulation::for_each_actor(|entity, sanity| {
+        if !misplaced.contains(&entity) {
+            return;
+        }
+        let ps = sanity.get_current_ps();
+        match building.nearest_free_ps(ps, sanity.walker) {
+            Some(free) => {
+                sanity.mv.relocate(free);
+                let from = if building.pos_within_bounds(ps) { ps } else { free };
+                building.move_occupant(entity, &from, &free);
+                report.relocated += 1;
+            }
+            None => println!("WARN: no free cell left for {:?} at {:?}", entity, ps),
+        }
+    });
+    report
+}
+
+// tile entities are spawned from the new building, the interactive objects that are still
+// there keep who uses them, the ones that are gone are returned by where they were used
+fn respawn_map_objects(sim: &mut Simulation) -> Vec<(Ps, InteractiveObjectHandle)> {
+    for (entity, _) in world().query::<&MapTile>().iter() {
+        commands().despawn(entity);
+    }
+    let old = std::mem::take(&mut *sim.reality.interactive.lock());
+    sim.registry.spawn_map_objects(&sim.reality.building);
+    sim.flush_commands();
+    sim.registry.initialize_map_objects(&mut sim.reality);
+
+    let mut interactive = sim.reality.interactive.lock();
+    let mut removed = Vec::new();
+    for (ps, handle) in old {
+        match interactive.get_mut(&ps) {
+            Some(kept) if kept.item_type == handle.item_type => {
+                kept.used_by = handle.used_by;
+                kept.assigned = handle.assigned;
+            }
+            _ => removed.push((ps, handle)),
+        }
+    }
+    removed.sort_by_key(|(ps, _)| (ps.floor, ps.x, ps.y));
+    removed
+}
+
+pub(crate) fn release_removed(removed: &[(Ps, InteractiveObjectHandle)]) {
+    let gone: HashSet<Ps> = removed.iter().map(|(ps, _)| *ps).collect();
+    let users: HashSet<Entity> = removed
+        .iter()
+        .filter_map(|(_, handle)| handle.used_by)
+        .collect();
+    for (_, worker) in world().query::<&mut OfficeWorker>().iter() {
+        let routine = &mut worker.sa.routine;
+        if routine.assigned_office.is_some_and(|ps| gone.contains(&ps)) {
+            routine.assigned_office = None;
+        }
+        if routine.assigned_bed.is_some_and(|ps| gone.contains(&ps)) {
+            routine.assigned_bed = None;
+        }
+    }
+    // whoever was using a removed object stops, there is nothing to release anymore
+    simulation::for_each_actor(|entity, sanity| {
+        if users.contains(&entity) {
+            sanity.reset_intentions();
+        }
+    });
+}
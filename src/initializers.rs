@@ -1,46 +1,78 @@
 use crate::{
     behavior::{
         carriable::carriableitem::CarriableItemHandle,
+        creatures::PsOffsetProvider,
         dog,
         item_types::{BONE, TRASHCAN},
         messaging::communication::Communicator,
     },
-    core::{animation::AdditionalAnimationDescr, position::Ps},
-    gameplay::ent::{officeworker::OfficeWorker, MapEntityObject},
+    building::{floor_of, Building, Floor},
+    core::{
+        animation::{AdditionalAnimationDescr, TileAnimation},
+        position::Ps,
+        rng::WorldRng,
+    },
+    gameplay::ent::{officeworker::OfficeWorker, MapEntityObject, MapTile},
     state::WorldState,
-    ui::statusbar::{self, Statusbar},
-    worldmap::{Cellmap, TileReference},
+    ui::statusbar::Statusbar,
+    worldmap::{Cellmap, ItemSpawner, TileReference},
     Bone, TrashCan, RES_I32,
 };
-use comfy::{num_traits::ToPrimitive, *};
+use comfy::*;
+
+/// Comfy animation playing a map tile, `image` is the tile's own image.
+pub fn tile_animation(name: &str, image: &str, animation: &TileAnimation) -> Animation {
+    match animation {
+        TileAnimation::Atlas(anim) => Animation {
+            name: name.to_owned(),
+            source: AnimationSource::Atlas {
+                name: image.to_owned().into(),
+                offset: ivec2(0, 0),
+                step: ivec2(RES_I32, 0),
+                size: isplat(RES_I32),
+                frames: anim.steps,
+            },
+            looping: true,
+            frame_time: anim.delay,
+        },
+        TileAnimation::Frames { key, .. } => {
+            // every slice is a texture of its own, named prefix + slice number
+            let (images, slice) = animation.slices();
+            let prefix = format!("{}#", key);
+            for (i, frame) in images.iter().enumerate() {
+                crate::lazy_load_texture_as(format!("{}{}", prefix, i), frame.to_string());
+            }
+            Animation {
+                name: name.to_owned(),
+                source: AnimationSource::Files {
+                    prefix: prefix.into(),
+                    frames: images.len() as i32,
+                },
+                looping: true,
+                frame_time: slice,
+            }
+        }
+    }
+}
 
 pub fn spawn_object_sprite<T, F>(
-    x: i32,
-    y: i32,
+    ps: Ps,
     tile: &TileReference,
     mut size: Vec2,
     name: String,
     spawner: F,
     animations: Vec<AdditionalAnimationDescr>,
 ) where
-    F: Fn() -> T,
+    F: FnOnce() -> T,
     T: MapEntityObject + 'static,
 {
-    match tile.animated {
+    let (x, y) = (ps.x, ps.y);
+    let floor = ps.floor;
+    match &tile.animated {
         Some(anim) => {
-            size.x = size.x / anim.steps as f32;
-            let mut builder = AnimatedSpriteBuilder::new().add_animation(
-                "base",
-                anim.delay,
-                true,
-                AnimationSource::Atlas {
-                    name: name.to_owned().into(),
-                    offset: ivec2(0, 0),
-                    step: ivec2(RES_I32, 0),
-                    size: isplat(RES_I32),
-                    frames: anim.steps.clone(),
-                },
-            );
+            size.x /= anim.frames_in_image() as f32;
+            let mut builder =
+                AnimatedSpriteBuilder::new().add_anim(tile_animation("base", &name, anim));
             for other_anim in animations.iter() {
                 builder = builder.add_animation(
                     &other_anim.animation_name,
@@ -69,6 +101,8 @@ pub fn spawn_object_sprite<T, F>(
                 Transform::position(
                     vec2(x as f32, y as f32) + ((size - vec2(1.0, 1.0)) / vec2(2.0, 2.0)),
                 ),
+                Floor(floor),
+                MapTile(ps),
                 spawner(),
             ));
         }
@@ -83,6 +117,8 @@ pub fn spawn_object_sprite<T, F>(
                 Transform::position(
                     vec2(x as f32, y as f32) + ((size - vec2(1.0, 1.0)) / vec2(2.0, 2.0)),
                 ),
+                Floor(floor),
+                MapTile(ps),
                 spawner(),
             ));
         }
@@ -91,34 +127,69 @@ pub fn spawn_object_sprite<T, F>(
 
 pub fn initialize_bones(state: &mut WorldState, _c: &mut EngineContext) {
     println!("Initializing bones...");
-    let mut carriables = state.reality.carriables.lock();
+    let mut carriables = state.sim.reality.carriables.lock();
+    let mut rng = state.sim.reality.rng.lock();
     for (entity, (_bone, transform)) in world().query::<(&mut Bone, &mut Transform)>().iter() {
-        let handle = CarriableItemHandle::new(BONE, entity, transform.position.into());
+        let ps = Ps::from(transform.position).with_floor(floor_of(entity));
+        let handle = CarriableItemHandle::new(BONE, entity, ps, &mut rng);
         println!("Bone {:?}: {:?}", entity, handle);
         carriables.insert(entity, handle);
     }
 }
 
-pub fn create_bones(count: usize, map: &Cellmap) {
+pub fn bone_components(
+    position: Vec2,
+    floor: usize,
+    bone: Bone,
+) -> (Sprite, Transform, Floor, Bone) {
+    (
+        Sprite::new("bone.png", vec2(1.0, 1.0), 10, WHITE).with_rect(0, 0, RES_I32, RES_I32),
+        Transform::position(position),
+        Floor(floor),
+        bone,
+    )
+}
+
+pub fn trashcan_components(
+    position: Vec2,
+    floor: usize,
+    trashcan: TrashCan,
+) -> (Sprite, Transform, Floor, TrashCan) {
+    (
+        Sprite::new("trash_can48.png", vec2(1.0, 1.0), 10, WHITE).with_rect(0, 0, RES_I32, RES_I32),
+        Transform::position(position),
+        Floor(floor),
+        trashcan,
+    )
+}
+
+pub fn create_bones(count: usize, building: &Building, rng: &mut WorldRng) {
     for _ in 0..count {
-        let ps = map.pick_random_passable_ps();
-        commands().spawn((
-            Sprite::new("bone.png", vec2(1.0, 1.0), 10, WHITE).with_rect(0, 0, RES_I32, RES_I32),
-            Transform::position(ps.into()),
-            Bone { initialized: false },
-        ));
+        let ps = building.pick_random_passable_ps(rng);
+        let bone = Bone { initialized: false };
+        commands().spawn(bone_components(ps.into(), ps.floor, bone));
     }
 }
 
-pub fn create_trashcans(count: usize, map: &Cellmap) {
+pub fn create_trashcans(count: usize, building: &Building, rng: &mut WorldRng) {
     for _ in 0..count {
-        let ps = map.pick_random_passable_ps();
-        commands().spawn((
-            Sprite::new("trash_can48.png", vec2(1.0, 1.0), 10, WHITE)
-                .with_rect(0, 0, RES_I32, RES_I32),
-            Transform::position(ps.into()),
-            TrashCan { initialized: false },
-        ));
+        let ps = building.pick_random_passable_ps(rng);
+        let trashcan = TrashCan { initialized: false };
+        commands().spawn(trashcan_components(ps.into(), ps.floor, trashcan));
+    }
+}
+
+/// Items of a map item spawner, on random passable cells around it on its floor.
+pub fn spawn_items(spawner: &ItemSpawner, cellmap: &Cellmap, rng: &mut WorldRng) {
+    for _ in 0..spawner.count {
+        let ps = cellmap.pick_passable_ps_near(spawner.ps, spawner.radius, rng);
+        if spawner.item_type == BONE {
+            let bone = Bone { initialized: false };
+            commands().spawn(bone_components(ps.into(), ps.floor, bone));
+        } else if spawner.item_type == TRASHCAN {
+            let trashcan = TrashCan { initialized: false };
+            commands().spawn(trashcan_components(ps.into(), ps.floor, trashcan));
+        }
     }
 }
 
@@ -128,48 +199,79 @@ pub fn initialize_trashcan(
     transform: &Transform,
     entity: Entity,
 ) {
-    let mut carriables = state.reality.carriables.lock();
-    let handle = CarriableItemHandle::new(TRASHCAN, entity, transform.position.into());
+    let mut carriables = state.sim.reality.carriables.lock();
+    let handle = CarriableItemHandle::new(
+        TRASHCAN,
+        entity,
+        Ps::from(transform.position).with_floor(floor_of(entity)),
+        &mut state.sim.reality.rng.lock(),
+    );
     println!("Initialized Trashcan {:?}: {:?}", entity, handle);
     carriables.insert(entity, handle);
     o.initialized = true;
 }
 
-pub fn spawn_dogs(count: isize, cellmap: &Cellmap, x_limit: usize, y_limit: usize) {
+pub fn spawn_dogs(
+    count: isize,
+    cellmap: &Cellmap,
+    x_limit: usize,
+    y_limit: usize,
+    rng: &mut WorldRng,
+) {
     // println!("Spawning {} dogs...", count);
     for i in 1..=count {
         let mut regenerate = true;
         while regenerate {
-            let x = usize::gen_range(0, x_limit);
-            let y = usize::gen_range(0, y_limit);
+            let x = rng.gen_range(0, x_limit);
+            let y = rng.gen_range(0, y_limit);
             if cellmap.get_xy(x, y).is_passable(true) {
                 regenerate = false;
-                spawn_dog(format!("Dog {}", i), x, y);
+                spawn_dog(format!("Dog {}", i), (x, y).into(), None, rng);
             }
         }
     }
 }
 
-pub fn spawn_workers(count: isize, cellmap: &Cellmap, x_limit: usize, y_limit: usize) {
+pub fn spawn_workers(
+    count: isize,
+    cellmap: &Cellmap,
+    x_limit: usize,
+    y_limit: usize,
+    rng: &mut WorldRng,
+) {
     println!("Spawning {} workers...", count);
     for i in 1..=count {
         let mut regenerate = true;
         while regenerate {
-            let x = usize::gen_range(0, x_limit);
-            let y = usize::gen_range(0, y_limit);
+            let x = rng.gen_range(0, x_limit);
+            let y = rng.gen_range(0, y_limit);
             if cellmap.get_xy(x, y).is_passable(true) {
                 regenerate = false;
-                spawn_worker(format!("Worker {}", i), x, y);
+                spawn_worker(format!("Worker {}", i), (x, y).into(), None, rng);
             }
         }
     }
 }
 
-pub fn spawn_dog(name: String, x: usize, y: usize) {
-    println!("++ {:?} (x: {}, y: {})", name, x, y);
+/// Random speed unless `speed` is given.
+pub fn spawn_dog(name: String, ps: Ps, speed: Option<f32>, rng: &mut WorldRng) {
+    println!("++ {:?} {:?}", name, ps);
+    let speed = speed.unwrap_or_else(|| rng.gen_range(3.0, 10.0));
+    let dog = dog::Dog::new(name.to_string(), speed, ps, rng);
+    commands().spawn(dog_components(dog));
+    // commands().spawn((
+    //     Sprite::new("dog48.png", vec2(1.0, 1.0), 1, WHITE).with_rect(0, 0, RES_I32, RES_I32),
+    //     Transform::position(vec2(x.to_f32().unwrap(), y.to_f32().unwrap())),
+    //     dog::Dog::new(name.to_string(), f32::gen_range(3.0, 10.0), (x, y).into()),
+    // ));
+}
+
+pub fn dog_components(dog: dog::Dog) -> (AnimatedSprite, Transform, Floor, dog::Dog, Communicator) {
     crate::lazy_load_texture("dog48_idle.png".into());
     crate::lazy_load_texture("dog48_idle_reversed.png".into());
-    commands().spawn((
+    let position = dog.get_exact_pos();
+    let communicator = Communicator::new(dog.get_ps());
+    (
         AnimatedSpriteBuilder::new()
             .z_index(1)
             .add_animation(
@@ -197,32 +299,44 @@ pub fn spawn_dog(name: String, x: usize, y: usize) {
                 },
             )
             .build(),
-        Transform::position(vec2(x as f32, y as f32)),
-        dog::Dog::new(name.to_string(), f32::gen_range(3.0, 10.0), (x, y).into()),
-        Communicator::new(Ps { x, y }),
-    ));
-    // commands().spawn((
-    //     Sprite::new("dog48.png", vec2(1.0, 1.0), 1, WHITE).with_rect(0, 0, RES_I32, RES_I32),
-    //     Transform::position(vec2(x.to_f32().unwrap(), y.to_f32().unwrap())),
-    //     dog::Dog::new(name.to_string(), f32::gen_range(3.0, 10.0), (x, y).into()),
-    // ));
+        Transform::position(position),
+        Floor(dog.get_ps().floor),
+        dog,
+        communicator,
+    )
 }
 
-pub fn spawn_worker(name: String, x: usize, y: usize) {
-    println!("WORKER {:?} (x: {}, y: {})", name, x, y);
+/// Random speed unless `speed` is given.
+pub fn spawn_worker(name: String, ps: Ps, speed: Option<f32>, rng: &mut WorldRng) {
+    println!("WORKER {:?} {:?}", name, ps);
 
+    let speed = speed.unwrap_or_else(|| rng.gen_range(3.0, 10.0));
+    let worker = OfficeWorker::new(name.to_string(), speed, ps, rng);
+    commands().spawn(worker_components(worker));
+}
+
+pub fn worker_components(
+    worker: OfficeWorker,
+) -> (
+    Sprite,
+    Transform,
+    Floor,
+    OfficeWorker,
+    Statusbar,
+    Communicator,
+) {
     crate::lazy_load_texture("human/human_base.png".into());
     let sprite = Sprite::new("human/human_base.png", vec2(1.0, 1.0), 11, WHITE)
         .with_rect(0, 0, RES_I32, RES_I32);
-    let worker = OfficeWorker::new(name.to_string(), f32::gen_range(3.0, 10.0), (x, y).into());
     worker.look.lazy_load_sprites();
-    let statusbar = Statusbar::new();
-    let communicator = Communicator::new(Ps { x, y });
-    commands().spawn((
+    let position = worker.sa.get_exact_pos();
+    let communicator = Communicator::new(worker.sa.get_ps());
+    (
         sprite,
-        Transform::position(vec2(x.to_f32().unwrap(), y.to_f32().unwrap())),
+        Transform::position(position),
+        Floor(worker.sa.get_ps().floor),
         worker,
-        statusbar,
+        Statusbar::new(),
         communicator,
-    ));
+    )
 }
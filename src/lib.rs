@@ -0,0 +1,427 @@
+pub mod behavior;
+pub mod building;
+pub mod editor;
+pub mod flowfield;
+pub mod gameplay;
+pub mod generator;
+pub mod hierarchy;
+pub mod hotreload;
+pub mod initializers;
+pub mod pathqueue;
+pub mod persistence;
+pub mod registry;
+pub mod replay;
+pub mod reservation;
+pub mod savegame;
+pub mod scenario;
+pub mod search;
+pub mod simulation;
+pub mod spacetime;
+pub mod state;
+pub mod tiledreader;
+pub mod tiledwriter;
+mod updaters;
+pub mod utils;
+pub mod worldmap;
+mod ui;
+
+pub mod core;
+
+use building::{floor_of, Building, Floor};
+use core::{anycellmap::AnyCellmap, position::Ps, Initializable};
+use std::{
+    fs::File,
+    io::Read,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+};
+
+use comfy::*;
+use editor::MapEditor;
+use state::{Reality, WorldState};
+use hotreload::MapWatcher;
+use savegame::SaveError;
+use scenario::Scenario;
+use simulation::Simulation;
+use tiledreader::*;
+use persistence::CARRIABLE;
+use updaters::GLOBAL_HEATMAP;
+
+use crate::behavior::carriable::carriableitem::CarriableItemHandle;
+
+pub const RES_I32: i32 = 48;
+
+lazy_static::lazy_static! {
+    // texture name -> image file in assets/
+    static ref TEXTURES_TO_LOAD: Arc<Mutex<HashMap<String, String>>> = Arc::new(Mutex::new(HashMap::new()));
+}
+
+pub fn lazy_load_texture(texture: String) {
+    let mut textures = TEXTURES_TO_LOAD.lock();
+    textures.insert(texture.clone(), texture);
+}
+
+/// Loads the image `file` as the texture `name`, for animations that need a texture per frame.
+pub fn lazy_load_texture_as(name: String, file: String) {
+    let mut textures = TEXTURES_TO_LOAD.lock();
+    textures.insert(name, file);
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Player;
+#[derive(Debug)]
+struct Bg;
+#[derive(Debug)]
+struct Fg;
+
+#[derive(Debug)]
+struct Selection;
+
+#[derive(Debug)]
+pub struct Bone {
+    initialized: bool,
+}
+
+impl Initializable for Bone {
+    fn initialize(&mut self, entity: &Entity, transform: &mut Transform, reality: &mut Reality) {
+        let mut carriables = reality.carriables.lock();
+        let handle = CarriableItemHandle::new(
+            behavior::item_types::BONE,
+            *entity,
+            Ps::from(transform.position).with_floor(floor_of(*entity)),
+            &mut reality.rng.lock(),
+        );
+        reality.persistence.lock().register(*entity, handle.item_type, CARRIABLE, &handle);
+        carriables.insert(*entity, handle);
+        self.initialized = true;
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+}
+
+#[derive(Debug)]
+pub struct TrashCan {
+    initialized: bool,
+}
+
+impl Initializable for TrashCan {
+    fn initialize(&mut self, entity: &Entity, transform: &mut Transform, reality: &mut Reality) {
+        let mut carriables = reality.carriables.lock();
+        let handle = behavior::carriable::carriableitem::CarriableItemHandle::new(
+            behavior::item_types::TRASHCAN,
+            *entity,
+            Ps::from(transform.position).with_floor(floor_of(*entity)),
+            &mut reality.rng.lock(),
+        );
+        println!("Initialized Trashcan {:?}: {:?}", entity, handle);
+        reality.persistence.lock().register(*entity, handle.item_type, CARRIABLE, &handle);
+        carriables.insert(*entity, handle);
+        self.initialized = true;
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+}
+
+impl GameLoop for WorldState {
+    fn new(_c: &mut EngineState) -> Self {
+        // begin
+
+        let scenario = Scenario::from_args().unwrap_or_else(|e| {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(2);
+        });
+        println!("Scenario: {} ({})", scenario.name, scenario.map_name());
+        let (decor, building) = scenario.load_building().unwrap_or_else(|e| {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(2);
+        });
+        set_y_sort(0, true);
+
+        let fixed_seed = scenario.seed.or_else(core::rng::WorldRng::env_seed);
+        let seed = fixed_seed.unwrap_or_else(|| core::rng::WorldRng::from_entropy().seed());
+        println!("World seed: {} (set {} to replay it)", seed, core::rng::SEED_ENV);
+        let mut world = Self {
+            sim: Simulation::from_scenario(&scenario, building, seed),
+            map_watcher: MapWatcher::new(&scenario.maps()),
+            scenario,
+            clock: simulation::FixedTimestep::new(),
+            replay_start: None,
+            step_requested: false,
+            x: 1,
+            y: 1,
+            initialized: false,
+            selected_cell: (100500, 100500).into(),
+            selected: false,
+            floor: 0,
+            entities_initialized: false,
+            paused: false,
+            editor: MapEditor::new(),
+            decor: Vec::new(),
+        };
+
+        spawn_scene(&decor, &world.sim.reality.building);
+        world.decor = decor;
+
+        // spawn_dog("Jumpy".to_string(), 6, 6);
+        // spawn_dog("Lasy".to_string(), 8, 6);
+        // spawn_dog("Kord".to_string(), 5, 4);
+        // spawn_dog("Donald".to_string(), 32, 3);
+        // spawn_dog("Jetty".to_string(), 6, 2);
+        world
+    }
+
+    fn update(&mut self, c: &mut EngineContext) {
+        if !self.initialized {
+            setup(c, &self.sim.reality.building);
+            initializers::initialize_bones(self, c);
+            self.initialized = true;
+            return;
+        }
+
+        let dt = c.delta;
+
+        if self.paused {
+            if self.step_requested {
+                step_simulation(self);
+            }
+        } else {
+            for _ in 0..self.clock.advance(dt) {
+                step_simulation(self);
+            }
+        }
+        self.step_requested = false;
+
+        updaters::update_conputers(self, c, dt);
+        updaters::update_dogs(self, c, dt);
+        updaters::update_actors(self);
+        updaters::update_camera(self, c, dt);
+        updaters::update_savegame(self, c);
+        updaters::update_map_reload(self, c, dt);
+        updaters::update_replay(self);
+        updaters::update_editor(self);
+        updaters::update_selection(self, c, dt);
+        // updaters::update_heatmap(self, c, dt);
+        updaters::update_time(self, c, dt);
+        updaters::update_human_looks();
+        updaters::update_statusbars(self);
+        updaters::update_floor(self);
+    }
+}
+
+// one tick of the world, a panic leaves a replay of the session behind before taking the game down
+fn step_simulation(state: &mut WorldState) {
+    if state.replay_start.is_none() {
+        state.replay_start = Some(state.sim.start_recording());
+    }
+    let stepped = panic::catch_unwind(AssertUnwindSafe(|| state.sim.step(simulation::TICK_DT)));
+    if let Err(cause) = stepped {
+        let path = Path::new(updaters::CRASH_REPLAY_PATH);
+        updaters::save_replay(state, path, state.sim.tick + 1);
+        panic::resume_unwind(cause);
+    }
+}
+
+// everything in the window that is not part of the simulation: map decorations, camera and selection
+fn spawn_scene(decor: &[AnyCellmap<DecorTile>], building: &Building) {
+    let cellmap = building.ground();
+    let mut heatmap = GLOBAL_HEATMAP.lock();
+    heatmap.reset_and_resize(
+        0.0,
+        cellmap.wh_i32().0,
+        cellmap.wh_i32().1,
+    );
+
+    for (floor, decor) in decor.iter().enumerate() {
+        spawn_floor_decor(decor, floor);
+    }
+
+    let (w, h) = cellmap.wh_usize();
+    let fw = w as f32;
+    let fh = h as f32;
+    commands().spawn((Transform::position(vec2(fw / 2.0, fh / 2.0)), Player));
+
+    commands().spawn((
+        Sprite::new("selectionhd.png", vec2(1.0, 1.0), 10, WHITE)
+            .with_rect(0, 0, RES_I32, RES_I32),
+        Transform::position(vec2(fw / 2.0, fh / 2.0)),
+        Selection,
+    ));
+}
+
+fn spawn_floor_decor(decor: &AnyCellmap<DecorTile>, floor: usize) {
+    let (max_x, max_y) = decor.wh_usize();
+
+    for x in 0..max_x {
+        for y in 0..max_y {
+            let decor_cell = decor.get_xy(x, y);
+            if let Some(bg) = &decor_cell.bg {
+                lazy_load_texture(bg.to_owned());
+                match &decor_cell.animated_bg {
+                    Some(anim) => {
+                        let mut builder = AnimatedSpriteBuilder::new()
+                            .add_anim(initializers::tile_animation("base", bg, anim));
+                        builder.z_index = -1;
+                        let mut animated = builder.build();
+                        animated.play("base");
+                        commands().spawn((
+                            animated,
+                            Transform::position(vec2(x as f32, y as f32)),
+                            Bg,
+                            Floor(floor),
+                        ));
+                    }
+                    None => {
+                        commands().spawn((
+                            Sprite::new(bg.to_owned(), vec2(1.0, 1.0), -1, WHITE)
+                                .with_rect(0, 0, RES_I32, RES_I32),
+                            Transform::position(vec2(x as f32, y as f32)),
+                            Bg,
+                            Floor(floor),
+                        ));
+                    }
+                }
+            }
+            if let Some(fg) = &decor_cell.top {
+                lazy_load_texture(fg.to_owned());
+                match &decor_cell.animated_top {
+                    Some(anim) => {
+                        let mut builder = AnimatedSpriteBuilder::new()
+                            .add_anim(initializers::tile_animation("base", fg, anim));
+                        builder.z_index = 100;
+                        let mut animated = builder.build();
+                        animated.play("base");
+                        commands().spawn((
+                            animated,
+                            Transform::position(vec2(x as f32, y as f32)),
+                            Fg,
+                            Floor(floor),
+                        ));
+                    }
+                    None => {
+                        commands().spawn((
+                            Sprite::new(fg.to_owned(), vec2(1.0, 1.0), 100, WHITE)
+                                .with_rect(0, 0, RES_I32, RES_I32),
+                            Transform::position(vec2(x as f32, y as f32)),
+                            Fg,
+                            Floor(floor),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Replaces the running world with a saved one, the map is read again from disk.
+fn load_world(state: &mut WorldState, c: &mut EngineContext, path: &Path) -> Result<(), SaveError> {
+    let (decor, building) = state.scenario.load_building()?;
+    state.sim = savegame::load(path, building)?;
+    // the replay starts over from the loaded world
+    state.replay_start = Some(state.sim.start_recording());
+    spawn_scene(&decor, &state.sim.reality.building);
+    state.decor = decor;
+    state.deselect_cell();
+    state.floor = 0;
+    state.clock.reset();
+    // restored workers may wear clothes nobody had before
+    setup(c, &state.sim.reality.building);
+    Ok(())
+}
+
+fn load_sprite(c: &mut EngineContext, sprite_name: &str) {
+    load_sprite_as(c, sprite_name, sprite_name);
+}
+
+fn load_sprite_as(c: &mut EngineContext, name: &str, file: &str) {
+    let path = env!("CARGO_MANIFEST_DIR").to_owned() + &format!("/assets/{}", { file });
+    load_texture(c, name, &path);
+}
+
+fn load_file(path: &str) -> Option<Vec<u8>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error opening file {}: {}", path, e);
+            return None;
+        }
+    };
+
+    let mut buffer = Vec::new();
+    match file.read_to_end(&mut buffer) {
+        Ok(_) => {
+            return Some(buffer);
+        }
+        Err(e) => {
+            eprintln!("Error reading file {}: {}", path, e);
+            return None;
+        }
+    }
+}
+
+fn load_texture(c: &mut EngineContext, name: &str, path: &str) {
+    let data = load_file(path).unwrap();
+    c.load_texture_from_bytes(
+        // Name of our sprite
+        name, // &[u8] with the image data.
+        &data,
+    );
+}
+
+/// Swaps in the map as it is on disk now, the world living on it keeps going.
+fn reload_map(state: &mut WorldState, c: &mut EngineContext) -> Result<(), MapLoadError> {
+    let (decor, building) = state.scenario.load_building()?;
+    let report = hotreload::reload(&mut state.sim, building);
+    println!("Map reloaded: {}", report);
+
+    for (entity, _) in world().query::<&Bg>().iter() {
+        commands().despawn(entity);
+    }
+    for (entity, _) in world().query::<&Fg>().iter() {
+        commands().despawn(entity);
+    }
+    for (floor, decor) in decor.iter().enumerate() {
+        spawn_floor_decor(decor, floor);
+    }
+    state.decor = decor;
+
+    let building = &state.sim.reality.building;
+    state.floor = state.floor.min(building.floor_count() - 1);
+    state.deselect_cell();
+    let (w, h) = state.shown_floor().wh_i32();
+    GLOBAL_HEATMAP.lock().reset_and_resize(0.0, w, h);
+    // a replay is played on the map as it is on disk, so it starts over from here
+    state.replay_start = Some(state.sim.start_recording());
+    // new tiles may need textures nobody had before
+    setup(c, &state.sim.reality.building);
+    Ok(())
+}
+
+fn setup(c: &mut EngineContext, building: &Building) {
+    const SPRITES: [&str; 5] = ["bone", "wat", "trash_can48", "selectionhd", "dog48"];
+
+    let mut sprites: HashSet<String> = building
+        .floors()
+        .iter()
+        .flat_map(|floor| floor.map.iter())
+        .filter_map(|f| f.get_tile_name())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+
+    sprites.extend(SPRITES.into_iter().map(|f| f.to_string() + ".png"));
+    let textures = TEXTURES_TO_LOAD.lock();
+    sprites.retain(|s| !textures.contains_key(s));
+
+    for s in sprites.iter() {
+        println!("Loading sprite {s}");
+        load_sprite(c, s);
+    }
+    for (name, file) in textures.iter() {
+        println!("Loading sprite {name}");
+        load_sprite_as(c, name, file);
+    }
+}
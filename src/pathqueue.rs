@@ -0,0 +1,391 @@
+// pathqueue takes route searches out of thinking: agents ask for a path and wait for it,
+// after every tick the queue searches for the oldest requests until the budget is spent,
+// a search that doesn't finish goes on from where it stopped the next tick
+
+use std::collections::LinkedList;
+
+use comfy::{world, Arc, Entity, HashMap, Mutex};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    behavior::routing::{AroundSearch, RouteSearch},
+    building::Building,
+    core::{position::Ps, rng::WorldRng},
+    flowfield::{FieldBuild, UNREACHABLE},
+    search::{Budget, Progress},
+    worldmap::Walker,
+};
+
+/// Nodes searches may expand per tick, all searches together.
+///
+/// Work is counted instead of timed, so the same requests get answered on the same tick on
+/// every run.
+pub const PATH_BUDGET: usize = 4000;
+
+/// Nodes an agent searches on its own before it asks the queue.
+pub const QUICK_BUDGET: usize = 64;
+
+/// How close to the destination the path has to end.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PathKind {
+    /// Onto the destination.
+    Exact,
+    /// Onto the destination or right next to it.
+    Around,
+    /// Down the flow field of the destination, the request only has the field found.
+    Shared,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathRequest {
+    #[serde(with = "crate::core::serialization::entity")]
+    pub entity: Entity,
+    pub walker: Walker,
+    pub start: Ps,
+    pub destination: Ps,
+    pub kind: PathKind,
+    // tick the request was made on, older requests are answered first
+    tick: u64,
+    // the agent's own stream, so searching for others first doesn't change the path
+    rng: WorldRng,
+    // what was searched so far, nothing before the first run
+    job: Option<Job>,
+}
+
+// search behind a request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Job {
+    Exact(RouteSearch),
+    Around(AroundSearch),
+    Shared(FieldBuild),
+}
+
+impl PathRequest {
+    pub fn new(
+        entity: Entity,
+        walker: Walker,
+        start: Ps,
+        destination: Ps,
+        kind: PathKind,
+        rng: WorldRng,
+    ) -> Self {
+        Self {
+            entity,
+            walker,
+            start,
+            destination,
+            kind,
+            tick: 0,
+            rng,
+            job: None,
+        }
+    }
+
+    /// Searches on until the path is found or the budget is spent.
+    pub fn search(&mut self, building: &Building, budget: &Budget) -> Progress<PathResult> {
+        let (walker, start, destination) = (self.walker, self.start, self.destination);
+        let fields = building.flow_fields();
+        if self.kind == PathKind::Shared {
+            // somebody else going there may have found the field meanwhile
+            if let Some(field) = fields.cached(building, walker, destination) {
+                return Progress::Done(self.answer(shared_path(field.cost(&start), destination)));
+            }
+            if !building.pos_within_bounds(destination)
+                || !building.get_pos(&destination).is_passable_for(walker, false)
+            {
+                return Progress::Done(self.answer(None));
+            }
+        }
+        let job = self.job.get_or_insert_with(|| match self.kind {
+            PathKind::Exact => Job::Exact(RouteSearch::new(building, start, destination, true)),
+            PathKind::Around => Job::Around(AroundSearch::new(building, start, destination)),
+            PathKind::Shared => Job::Shared(FieldBuild::new(building, destination)),
+        });
+        let found = match job {
+            Job::Exact(search) => search
+                .run(building, walker, &mut self.rng, budget)
+                .map(|found| found.map(|steps| (destination, steps))),
+            Job::Around(search) => search.run(building, walker, &mut self.rng, budget),
+            Job::Shared(build) => build.run(building, walker, budget).map(|field| {
+                let cost = field.cost(&start);
+                fields.insert(walker, Arc::new(field));
+                shared_path(cost, destination)
+            }),
+        };
+        found.map(|found| self.answer(found))
+    }
+
+    // goes on over `building` after a load, whatever was searched so far was searched on it
+    fn adopt(&mut self, building: &Building) {
+        if let Some(Job::Shared(build)) = &mut self.job {
+            build.adopt(building);
+        }
+    }
+
+    fn answer(&self, found: Option<(Ps, LinkedList<Ps>)>) -> PathResult {
+        PathResult {
+            entity: self.entity,
+            start: self.start,
+            destination: self.destination,
+            kind: self.kind,
+            found,
+        }
+    }
+}
+
+// shared destinations come without steps, they are read off the field
+fn shared_path(cost: u32, destination: Ps) -> Option<(Ps, LinkedList<Ps>)> {
+    (cost != UNREACHABLE).then(|| (destination, LinkedList::new()))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathResult {
+    #[serde(with = "crate::core::serialization::entity")]
+    pub entity: Entity,
+    pub start: Ps,
+    pub destination: Ps,
+    pub kind: PathKind,
+    /// Cell the path ends on with the steps there, `None` if it can't be reached. Shared
+    /// destinations come without steps, they are read off the field.
+    pub found: Option<(Ps, LinkedList<Ps>)>,
+}
+
+pub enum PathStatus {
+    /// Not answered yet.
+    Pending,
+    Ready(PathResult),
+    /// Nothing was asked, or the answer was already taken.
+    Unknown,
+}
+
+/// Path requests of every agent, one at a time per agent.
+#[derive(Debug)]
+pub struct PathQueue {
+    pub budget: usize,
+    requests: Mutex<Requests>,
+}
+
+#[derive(Debug, Default)]
+struct Requests {
+    // tick new requests are stamped with
+    tick: u64,
+    pending: HashMap<Entity, PathRequest>,
+    ready: HashMap<Entity, PathResult>,
+}
+
+impl PathQueue {
+    pub fn new() -> Self {
+        Self {
+            budget: PATH_BUDGET,
+            requests: Mutex::new(Requests::default()),
+        }
+    }
+
+    /// Asks for a path, whatever the agent asked for before is cancelled.
+    pub fn submit(&self, mut request: PathRequest) {
+        let mut requests = self.requests.lock();
+        request.tick = requests.tick;
+        requests.ready.remove(&request.entity);
+        requests.pending.insert(request.entity, request);
+    }
+
+    pub fn cancel(&self, entity: Entity) {
+        let mut requests = self.requests.lock();
+        requests.pending.remove(&entity);
+        requests.ready.remove(&entity);
+    }
+
+    /// Cancels what everybody asked for.
+    pub fn clear(&self) {
+        let mut requests = self.requests.lock();
+        requests.pending.clear();
+        requests.ready.clear();
+    }
+
+    /// Takes the answer for `entity` if there is one.
+    pub fn poll(&self, entity: Entity) -> PathStatus {
+        let mut requests = self.requests.lock();
+        if let Some(result) = requests.ready.remove(&entity) {
+            PathStatus::Ready(result)
+        } else if requests.pending.contains_key(&entity) {
+            PathStatus::Pending
+        } else {
+            PathStatus::Unknown
+        }
+    }
+
+    /// Searches for the oldest requests one after another until the budget is spent, the one
+    /// it runs out on goes on next time. `tick` is the one that just ended.
+    pub fn process(&self, building: &Building, tick: u64) {
+        let mut queued: Vec<PathRequest> = {
+            let mut requests = self.requests.lock();
+            requests.tick = tick + 1;
+            // nobody comes for the paths of despawned agents
+            let wrld = world();
+            requests.pending.retain(|entity, _| wrld.contains(*entity));
+            requests.ready.retain(|entity, _| wrld.contains(*entity));
+            requests.pending.drain().map(|(_, request)| request).collect()
+        };
+        queued.sort_by_key(|request| (request.tick, request.entity));
+        let budget = Budget::new(self.budget);
+        let mut answered = Vec::new();
+        let mut left = Vec::new();
+        for mut request in queued {
+            if budget.is_spent() {
+                left.push(request);
+                continue;
+            }
+            match request.search(building, &budget) {
+                Progress::Done(result) => answered.push(result),
+                Progress::Paused => left.push(request),
+            }
+        }
+        let mut requests = self.requests.lock();
+        for request in left {
+            requests.pending.insert(request.entity, request);
+        }
+        for result in answered {
+            requests.ready.insert(result.entity, result);
+        }
+    }
+
+    /// Requests and answers in a stable order, for saving.
+    pub fn saved(&self) -> (Vec<PathRequest>, Vec<PathResult>) {
+        let requests = self.requests.lock();
+        let mut pending: Vec<PathRequest> = requests.pending.values().cloned().collect();
+        pending.sort_by_key(|request| request.entity);
+        let mut ready: Vec<PathResult> = requests.ready.values().cloned().collect();
+        ready.sort_by_key(|result| result.entity);
+        (pending, ready)
+    }
+
+    /// Puts saved requests and answers back, `tick` is the one the simulation goes on from and
+    /// `building` the one they were saved with.
+    pub fn restore(
+        &self,
+        building: &Building,
+        pending: Vec<PathRequest>,
+        ready: Vec<PathResult>,
+        tick: u64,
+    ) {
+        let mut requests = self.requests.lock();
+        requests.tick = tick;
+        for mut request in pending {
+            request.adopt(building);
+            requests.pending.insert(request.entity, request);
+        }
+        for result in ready {
+            requests.ready.insert(result.entity, result);
+        }
+    }
+}
+
+impl Default for PathQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use comfy::world_mut;
+
+    use super::*;
+    use crate::building::testing::ps;
+
+    // the queue looks agents up in the world everybody shares
+    static WORLD: Mutex<()> = Mutex::new(());
+
+    fn hall() -> Building {
+        Building::from_rows(&["................", "................", "................"])
+    }
+
+    fn request(entity: Entity, start: Ps, destination: Ps) -> PathRequest {
+        PathRequest::new(
+            entity,
+            Walker::Dog,
+            start,
+            destination,
+            PathKind::Exact,
+            WorldRng::new(7),
+        )
+    }
+
+    // nodes the request expands before it is answered
+    fn nodes(building: &Building, request: &PathRequest) -> usize {
+        let budget = Budget::unlimited();
+        let _ = request.clone().search(building, &budget).finished();
+        budget.spent()
+    }
+
+    #[test]
+    fn answers_once_the_budget_covered_the_search() {
+        let _world = WORLD.lock();
+        let building = hall();
+        let entity = world_mut().spawn((0u8,));
+        let asked = request(entity, ps(0, 0), ps(15, 2));
+        let nodes = nodes(&building, &asked);
+        assert!(nodes > 4);
+
+        let mut queue = PathQueue::new();
+        queue.budget = 4;
+        queue.submit(asked);
+        let ticks = (nodes / queue.budget + 1) as u64;
+        for tick in 0..ticks - 1 {
+            queue.process(&building, tick);
+            assert!(
+                matches!(queue.poll(entity), PathStatus::Pending),
+                "tick {}",
+                tick
+            );
+        }
+        queue.process(&building, ticks - 1);
+        let PathStatus::Ready(result) = queue.poll(entity) else {
+            panic!("not answered after {} ticks", ticks);
+        };
+        let (end, steps) = result.found.expect("the hall is open");
+        assert_eq!(end, ps(15, 2));
+        assert_eq!(steps.back(), Some(&ps(15, 2)));
+        world_mut().despawn(entity).unwrap();
+    }
+
+    #[test]
+    fn oldest_requests_go_first() {
+        let _world = WORLD.lock();
+        let building = hall();
+        // the younger request comes from the agent spawned first, entities don't decide
+        let young = world_mut().spawn((0u8,));
+        let old = world_mut().spawn((0u8,));
+        let old_request = request(old, ps(0, 0), ps(15, 2));
+        let young_request = request(young, ps(0, 2), ps(15, 0));
+
+        let mut queue = PathQueue::new();
+        queue.budget = 0;
+        queue.submit(old_request.clone());
+        queue.process(&building, 0);
+        assert!(matches!(queue.poll(old), PathStatus::Pending));
+
+        // just enough for the old one
+        queue.budget = nodes(&building, &old_request) + 1;
+        queue.submit(young_request);
+        queue.process(&building, 1);
+        assert!(matches!(queue.poll(old), PathStatus::Ready(_)));
+        assert!(matches!(queue.poll(young), PathStatus::Pending));
+        queue.process(&building, 2);
+        assert!(matches!(queue.poll(young), PathStatus::Ready(_)));
+        world_mut().despawn(old).unwrap();
+        world_mut().despawn(young).unwrap();
+    }
+
+    #[test]
+    fn drops_requests_of_despawned_agents() {
+        let _world = WORLD.lock();
+        let building = hall();
+        let entity = world_mut().spawn((0u8,));
+        let queue = PathQueue::new();
+        queue.submit(request(entity, ps(0, 0), ps(3, 0)));
+        world_mut().despawn(entity).unwrap();
+        queue.process(&building, 0);
+        assert!(matches!(queue.poll(entity), PathStatus::Unknown));
+    }
+}
@@ -1,55 +1,331 @@
 // persistence is a way for GameObjects to know anything about what is around
 
-use comfy::{HashMap, Itertools, Vec2};
+use comfy::{Entity, HashMap, Itertools, Vec2};
 
-use crate::core::position::Ps;
+use crate::{behavior::creatures::PsOffsetProvider, core::position::Ps};
 
+// subclasses, the broad kind of thing the class belongs to
+pub static CARRIABLE: &str = "carriable";
+pub static INTERACTIVE: &str = "interactive";
+pub static CREATURE: &str = "creature";
+
+#[derive(Debug, Clone, Copy)]
 pub struct PersistentCell {
-    item_class: &'static str,
-    item_subclass: &'static str,
-    offset: Vec2,
+    pub entity: Entity,
+    pub item_class: &'static str,
+    pub item_subclass: &'static str,
+    pub offset: Vec2,
 }
 
+impl PersistentCell {
+    pub fn new(
+        entity: Entity,
+        item_class: &'static str,
+        item_subclass: &'static str,
+        offset: Vec2,
+    ) -> Self {
+        Self {
+            entity,
+            item_class,
+            item_subclass,
+            offset,
+        }
+    }
+}
+
+/// Index of everything on the map by cell, so "what is around me" doesn't need
+/// to walk every carriable or interactive object in the world.
+///
+/// Entities are put here when they are initialized, moved when they step into another cell
+/// and removed when they are consumed or despawned.
 pub struct Persistence {
-    map: HashMap<Ps, HashMap<&'static str, PersistentCell>>,
+    map: HashMap<Ps, Vec<PersistentCell>>,
+    positions: HashMap<Entity, Ps>,
+    // width and height of every floor, queries don't look past them
+    sizes: Vec<(usize, usize)>,
 }
 
 impl Persistence {
-    pub fn new() -> Self {
+    pub fn new(sizes: Vec<(usize, usize)>) -> Self {
         Self {
             map: HashMap::new(),
+            positions: HashMap::new(),
+            sizes,
         }
     }
 
+    /// Follows a building of other floors, whatever is indexed stays.
+    pub fn resize(&mut self, sizes: Vec<(usize, usize)>) {
+        self.sizes = sizes;
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.positions.clear();
+    }
+
     pub fn map_cell<F, T>(&self, ps: Ps, fnct: F) -> Option<T>
     where
-        F: Fn(&HashMap<&str, PersistentCell>) -> T,
+        F: Fn(&[PersistentCell]) -> T,
     {
         self.map.get(&ps).map(|borrowed| fnct(borrowed))
     }
 
-    pub fn remove_class(&mut self, ps: Ps, class_name: &str) -> Option<PersistentCell> {
-        let map = self.map.get_mut(&ps);
-        map.map(|f| f.remove(class_name)).flatten()
+    /// Registers the entity at `ps`, or moves it there if it was somewhere else.
+    pub fn put(&mut self, ps: Ps, item: PersistentCell) {
+        match self.positions.insert(item.entity, ps) {
+            Some(old) if old == ps => {
+                let cell = self.map.get_mut(&ps).unwrap();
+                if let Some(existing) = cell.iter_mut().find(|c| c.entity == item.entity) {
+                    *existing = item;
+                }
+                return;
+            }
+            Some(old) => self.remove_from_cell(old, item.entity),
+            None => {}
+        }
+        let cell = self.map.entry(ps).or_default();
+        cell.push(item);
+        // entity order, so queries give the same answer regardless of registration order
+        cell.sort_by_key(|c| c.entity);
+    }
+
+    /// Registers the entity on the cell `at` stands on.
+    pub fn register(
+        &mut self,
+        entity: Entity,
+        item_class: &'static str,
+        item_subclass: &'static str,
+        at: &dyn PsOffsetProvider,
+    ) {
+        let item = PersistentCell::new(entity, item_class, item_subclass, at.get_offset());
+        self.put(at.get_ps(), item);
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> Option<PersistentCell> {
+        let ps = self.positions.remove(&entity)?;
+        let cell = self.map.get_mut(&ps)?;
+        let index = cell.iter().position(|c| c.entity == entity)?;
+        let removed = cell.remove(index);
+        if cell.is_empty() {
+            self.map.remove(&ps);
+        }
+        Some(removed)
+    }
+
+    fn remove_from_cell(&mut self, ps: Ps, entity: Entity) {
+        if let Some(cell) = self.map.get_mut(&ps) {
+            cell.retain(|c| c.entity != entity);
+            if cell.is_empty() {
+                self.map.remove(&ps);
+            }
+        }
+    }
+
+    /// Moves an entity that is already registered to `ps`, it stays what it was registered as.
+    pub fn move_to(&mut self, entity: Entity, ps: Ps) {
+        if self.position_of(entity).is_some_and(|old| old != ps) {
+            if let Some(item) = self.remove(entity) {
+                self.put(ps, item);
+            }
+        }
+    }
+
+    pub fn position_of(&self, entity: Entity) -> Option<Ps> {
+        self.positions.get(&entity).copied()
+    }
+
+    pub fn get_all(&self, ps: Ps) -> &[PersistentCell] {
+        self.map.get(&ps).map(|cell| cell.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn get_all_classes(&self, ps: Ps) -> Vec<&'static str> {
+        self.get_all(ps)
+            .iter()
+            .map(|c| c.item_class)
+            .unique()
+            .collect_vec()
+    }
+
+    /// Everything within `radius` cells (manhattan) of `ps`, closest first.
+    pub fn query_radius(&self, ps: Ps, radius: usize) -> Vec<(Ps, PersistentCell)> {
+        let radius = radius.min(i32::MAX as usize);
+        let from = Ps {
+            x: ps.x.saturating_sub(radius),
+            y: ps.y.saturating_sub(radius),
+            floor: ps.floor,
+        };
+        let to = Ps {
+            x: ps.x.saturating_add(radius),
+            y: ps.y.saturating_add(radius),
+            floor: ps.floor,
+        };
+        let radius = radius as i32;
+        let mut found = self
+            .query_rect(from, to)
+            .into_iter()
+            .filter(|(cell_ps, _)| ps.manhattan_distance(cell_ps) <= radius)
+            .collect_vec();
+        found.sort_by_key(|(cell_ps, cell)| (ps.manhattan_distance(cell_ps), cell.entity));
+        found
     }
 
-    pub fn put_class(&mut self, ps: Ps, item: PersistentCell) {
-        let exists = self.map.contains_key(&ps);
-        if !exists {
-            self.map.insert(ps, HashMap::new());
+    /// Everything inside the rectangle on the floor of `from`, both corners included,
+    /// ordered by row and column. The part outside the floor is left out.
+    pub fn query_rect(&self, from: Ps, to: Ps) -> Vec<(Ps, PersistentCell)> {
+        let floor = from.floor;
+        let Some(&(width, height)) = self.sizes.get(floor) else {
+            return Vec::new();
+        };
+        if width == 0 || height == 0 {
+            return Vec::new();
         }
-        self.map.get_mut(&ps).unwrap().insert(item.item_class, item);
+        let (min_x, max_x) = (from.x.min(to.x), from.x.max(to.x).min(width - 1));
+        let (min_y, max_y) = (from.y.min(to.y), from.y.max(to.y).min(height - 1));
+        if min_x > max_x || min_y > max_y {
+            return Vec::new();
+        }
+        let inside = |ps: &Ps| {
+            ps.floor == floor && ps.x >= min_x && ps.x <= max_x && ps.y >= min_y && ps.y <= max_y
+        };
+
+        let area = (max_x - min_x + 1) * (max_y - min_y + 1);
+        // walk whichever is smaller, the rectangle or the occupied cells
+        let cells: Vec<Ps> = if area <= self.map.len() {
+            (min_y..=max_y)
+                .cartesian_product(min_x..=max_x)
+                .map(|(y, x)| Ps { x, y, floor })
+                .filter(|ps| self.map.contains_key(ps))
+                .collect()
+        } else {
+            self.map
+                .keys()
+                .filter(|ps| inside(ps))
+                .copied()
+                .sorted_by_key(|ps| (ps.y, ps.x))
+                .collect()
+        };
+
+        cells
+            .into_iter()
+            .flat_map(|ps| self.get_all(ps).iter().map(move |cell| (ps, *cell)))
+            .collect()
     }
 
-    pub fn get_all_classes(&mut self, ps: Ps) -> Vec<&'static str> {
-        self.map
-            .get(&ps)
-            .map(|data| {
-                data.keys()
-                    .into_iter()
-                    .map(|slink| slink.to_owned())
-                    .collect_vec()
-            })
-            .unwrap_or(Vec::new())
+    /// Distinct classes within `radius` cells of `ps`, closest first.
+    pub fn classes_within(&self, ps: Ps, radius: usize) -> Vec<&'static str> {
+        self.query_radius(ps, radius)
+            .into_iter()
+            .map(|(_, cell)| cell.item_class)
+            .unique()
+            .collect_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::building::testing::{agent, ps};
+
+    fn cell(id: u64, class: &'static str) -> PersistentCell {
+        PersistentCell::new(agent(id), class, CARRIABLE, Vec2::ZERO)
+    }
+
+    fn entities(found: Vec<(Ps, PersistentCell)>) -> Vec<Entity> {
+        found.into_iter().map(|(_, cell)| cell.entity).collect()
+    }
+
+    #[test]
+    fn put_move_and_remove_keep_one_place_per_entity() {
+        let mut persistence = Persistence::new(vec![(10, 10)]);
+        persistence.put(ps(1, 1), cell(1, "bone"));
+        persistence.put(ps(1, 1), cell(2, "bone"));
+        assert_eq!(persistence.len(), 2);
+
+        persistence.move_to(agent(1), ps(2, 1));
+        assert_eq!(persistence.position_of(agent(1)), Some(ps(2, 1)));
+        assert_eq!(persistence.get_all(ps(1, 1)).len(), 1);
+        assert_eq!(persistence.get_all_classes(ps(2, 1)), vec!["bone"]);
+
+        // putting it again moves it as well
+        persistence.put(ps(3, 3), cell(1, "trash_can"));
+        assert!(persistence.get_all(ps(2, 1)).is_empty());
+        assert_eq!(persistence.get_all_classes(ps(3, 3)), vec!["trash_can"]);
+
+        assert_eq!(
+            persistence.remove(agent(1)).map(|c| c.entity),
+            Some(agent(1))
+        );
+        assert_eq!(persistence.remove(agent(1)).map(|c| c.entity), None);
+        assert_eq!(persistence.position_of(agent(1)), None);
+        // moving something that isn't indexed doesn't index it
+        persistence.move_to(agent(1), ps(4, 4));
+        assert_eq!(persistence.len(), 1);
+    }
+
+    #[test]
+    fn queries_give_what_is_around_closest_first() {
+        let mut persistence = Persistence::new(vec![(10, 10)]);
+        persistence.put(ps(5, 5), cell(3, "bone"));
+        persistence.put(ps(5, 7), cell(1, "trash_can"));
+        persistence.put(ps(7, 5), cell(2, "bone"));
+        persistence.put(ps(0, 0), cell(4, "bone"));
+        persistence.put(
+            Ps {
+                x: 5,
+                y: 5,
+                floor: 1,
+            },
+            cell(5, "bone"),
+        );
+
+        assert_eq!(
+            entities(persistence.query_radius(ps(5, 5), 2)),
+            vec![agent(3), agent(1), agent(2)]
+        );
+        assert_eq!(
+            entities(persistence.query_rect(ps(7, 7), ps(5, 5))),
+            vec![agent(3), agent(2), agent(1)]
+        );
+        assert_eq!(
+            persistence.classes_within(ps(5, 5), 2),
+            vec!["bone", "trash_can"]
+        );
+        assert!(persistence.query_radius(ps(9, 9), 3).is_empty());
+    }
+
+    #[test]
+    fn queries_stop_at_the_edges_of_the_floor() {
+        let mut persistence = Persistence::new(vec![(10, 10)]);
+        persistence.put(ps(0, 0), cell(1, "bone"));
+        persistence.put(ps(9, 9), cell(2, "bone"));
+
+        assert_eq!(
+            entities(persistence.query_radius(ps(9, 9), usize::MAX)),
+            vec![agent(2), agent(1)]
+        );
+        assert_eq!(
+            entities(persistence.query_rect(ps(0, 0), ps(usize::MAX, usize::MAX))),
+            vec![agent(1), agent(2)]
+        );
+        // no such floor
+        assert!(persistence
+            .query_rect(
+                Ps {
+                    x: 0,
+                    y: 0,
+                    floor: 3
+                },
+                ps(9, 9)
+            )
+            .is_empty());
     }
 }
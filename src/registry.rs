@@ -0,0 +1,156 @@
+// registry is the one place that knows which entity types exist: what a Tiled class
+// turns into on the map and which components have to be initialized every tick
+
+use comfy::{hecs::Component, ivec2, vec2, Vec2};
+
+use crate::{
+    behavior::actor::ACTOR_TYPES,
+    building::Building,
+    core::{animation::AdditionalAnimationDescr, position::Ps, Initializable},
+    gameplay::ent::{bed::Bed, conputer::Conputer, Grass, MapEntityObject},
+    initializers::spawn_object_sprite,
+    simulation::update_init,
+    state::Reality,
+    worldmap::{Cellmap, TileReference},
+    Bone, TrashCan, RES_I32,
+};
+
+type TileSpawner = Box<dyn Fn(Ps, &TileReference, Vec2, String)>;
+
+struct MapClass {
+    class: &'static str,
+    spawn: TileSpawner,
+}
+
+pub struct EntityRegistry {
+    map_classes: Vec<MapClass>,
+    // in registration order, which is the order entities are initialized in
+    initializers: Vec<fn(&mut Reality)>,
+    map_initializers: Vec<fn(&mut Reality)>,
+}
+
+impl EntityRegistry {
+    pub fn new() -> Self {
+        Self {
+            map_classes: Vec::new(),
+            initializers: Vec::new(),
+            map_initializers: Vec::new(),
+        }
+    }
+
+    /// Everything the game knows about, add new map classes and creatures here.
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::new();
+        registry.register_initializable::<TrashCan>();
+        registry.register_initializable::<Bone>();
+        registry.register_tile_class(
+            "conputer",
+            |tile| {
+                let workplace = ivec2(
+                    tile.int_prop("x").unwrap_or(0),
+                    tile.int_prop("y").unwrap_or(0),
+                );
+                Conputer::new(workplace)
+            },
+            vec![AdditionalAnimationDescr::new(
+                "idle".into(),
+                "conputer_idle.png".into(),
+                10,
+                1.0,
+            )],
+        );
+        registry.register_tile_class("bed", |_| Bed::new(), Vec::new());
+        for actor_type in ACTOR_TYPES.iter() {
+            registry.initializers.push(actor_type.initialize);
+        }
+        registry
+    }
+
+    /// Tiles with the Tiled class `class` become entities made by `from_tile`,
+    /// `animations` are played next to the tile's own "base" one.
+    pub fn register_tile_class<T, F>(
+        &mut self,
+        class: &'static str,
+        from_tile: F,
+        animations: Vec<AdditionalAnimationDescr>,
+    ) where
+        T: MapEntityObject + Initializable + Component,
+        F: Fn(&TileReference) -> T + 'static,
+    {
+        for animation in animations.iter() {
+            crate::lazy_load_texture(animation.atlas_name.clone());
+        }
+        let spawn = move |ps: Ps, tile: &TileReference, size: Vec2, name: String| {
+            let object = from_tile(tile);
+            println!("{} created: {:?}", class, object);
+            spawn_object_sprite(ps, tile, size, name, move || object, animations.clone())
+        };
+        self.map_classes.push(MapClass {
+            class,
+            spawn: Box::new(spawn),
+        });
+        self.initializers.push(update_init::<T>);
+        self.map_initializers.push(update_init::<T>);
+    }
+
+    /// Component that needs `Initializable::initialize` but doesn't come from the map.
+    pub fn register_initializable<T: Initializable + Component>(&mut self) {
+        self.initializers.push(update_init::<T>);
+    }
+
+    /// Spawns an entity for every tile on every floor, unknown classes become plain decorations.
+    pub fn spawn_map_objects(&self, building: &Building) {
+        for (floor, cellmap) in building.floors().iter().enumerate() {
+            self.spawn_floor_objects(floor, cellmap);
+        }
+    }
+
+    fn spawn_floor_objects(&self, floor: usize, cellmap: &Cellmap) {
+        let (max_x, max_y) = cellmap.wh_i32();
+
+        for x in 0..max_x {
+            for y in 0..max_y {
+                let cell = cellmap.get_xy(x, y);
+                if let Some(tile) = &cell.reference {
+                    self.spawn_tile_object(cell.position.with_floor(floor), tile);
+                }
+            }
+        }
+    }
+
+    /// Entity for the tile of the cell at `ps`.
+    pub fn spawn_tile_object(&self, ps: Ps, tile: &TileReference) {
+        let name = tile.tile_image.clone();
+        let size = vec2(
+            tile.size.x as f32 / RES_I32 as f32,
+            tile.size.y as f32 / RES_I32 as f32,
+        );
+        match self
+            .map_classes
+            .iter()
+            .find(|registered| registered.class == tile.klass)
+        {
+            Some(registered) => (registered.spawn)(ps, tile, size, name),
+            None => spawn_object_sprite(ps, tile, size, name, || Grass {}, Vec::new()),
+        }
+    }
+
+    pub fn initialize_all(&self, reality: &mut Reality) {
+        for initialize in self.initializers.iter() {
+            initialize(reality);
+        }
+    }
+
+    /// Only the types coming from the map, they are not part of a save.
+    pub fn initialize_map_objects(&self, reality: &mut Reality) {
+        for initialize in self.map_initializers.iter() {
+            initialize(reality);
+        }
+    }
+}
+
+impl Default for EntityRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,142 @@
+// replay is a recording of everything the player did to the world, tick by tick,
+// on top of the world as it was when the recording started
+
+use std::{fs, path::Path};
+
+use comfy::IVec2;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    behavior::creatures::Direction,
+    building::Building,
+    core::position::Ps,
+    editor::Brush,
+    savegame::{self, SaveError, WorldSnapshot},
+    simulation::Simulation,
+};
+
+/// Bump this whenever the replay layout or the snapshot inside it changes.
+pub const REPLAY_VERSION: u32 = 8;
+
+/// A world mutation coming from the player. Input only queues commands,
+/// the simulation applies them at the start of the next tick.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Command {
+    /// Every dog goes to the cell (right click).
+    DogOrder(Ps),
+    /// Cell blocked by the selection (left click).
+    Occupy(Ps),
+    /// Cell released by the selection.
+    Deoccupy(Ps),
+    /// Every dog takes a step in the direction (arrow keys).
+    RedirectDogs(Direction),
+    /// Every dog walks to the cell (P key).
+    MoveDogsTo(Ps),
+    /// The brush is used on the cell (left click in edit mode).
+    Edit {
+        brush: Brush,
+        ps: Ps,
+        /// Workplace of a placed conputer relative to it.
+        #[serde(with = "crate::core::serialization::ivec2")]
+        workplace: IVec2,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RecordedCommand {
+    pub tick: u64,
+    pub command: Command,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    /// Name of the scenario the replay was recorded in.
+    pub scenario: String,
+    /// `Building::walk_hash` of the map the recording started on.
+    pub map: u64,
+    pub seed: u64,
+    pub dt: f32,
+    /// Ticks run up to here, exclusive.
+    pub end_tick: u64,
+    /// World at the tick recording started, so the replay doesn't depend on how it was set up.
+    pub start: WorldSnapshot,
+    pub commands: Vec<RecordedCommand>,
+}
+
+#[derive(Deserialize)]
+struct ReplayHeader {
+    #[serde(default)]
+    version: u32,
+}
+
+impl Replay {
+    /// Replay of `sim` from `start` up to (not including) `end_tick`.
+    pub fn new(
+        start: WorldSnapshot,
+        sim: &Simulation,
+        scenario: &str,
+        dt: f32,
+        end_tick: u64,
+    ) -> Self {
+        let commands = sim
+            .recorded
+            .iter()
+            .filter(|recorded| recorded.tick >= start.tick && recorded.tick < end_tick)
+            .copied()
+            .collect();
+        Self {
+            version: REPLAY_VERSION,
+            scenario: scenario.to_string(),
+            map: start.map,
+            seed: start.seed,
+            dt,
+            end_tick,
+            start,
+            commands,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SaveError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self, SaveError> {
+        let text = fs::read_to_string(path)?;
+        let header: ReplayHeader = serde_json::from_str(&text)?;
+        if header.version != REPLAY_VERSION {
+            return Err(SaveError::UnsupportedVersion(header.version));
+        }
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// Restores the starting world and runs it to the end, feeding the commands on their ticks.
+    /// Refused unless played in the scenario and on the map it was recorded on.
+    pub fn play(self, scenario: &str, building: Building) -> Result<Simulation, SaveError> {
+        if self.scenario != scenario {
+            return Err(SaveError::ScenarioMismatch {
+                recorded: self.scenario,
+                loaded: scenario.to_string(),
+            });
+        }
+        if self.map != building.walk_hash() {
+            return Err(SaveError::MapChanged);
+        }
+        let mut sim = savegame::restore(self.start, building)?;
+        // replays are only worth anything if they are reproducible
+        sim.deterministic = true;
+        let mut commands = self.commands.into_iter().peekable();
+        while sim.tick < self.end_tick {
+            while let Some(recorded) = commands.next_if(|recorded| recorded.tick <= sim.tick) {
+                sim.submit(recorded.command);
+            }
+            sim.step(self.dt);
+        }
+        sim.flush_commands();
+        Ok(sim)
+    }
+}
@@ -0,0 +1,167 @@
+// reservation decides who may enter which cell during one tick, agents think in parallel
+// and can all pick the same free cell, so the sequential movement phase asks here first
+
+use comfy::{Entity, HashMap};
+
+use crate::{building::Building, core::position::Ps};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReservationDenied {
+    /// Wall, player block or another agent already there.
+    Blocked,
+    /// Someone else reserved the cell earlier this tick.
+    Taken(Entity),
+    /// The agent in the target cell is walking into ours this tick.
+    Swap(Entity),
+    /// Someone walks across the other diagonal of the same square this tick.
+    Cross(Entity),
+}
+
+/// Cells claimed during the current tick.
+///
+/// Conflicts go to whoever asks first. The movement phase visits agents in an order
+/// shuffled by the world rng, so the winner is the same on every run with the same seed.
+#[derive(Debug, Default)]
+pub struct Reservations {
+    claimed: HashMap<Ps, Entity>,
+    // target -> source of every move granted this tick
+    moves: HashMap<Ps, Ps>,
+}
+
+impl Reservations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forgets the previous tick.
+    pub fn clear(&mut self) {
+        self.claimed.clear();
+        self.moves.clear();
+    }
+
+    pub fn try_reserve(
+        &mut self,
+        entity: Entity,
+        from: Ps,
+        to: Ps,
+        building: &Building,
+    ) -> Result<(), ReservationDenied> {
+        if let Some(holder) = self.claimed.get(&to) {
+            if *holder != entity {
+                return Err(ReservationDenied::Taken(*holder));
+            }
+        }
+        // someone who came from our target into our cell would pass through us
+        if self.moves.get(&from) == Some(&to) {
+            return Err(ReservationDenied::Swap(self.claimed[&from]));
+        }
+        // a diagonal step passes through whoever takes the other diagonal of the square
+        if from.x != to.x && from.y != to.y {
+            let a = Ps { x: from.x, y: to.y, floor: from.floor };
+            let b = Ps { x: to.x, y: from.y, floor: from.floor };
+            if self.moves.get(&a) == Some(&b) {
+                return Err(ReservationDenied::Cross(self.claimed[&a]));
+            }
+            if self.moves.get(&b) == Some(&a) {
+                return Err(ReservationDenied::Cross(self.claimed[&b]));
+            }
+        }
+        if !building.get_pos(&to).is_free_for(entity) {
+            return Err(ReservationDenied::Blocked);
+        }
+        self.claimed.insert(to, entity);
+        self.moves.insert(to, from);
+        Ok(())
+    }
+
+    pub fn holder(&self, ps: Ps) -> Option<Entity> {
+        self.claimed.get(&ps).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::building::testing::{agent, ps};
+
+    fn open() -> Building {
+        Building::from_rows(&["....", "....", "...."])
+    }
+
+    #[test]
+    fn same_target_goes_to_whoever_asks_first() {
+        let building = open();
+        let mut reservations = Reservations::new();
+        let (a, b) = (agent(1), agent(2));
+        assert_eq!(
+            reservations.try_reserve(a, ps(0, 1), ps(1, 1), &building),
+            Ok(())
+        );
+        assert_eq!(
+            reservations.try_reserve(b, ps(2, 1), ps(1, 1), &building),
+            Err(ReservationDenied::Taken(a))
+        );
+        assert_eq!(reservations.holder(ps(1, 1)), Some(a));
+        reservations.clear();
+        assert_eq!(
+            reservations.try_reserve(b, ps(2, 1), ps(1, 1), &building),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn agents_do_not_swap_cells() {
+        let building = open();
+        let mut reservations = Reservations::new();
+        let (a, b) = (agent(1), agent(2));
+        assert_eq!(
+            reservations.try_reserve(a, ps(1, 1), ps(2, 1), &building),
+            Ok(())
+        );
+        assert_eq!(
+            reservations.try_reserve(b, ps(2, 1), ps(1, 1), &building),
+            Err(ReservationDenied::Swap(a))
+        );
+    }
+
+    #[test]
+    fn diagonal_steps_do_not_cross() {
+        let building = open();
+        let mut reservations = Reservations::new();
+        let (a, b, c) = (agent(1), agent(2), agent(3));
+        assert_eq!(
+            reservations.try_reserve(a, ps(1, 1), ps(2, 2), &building),
+            Ok(())
+        );
+        // the other diagonal of the same square, either way
+        assert_eq!(
+            reservations.try_reserve(b, ps(2, 1), ps(1, 2), &building),
+            Err(ReservationDenied::Cross(a))
+        );
+        assert_eq!(
+            reservations.try_reserve(b, ps(1, 2), ps(2, 1), &building),
+            Err(ReservationDenied::Cross(a))
+        );
+        // the square next to it is free
+        assert_eq!(
+            reservations.try_reserve(c, ps(3, 1), ps(2, 0), &building),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn walls_and_agents_block() {
+        let mut building = Building::from_rows(&["..#", "..."]);
+        let mut reservations = Reservations::new();
+        let (a, b) = (agent(1), agent(2));
+        assert_eq!(
+            reservations.try_reserve(a, ps(1, 0), ps(2, 0), &building),
+            Err(ReservationDenied::Blocked)
+        );
+        building.move_occupant(b, &ps(1, 1), &ps(2, 1));
+        assert_eq!(
+            reservations.try_reserve(a, ps(1, 1), ps(2, 1), &building),
+            Err(ReservationDenied::Blocked)
+        );
+    }
+}
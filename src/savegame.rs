@@ -0,0 +1,517 @@
+// savegame is a snapshot of everything in Reality that can't be rebuilt from the map:
+// agents with their minds and paths, items, who uses what, occupied cells, the clock and the rng
+
+use std::{collections::BTreeMap, fmt, fs, io, path::Path};
+
+use comfy::{commands, world, world_mut, Entity, Transform, Vec2};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::value::RawValue;
+
+use crate::{
+    behavior::{
+        actor::{Actor, ACTOR_TYPES},
+        carriable::carriableitem::CarriableItemHandle,
+        dog::{Dog, DogRoutine},
+        messaging::MessagingHost,
+        sanity::{Sanity, SelfAware},
+    },
+    building::{Building, Floor},
+    core::{position::Ps, rng::WorldRng},
+    gameplay::{
+        ent::officeworker::{OfficeWorker, OfficeWorkerRoutine},
+        gametime::Time,
+        humanclothes::Look,
+    },
+    initializers,
+    pathqueue::{PathRequest, PathResult},
+    simulation::{self, Simulation},
+    spacetime::Hold,
+    state::Reality,
+    tiledreader::MapLoadError,
+    Bone, TrashCan,
+};
+
+/// Bump this whenever the snapshot layout changes, old saves are refused instead of misread.
+/// Fields added along with a bump need no serde defaults, no older save gets that far.
+pub const SAVE_VERSION: u32 = 7;
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    UnsupportedVersion(u32),
+    /// The map the save is played on could not be loaded.
+    Map(MapLoadError),
+    /// Sizes of the floors, from the ground up.
+    MapMismatch {
+        saved: Vec<(usize, usize)>,
+        loaded: Vec<(usize, usize)>,
+    },
+    /// The floors are the same size, but their cells are walked differently.
+    MapChanged,
+    /// A replay is played in another scenario than it was recorded in.
+    ScenarioMismatch {
+        recorded: String,
+        loaded: String,
+    },
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "cannot access save file: {}", e),
+            SaveError::Format(e) => write!(f, "broken save file: {}", e),
+            SaveError::UnsupportedVersion(v) => write!(
+                f,
+                "save file version {} is not supported (expected {})",
+                v, SAVE_VERSION
+            ),
+            SaveError::Map(e) => write!(f, "cannot load the map: {}", e),
+            SaveError::MapMismatch { saved, loaded } => write!(
+                f,
+                "save was made on floors of {}, but the loaded map has {}",
+                floor_sizes(saved),
+                floor_sizes(loaded)
+            ),
+            SaveError::MapChanged => write!(
+                f,
+                "save was made on a different map with floors of the same size"
+            ),
+            SaveError::ScenarioMismatch { recorded, loaded } => write!(
+                f,
+                "replay was recorded in scenario \"{}\", not \"{}\"",
+                recorded, loaded
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+fn floor_sizes(floors: &[(usize, usize)]) -> String {
+    floors
+        .iter()
+        .map(|(w, h)| format!("{}x{}", w, h))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl From<io::Error> for SaveError {
+    fn from(e: io::Error) -> Self {
+        SaveError::Io(e)
+    }
+}
+
+impl From<MapLoadError> for SaveError {
+    fn from(e: MapLoadError) -> Self {
+        SaveError::Map(e)
+    }
+}
+
+impl From<serde_json::Error> for SaveError {
+    fn from(e: serde_json::Error) -> Self {
+        SaveError::Format(e)
+    }
+}
+
+/// Actor that goes into saves as a snapshot of its own.
+pub trait SavedActor: Actor {
+    type Snapshot: Serialize + DeserializeOwned;
+
+    fn snapshot(&self, entity: Entity) -> Self::Snapshot;
+
+    /// Spawns the saved actor again under its old id.
+    fn respawn(snapshot: Self::Snapshot);
+
+    /// Puts back what the actor keeps in `reality` besides persistence, once it is rebuilt.
+    fn reregister(&self, _entity: Entity, _reality: &Reality) {}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DogSnapshot {
+    #[serde(with = "crate::core::serialization::entity")]
+    pub entity: Entity,
+    pub name: String,
+    pub initialized: bool,
+    pub routine: DogRoutine,
+    pub sanity: Sanity,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkerSnapshot {
+    #[serde(with = "crate::core::serialization::entity")]
+    pub entity: Entity,
+    pub name: String,
+    pub initialized: bool,
+    pub routine: OfficeWorkerRoutine,
+    pub sanity: Sanity,
+    pub look: Look,
+}
+
+impl SavedActor for Dog {
+    type Snapshot = DogSnapshot;
+
+    fn snapshot(&self, entity: Entity) -> DogSnapshot {
+        DogSnapshot {
+            entity,
+            name: self.name.clone(),
+            initialized: self.initialized,
+            routine: (*self.sa.routine).clone(),
+            sanity: self.sa.sanity.lock().clone(),
+        }
+    }
+
+    fn respawn(dog: DogSnapshot) {
+        let restored = Dog {
+            name: dog.name,
+            initialized: dog.initialized,
+            sa: SelfAware {
+                routine: Box::new(dog.routine),
+                sanity: comfy::Mutex::new(dog.sanity),
+            },
+        };
+        world_mut().spawn_at(dog.entity, initializers::dog_components(restored));
+    }
+
+    fn reregister(&self, entity: Entity, reality: &Reality) {
+        if self.initialized {
+            reality.messaging.lock().insert(entity, MessagingHost::new());
+        }
+    }
+}
+
+impl SavedActor for OfficeWorker {
+    type Snapshot = WorkerSnapshot;
+
+    fn snapshot(&self, entity: Entity) -> WorkerSnapshot {
+        WorkerSnapshot {
+            entity,
+            name: self.name.clone(),
+            initialized: self.initialized,
+            routine: (*self.sa.routine).clone(),
+            sanity: self.sa.sanity.lock().clone(),
+            look: self.look.clone(),
+        }
+    }
+
+    fn respawn(worker: WorkerSnapshot) {
+        // body parts are spawned on initialization, which won't happen again
+        if worker.initialized {
+            worker.look.spawn_for_entity(worker.entity);
+        }
+        let restored = OfficeWorker {
+            name: worker.name,
+            initialized: worker.initialized,
+            look: worker.look,
+            sa: SelfAware {
+                routine: Box::new(worker.routine),
+                sanity: comfy::Mutex::new(worker.sanity),
+            },
+        };
+        world_mut().spawn_at(worker.entity, initializers::worker_components(restored));
+    }
+}
+
+/// Snapshots of every actor of type `A` in entity order.
+pub fn save_actors<A: SavedActor>() -> Box<RawValue> {
+    let wrld = world();
+    let mut queried = wrld.query::<&A>();
+    let mut actors = queried.iter().collect::<Vec<_>>();
+    actors.sort_by_key(|(entity, _)| *entity);
+    let snapshots: Vec<A::Snapshot> = actors
+        .into_iter()
+        .map(|(entity, actor)| actor.snapshot(entity))
+        .collect();
+    // the whole save is json, whatever doesn't make it here wouldn't make it to the file either
+    serde_json::value::to_raw_value(&snapshots).expect("actor snapshots are json")
+}
+
+pub fn respawn_actors<A: SavedActor>(saved: &RawValue) -> Result<(), SaveError> {
+    let snapshots: Vec<A::Snapshot> = serde_json::from_str(saved.get())?;
+    for snapshot in snapshots {
+        A::respawn(snapshot);
+    }
+    Ok(())
+}
+
+pub fn reregister_actors<A: SavedActor>(reality: &Reality) {
+    for (entity, actor) in world().query::<&A>().iter() {
+        actor.reregister(entity, reality);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ItemSnapshot {
+    #[serde(with = "crate::core::serialization::entity")]
+    pub entity: Entity,
+    #[serde(with = "crate::core::serialization::vec2")]
+    pub position: Vec2,
+    pub floor: usize,
+    pub initialized: bool,
+    pub handle: Option<CarriableItemHandle>,
+}
+
+// interactive objects come from the map, only their state is saved
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InteractiveSnapshot {
+    pub position: Ps,
+    #[serde(with = "crate::core::serialization::option_entity")]
+    pub used_by: Option<Entity>,
+    pub assigned: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OccupantSnapshot {
+    pub position: Ps,
+    #[serde(with = "crate::core::serialization::entity")]
+    pub entity: Entity,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    pub version: u32,
+    pub seed: u64,
+    pub tick: u64,
+    pub time: Time,
+    pub rng: WorldRng,
+    /// Sizes of the floors the save was made on, from the ground up.
+    pub floors: Vec<(usize, usize)>,
+    /// `Building::walk_hash` of the map the save was made on.
+    pub map: u64,
+    pub occupied: Vec<Ps>,
+    pub occupants: Vec<OccupantSnapshot>,
+    /// Snapshots of the actors of every type by class, read once the type is known.
+    pub actors: BTreeMap<String, Box<RawValue>>,
+    pub bones: Vec<ItemSnapshot>,
+    pub trashcans: Vec<ItemSnapshot>,
+    pub interactive: Vec<InteractiveSnapshot>,
+    /// Cells agents planned ahead, they keep walking the same plans.
+    pub timetable: Vec<Hold>,
+    /// Paths asked for and not handed out yet.
+    pub path_requests: Vec<PathRequest>,
+    pub path_results: Vec<PathResult>,
+}
+
+#[derive(Deserialize)]
+struct SaveHeader {
+    #[serde(default)]
+    version: u32,
+}
+
+pub fn snapshot(sim: &Simulation) -> WorldSnapshot {
+    // spawns and despawns still in the queue belong to the saved world
+    sim.flush_commands();
+    let reality = &sim.reality;
+    let floors = reality.building.sizes();
+
+    let actors = ACTOR_TYPES
+        .iter()
+        .map(|actor_type| (actor_type.class.to_string(), (actor_type.save)()))
+        .collect();
+
+    let carriables = reality.carriables.lock();
+    let item_snapshot =
+        |entity: Entity, transform: &Transform, floor: Option<&Floor>, initialized: bool| {
+            ItemSnapshot {
+                entity,
+                position: transform.position,
+                floor: floor.map_or(0, |floor| floor.0),
+                initialized,
+                handle: carriables.get(&entity).copied(),
+            }
+        };
+    let mut bones: Vec<ItemSnapshot> = world()
+        .query::<(&Bone, &Transform, Option<&Floor>)>()
+        .iter()
+        .map(|(entity, (bone, transform, floor))| {
+            item_snapshot(entity, transform, floor, bone.initialized)
+        })
+        .collect();
+    bones.sort_by_key(|item| item.entity);
+    let mut trashcans: Vec<ItemSnapshot> = world()
+        .query::<(&TrashCan, &Transform, Option<&Floor>)>()
+        .iter()
+        .map(|(entity, (trashcan, transform, floor))| {
+            item_snapshot(entity, transform, floor, trashcan.initialized)
+        })
+        .collect();
+    trashcans.sort_by_key(|item| item.entity);
+
+    let mut interactive: Vec<InteractiveSnapshot> = reality
+        .interactive
+        .lock()
+        .iter()
+        .map(|(ps, handle)| InteractiveSnapshot {
+            position: *ps,
+            used_by: handle.used_by,
+            assigned: handle.assigned,
+        })
+        .collect();
+    interactive.sort_by_key(|item| (item.position.floor, item.position.x, item.position.y));
+
+    let cellmaps = reality.building.floors();
+    let cells = || cellmaps.iter().flat_map(|floor| floor.map.iter());
+    let occupied = cells()
+        .filter(|cell| cell.status.occupied)
+        .map(|cell| cell.position)
+        .collect();
+    let occupants = cells()
+        .filter_map(|cell| {
+            cell.status.occupant.map(|entity| OccupantSnapshot {
+                position: cell.position,
+                entity,
+            })
+        })
+        .collect();
+
+    let rng = reality.rng.lock().clone();
+    let (path_requests, path_results) = reality.paths.saved();
+    WorldSnapshot {
+        version: SAVE_VERSION,
+        seed: rng.seed(),
+        tick: sim.tick,
+        time: reality.time.clone(),
+        rng,
+        floors,
+        map: reality.building.walk_hash(),
+        occupied,
+        occupants,
+        actors,
+        bones,
+        trashcans,
+        interactive,
+        timetable: reality.timetable.all_holds(),
+        path_requests,
+        path_results,
+    }
+}
+
+pub fn save(sim: &Simulation, path: &Path) -> Result<(), SaveError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_string_pretty(&snapshot(sim))?;
+    fs::write(path, json)?;
+    Ok(())
+}
+
+pub fn read(path: &Path) -> Result<WorldSnapshot, SaveError> {
+    let text = fs::read_to_string(path)?;
+    // look at the version first, an old layout would fail with a confusing field error
+    let header: SaveHeader = serde_json::from_str(&text)?;
+    if header.version != SAVE_VERSION {
+        return Err(SaveError::UnsupportedVersion(header.version));
+    }
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// Rebuilds a simulation from the snapshot on top of a freshly read building.
+///
+/// Saved entities are respawned with their old ids, so the comfy world is cleared first:
+/// anything else living there (decorations, camera) has to be spawned again by the caller.
+pub fn restore(mut snapshot: WorldSnapshot, building: Building) -> Result<Simulation, SaveError> {
+    let loaded = building.sizes();
+    if loaded != snapshot.floors {
+        return Err(SaveError::MapMismatch {
+            saved: snapshot.floors,
+            loaded,
+        });
+    }
+    if building.walk_hash() != snapshot.map {
+        return Err(SaveError::MapChanged);
+    }
+
+    {
+        let mut wrld = world_mut();
+        commands().run_on(&mut wrld);
+        wrld.clear();
+    }
+
+    for actor_type in ACTOR_TYPES.iter() {
+        if let Some(saved) = snapshot.actors.remove(actor_type.class) {
+            (actor_type.respawn)(&saved)?;
+        }
+    }
+    for class in snapshot.actors.keys() {
+        println!("WARN: saved actors of class {} are not known", class);
+    }
+
+    let mut handles = Vec::new();
+    for bone in snapshot.bones {
+        let restored = Bone { initialized: bone.initialized };
+        world_mut().spawn_at(
+            bone.entity,
+            initializers::bone_components(bone.position, bone.floor, restored),
+        );
+        handles.extend(bone.handle);
+    }
+    for trashcan in snapshot.trashcans {
+        let restored = TrashCan { initialized: trashcan.initialized };
+        world_mut().spawn_at(
+            trashcan.entity,
+            initializers::trashcan_components(trashcan.position, trashcan.floor, restored),
+        );
+        handles.extend(trashcan.handle);
+    }
+
+    let mut sim = Simulation::new(building, snapshot.seed);
+    sim.flush_commands();
+
+    // map objects register their handles on initialization, the saved state goes on top
+    sim.registry.initialize_map_objects(&mut sim.reality);
+    {
+        let mut interactive = sim.reality.interactive.lock();
+        for saved in snapshot.interactive {
+            match interactive.get_mut(&saved.position) {
+                Some(handle) => {
+                    handle.used_by = saved.used_by;
+                    handle.assigned = saved.assigned;
+                }
+                None => println!(
+                    "WARN: saved interactive object at {:?} is not on the map",
+                    saved.position
+                ),
+            }
+        }
+    }
+
+    {
+        let mut carriables = sim.reality.carriables.lock();
+        for handle in handles {
+            carriables.insert(handle.item_id, handle);
+        }
+    }
+
+    for actor_type in ACTOR_TYPES.iter() {
+        (actor_type.reregister)(&sim.reality);
+    }
+
+    for ps in snapshot.occupied.iter() {
+        sim.reality.building.occupy_ps(ps);
+    }
+    for occupant in snapshot.occupants.iter() {
+        let ps = occupant.position;
+        sim.reality
+            .building
+            .move_occupant(occupant.entity, &ps, &ps);
+    }
+
+    sim.reality.time = snapshot.time;
+    *sim.reality.rng.get_mut() = snapshot.rng;
+    sim.tick = snapshot.tick;
+    sim.reality.timetable.restore(snapshot.timetable);
+    sim.reality.paths.restore(
+        &sim.reality.building,
+        snapshot.path_requests,
+        snapshot.path_results,
+        snapshot.tick,
+    );
+    simulation::update_communication(&mut sim.reality);
+    simulation::rebuild_persistence(&mut sim.reality);
+
+    Ok(sim)
+}
+
+pub fn load(path: &Path, building: Building) -> Result<Simulation, SaveError> {
+    restore(read(path)?, building)
+}
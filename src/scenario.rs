@@ -0,0 +1,226 @@
+// scenario is everything about a run that is not the code: which map, how many of what,
+// when the day starts and how fast it goes, kept in json files under scenarios/
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    behavior::item_types::{BONE, DOG, OFFICE_WORKER, TRASHCAN},
+    building::{Building, Movement},
+    core::anycellmap::AnyCellmap,
+    generator::{OfficeLayout, OfficeParams},
+    tiledreader::{stack_floors, DecorTile, MapLoadError},
+};
+
+pub const SCENARIO_ARG: &str = "--scenario";
+pub const MAP_ARG: &str = "--map";
+pub const GENERATE_ARG: &str = "--generate";
+pub const DEFAULT_MAP: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/level0.tmx");
+
+// entity types a scenario can ask to spawn
+const SPAWNABLE: [&str; 4] = [DOG, OFFICE_WORKER, BONE, TRASHCAN];
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(PathBuf, io::Error),
+    Format(PathBuf, serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            ScenarioError::Format(path, e) => {
+                write!(f, "broken scenario {}: {}", path.display(), e)
+            }
+            ScenarioError::Invalid(reason) => write!(f, "invalid scenario: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    pub name: String,
+    /// Relative paths are resolved against the scenario file.
+    pub map: PathBuf,
+    /// Maps of the floors above `map`, resolved the same way. Any map can hold
+    /// several floors of its own as layer groups.
+    pub floors: Vec<PathBuf>,
+    /// Ground floor generated from these instead of read from `map`.
+    pub generate: Option<OfficeParams>,
+    /// Fixed world seed, otherwise taken from the environment or picked at random.
+    pub seed: Option<u64>,
+    /// How many entities of each type to spawn, by item type name.
+    pub spawn: BTreeMap<String, usize>,
+    /// Clock at the start, "HH:MM".
+    pub start_time: String,
+    /// Game minutes per real second.
+    pub time_speed: f32,
+    /// Straight steps only unless `"diagonal": true`, `"corners"` says which walls a diagonal
+    /// step may pass: "cut", "no_squeeze" or "no_cutting".
+    pub movement: Movement,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            name: "default".to_owned(),
+            map: PathBuf::from(DEFAULT_MAP),
+            floors: Vec::new(),
+            generate: None,
+            seed: None,
+            spawn: BTreeMap::from([
+                (DOG.to_owned(), 100),
+                (OFFICE_WORKER.to_owned(), 5),
+                (BONE.to_owned(), 3),
+                (TRASHCAN.to_owned(), 2),
+            ]),
+            start_time: "16:00".to_owned(),
+            time_speed: 10.0,
+            movement: Movement::default(),
+        }
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, ScenarioError> {
+        let text = fs::read_to_string(path).map_err(|e| ScenarioError::Io(path.to_owned(), e))?;
+        let mut scenario: Scenario =
+            serde_json::from_str(&text).map_err(|e| ScenarioError::Format(path.to_owned(), e))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for map in std::iter::once(&mut scenario.map).chain(scenario.floors.iter_mut()) {
+            if map.is_relative() {
+                *map = dir.join(&*map);
+            }
+        }
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /// Scenario named by `--scenario PATH` in the process arguments, the default one without it.
+    /// `--map PATH` swaps the map of whichever scenario was picked, `--generate SEED` swaps it
+    /// for a generated one.
+    pub fn from_args() -> Result<Self, ScenarioError> {
+        let mut scenario = None;
+        let mut map = None;
+        let mut generate = None;
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            if arg == SCENARIO_ARG || arg == MAP_ARG || arg == GENERATE_ARG {
+                let value = args
+                    .next()
+                    .ok_or_else(|| ScenarioError::Invalid(format!("{} needs a value", arg)))?;
+                if arg == SCENARIO_ARG {
+                    scenario = Some(Self::load(Path::new(&value))?);
+                } else if arg == MAP_ARG {
+                    map = Some(PathBuf::from(value));
+                } else {
+                    let seed = value.parse().map_err(|_| {
+                        ScenarioError::Invalid(format!("{} needs a number", GENERATE_ARG))
+                    })?;
+                    generate = Some(seed);
+                }
+            }
+        }
+        let mut scenario = scenario.unwrap_or_default();
+        if let Some(map) = map {
+            scenario.map = map;
+            scenario.generate = None;
+        }
+        if let Some(seed) = generate {
+            scenario.generate_with_seed(seed);
+        }
+        Ok(scenario)
+    }
+
+    /// Generates the ground floor from `seed`, with the parameters the scenario has or the defaults.
+    pub fn generate_with_seed(&mut self, seed: u64) {
+        self.generate = Some(OfficeParams {
+            seed,
+            ..self.generate.take().unwrap_or_default()
+        });
+    }
+
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        for name in self.spawn.keys() {
+            if !SPAWNABLE.contains(&name.as_str()) {
+                return Err(ScenarioError::Invalid(format!(
+                    "cannot spawn '{}', known types are {:?}",
+                    name, SPAWNABLE
+                )));
+            }
+        }
+        if self.time_speed.is_nan() || self.time_speed <= 0.0 {
+            return Err(ScenarioError::Invalid(format!(
+                "time_speed must be positive, got {}",
+                self.time_speed
+            )));
+        }
+        if let Some(params) = &self.generate {
+            params
+                .validate()
+                .map_err(|e| ScenarioError::Invalid(e.to_string()))?;
+        }
+        self.start_minutes()?;
+        Ok(())
+    }
+
+    /// Every map file of the building, from the ground up. A generated ground floor has none.
+    pub fn maps(&self) -> Vec<PathBuf> {
+        let ground = self.generate.is_none().then_some(&self.map);
+        ground
+            .into_iter()
+            .chain(self.floors.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// What the ground floor comes from, for messages.
+    pub fn map_name(&self) -> String {
+        match &self.generate {
+            Some(params) => format!("generated from seed {}", params.seed),
+            None => self.map.display().to_string(),
+        }
+    }
+
+    /// Decor and cells of every floor, generated or read from the maps.
+    pub fn load_building(&self) -> Result<(Vec<AnyCellmap<DecorTile>>, Building), MapLoadError> {
+        let generated = match &self.generate {
+            Some(params) => OfficeLayout::generate(params)
+                .map_err(MapLoadError::Generator)?
+                .load()?,
+            None => Vec::new(),
+        };
+        let (decor, building) = stack_floors(generated, &self.maps())?;
+        Ok((decor, building.with_movement(self.movement)))
+    }
+
+    pub fn count(&self, item_type: &str) -> usize {
+        self.spawn.get(item_type).copied().unwrap_or(0)
+    }
+
+    pub fn start_minutes(&self) -> Result<usize, ScenarioError> {
+        let invalid = || {
+            ScenarioError::Invalid(format!(
+                "start_time must look like \"HH:MM\", got \"{}\"",
+                self.start_time
+            ))
+        };
+        let (hours, minutes) = self.start_time.split_once(':').ok_or_else(invalid)?;
+        let hours: usize = hours.trim().parse().map_err(|_| invalid())?;
+        let minutes: usize = minutes.trim().parse().map_err(|_| invalid())?;
+        if hours >= 24 || minutes >= 60 {
+            return Err(invalid());
+        }
+        Ok(hours * 60 + minutes)
+    }
+}
@@ -0,0 +1,188 @@
+// search is A* that stops when its budget runs out and goes on later from where it stopped:
+// long searches are spread over ticks and counted by the nodes they really expand, and what
+// was searched so far is plain data, so a save keeps it
+
+use std::{cell::Cell, cmp::Ordering, collections::BinaryHeap};
+
+use comfy::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::core::position::Ps;
+
+/// Nodes a search may still expand. Everything one search does is charged to it, a single
+/// step may run it over a little.
+#[derive(Debug)]
+pub struct Budget {
+    left: Cell<usize>,
+    spent: Cell<usize>,
+}
+
+impl Budget {
+    pub fn new(nodes: usize) -> Self {
+        Self {
+            left: Cell::new(nodes),
+            spent: Cell::new(0),
+        }
+    }
+
+    /// For searches that have to finish right away.
+    pub fn unlimited() -> Self {
+        Self::new(usize::MAX)
+    }
+
+    /// Charges `nodes` expanded nodes.
+    pub fn spend(&self, nodes: usize) {
+        self.left.set(self.left.get().saturating_sub(nodes));
+        self.spent.set(self.spent.get().saturating_add(nodes));
+    }
+
+    pub fn is_spent(&self) -> bool {
+        self.left.get() == 0
+    }
+
+    pub fn spent(&self) -> usize {
+        self.spent.get()
+    }
+}
+
+/// How far a search got with its budget.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Progress<T> {
+    Done(T),
+    /// The budget ran out first, the search goes on when run again.
+    Paused,
+}
+
+impl<T> Progress<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Progress<U> {
+        match self {
+            Progress::Done(result) => Progress::Done(f(result)),
+            Progress::Paused => Progress::Paused,
+        }
+    }
+
+    /// The result of a search run with an unlimited budget.
+    pub fn finished(self) -> T {
+        match self {
+            Progress::Done(result) => result,
+            Progress::Paused => panic!("search paused without a budget"),
+        }
+    }
+}
+
+// cell waiting to be expanded, the cheapest estimate goes first, of equal ones the
+// furthest from the start and then the one found first
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Open {
+    estimate: u32,
+    cost: u32,
+    order: u64,
+    ps: Ps,
+}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .cmp(&self.estimate)
+            .then(self.cost.cmp(&other.cost))
+            .then(other.order.cmp(&self.order))
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A* from one cell to the first one `success` accepts. What the cells around cost is asked
+/// for on every run, so it always comes from the building as it is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Search {
+    open: BinaryHeap<Open>,
+    // cheapest known way into every cell reached, the start comes from itself
+    #[serde(with = "crate::core::serialization::ps_map")]
+    reached: HashMap<Ps, (Ps, u32)>,
+    // grows with every cell queued, breaks ties the same way on every run
+    order: u64,
+}
+
+impl Search {
+    pub fn new(start: Ps) -> Self {
+        let mut reached = HashMap::default();
+        reached.insert(start, (start, 0));
+        let mut open = BinaryHeap::new();
+        open.push(Open {
+            estimate: 0,
+            cost: 0,
+            order: 0,
+            ps: start,
+        });
+        Self {
+            open,
+            reached,
+            order: 0,
+        }
+    }
+
+    /// Goes on until a cell is accepted, every cell is expanded or the budget runs out.
+    /// Done with the cells from the start to the accepted one and what walking them costs.
+    pub fn run<S, I>(
+        &mut self,
+        budget: &Budget,
+        mut successors: S,
+        heuristic: impl Fn(&Ps) -> u32,
+        success: impl Fn(&Ps) -> bool,
+    ) -> Progress<Option<(Vec<Ps>, u32)>>
+    where
+        S: FnMut(&Ps) -> I,
+        I: IntoIterator<Item = (Ps, u32)>,
+    {
+        while !budget.is_spent() {
+            let Some(Open { cost, ps, .. }) = self.open.pop() else {
+                return Progress::Done(None);
+            };
+            if cost > self.reached[&ps].1 {
+                // a cheaper way in was found after this one was queued
+                continue;
+            }
+            if success(&ps) {
+                return Progress::Done(Some((self.path_to(ps), cost)));
+            }
+            budget.spend(1);
+            for (next, step) in successors(&ps) {
+                let through = cost.saturating_add(step);
+                if let Some((_, known)) = self.reached.get(&next) {
+                    if *known <= through {
+                        continue;
+                    }
+                }
+                self.reached.insert(next, (ps, through));
+                self.order += 1;
+                self.open.push(Open {
+                    estimate: through.saturating_add(heuristic(&next)),
+                    cost: through,
+                    order: self.order,
+                    ps: next,
+                });
+            }
+        }
+        Progress::Paused
+    }
+
+    fn path_to(&self, end: Ps) -> Vec<Ps> {
+        let mut path = vec![end];
+        let mut here = end;
+        loop {
+            let (from, _) = self.reached[&here];
+            if from == here {
+                break;
+            }
+            path.push(from);
+            here = from;
+        }
+        path.reverse();
+        path
+    }
+}
@@ -0,0 +1,437 @@
+// simulation is everything that happens in the world, without any window, input or rendering
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use comfy::hecs::Component;
+use comfy::{
+    commands, world, world_mut, Entity, IntoParallelIterator, ParallelIterator, Transform,
+};
+
+use crate::behavior::actor::{Actor, Visitor, ACTOR_TYPES};
+use crate::behavior::creatures::PsOffsetProvider;
+use crate::behavior::{dog, item_types};
+use crate::behavior::messaging::communication::Communicator;
+use crate::behavior::sanity::Sanity;
+use crate::building::{Building, Floor};
+use crate::editor;
+use crate::core::position::Ps;
+use crate::core::Initializable;
+use crate::gameplay::gametime::Time;
+use crate::initializers::{self, create_bones};
+use crate::registry::EntityRegistry;
+use crate::persistence::{PersistentCell, CARRIABLE, CREATURE, INTERACTIVE};
+use crate::replay::{Command, RecordedCommand};
+use crate::savegame::{self, WorldSnapshot};
+use crate::scenario::Scenario;
+use crate::state::Reality;
+use crate::Bone;
+
+/// Length of one simulation tick in seconds, the same no matter how fast frames are drawn.
+pub const TICK_DT: f32 = 1.0 / 60.0;
+pub const SPEED_MULTIPLIERS: [f32; 5] = [0.5, 1.0, 2.0, 4.0, 8.0];
+const DEFAULT_SPEED: usize = 1;
+// a slow frame should not make the next one try to catch up forever
+const MAX_TICKS_PER_FRAME: u32 = 64;
+
+/// Turns frame time into a whole number of fixed ticks, keeping the leftover for the next frame.
+pub struct FixedTimestep {
+    accumulator: f32,
+    speed: usize,
+}
+
+impl FixedTimestep {
+    pub fn new() -> Self {
+        Self {
+            accumulator: 0.0,
+            speed: DEFAULT_SPEED,
+        }
+    }
+
+    pub fn multiplier(&self) -> f32 {
+        SPEED_MULTIPLIERS[self.speed]
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed + 1).min(SPEED_MULTIPLIERS.len() - 1);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = self.speed.saturating_sub(1);
+    }
+
+    /// How many ticks to run for a frame that took `frame_dt` seconds.
+    pub fn advance(&mut self, frame_dt: f32) -> u32 {
+        self.accumulator += frame_dt * self.multiplier();
+        let mut ticks = 0;
+        while self.accumulator >= TICK_DT && ticks < MAX_TICKS_PER_FRAME {
+            self.accumulator -= TICK_DT;
+            ticks += 1;
+        }
+        if ticks == MAX_TICKS_PER_FRAME {
+            self.accumulator = 0.0;
+        }
+        ticks
+    }
+
+    /// Drops the leftover time, so unpausing doesn't run ticks for the time spent paused.
+    pub fn reset(&mut self) {
+        self.accumulator = 0.0;
+    }
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// World simulation over `Reality` that can be stepped without a comfy window.
+///
+/// Entities still live in the global comfy world, so only one `Simulation`
+/// should exist per process.
+pub struct Simulation {
+    pub reality: Reality,
+    pub registry: EntityRegistry,
+    pub tick: u64,
+    pub dog_order: Option<Ps>,
+    /// Think one agent at a time in entity order instead of in parallel.
+    /// Slower, but together with the seed makes every run reproducible.
+    pub deterministic: bool,
+    /// Player commands waiting for the next tick.
+    pub queued: Vec<Command>,
+    /// Every command applied so far, with the tick it was applied on.
+    pub recorded: Vec<RecordedCommand>,
+}
+
+impl Simulation {
+    pub fn new(building: Building, seed: u64) -> Self {
+        let registry = EntityRegistry::with_defaults();
+        registry.spawn_map_objects(&building);
+        Self {
+            reality: Reality::new(building, seed),
+            registry,
+            tick: 0,
+            dog_order: None,
+            deterministic: false,
+            queued: Vec::new(),
+            recorded: Vec::new(),
+        }
+    }
+
+    /// World to record a replay from, actors think one at a time from here on:
+    /// thinking in parallel the replay would play back differently.
+    pub fn start_recording(&mut self) -> WorldSnapshot {
+        self.deterministic = true;
+        savegame::snapshot(self)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.reality.rng.lock().seed()
+    }
+
+    /// Random spawns, all of them on the ground floor.
+    pub fn populate(&mut self, dogs: isize, workers: isize, bones: usize, trashcans: usize) {
+        let building = &self.reality.building;
+        let cellmap = building.ground();
+        let (w, h) = cellmap.wh_usize();
+        let rng = self.reality.rng.get_mut();
+        create_bones(bones, building, rng);
+        initializers::create_trashcans(trashcans, building, rng);
+        initializers::spawn_dogs(dogs, cellmap, w, h, rng);
+        initializers::spawn_workers(workers, cellmap, w, h, rng);
+    }
+
+    /// Spawns the creatures and items placed on the object layers of every floor.
+    pub fn populate_placed(&mut self) {
+        let rng = self.reality.rng.get_mut();
+        let mut placed = 0;
+        for cellmap in self.reality.building.floors() {
+            for spawner in cellmap.objects.item_spawners.iter() {
+                initializers::spawn_items(spawner, cellmap, rng);
+            }
+            for spawn in cellmap.objects.spawns.iter() {
+                placed += 1;
+                let name = spawn.name.clone();
+                if spawn.item_type == item_types::DOG {
+                    let name = name.unwrap_or_else(|| format!("Placed dog {}", placed));
+                    initializers::spawn_dog(name, spawn.ps, spawn.speed, rng);
+                } else if spawn.item_type == item_types::OFFICE_WORKER {
+                    let name = name.unwrap_or_else(|| format!("Placed worker {}", placed));
+                    initializers::spawn_worker(name, spawn.ps, spawn.speed, rng);
+                }
+            }
+        }
+    }
+
+    /// Fresh world with the scenario's clock and population, the cast placed on the map
+    /// comes first and the scenario's random spawns are added on top.
+    pub fn from_scenario(scenario: &Scenario, building: Building, seed: u64) -> Self {
+        let mut sim = Self::new(building, seed);
+        let start = scenario.start_minutes().expect("scenario is validated on load");
+        sim.reality.time = Time::new(start);
+        sim.reality.time.speed = scenario.time_speed;
+        sim.populate_placed();
+        sim.populate(
+            scenario.count(item_types::DOG) as isize,
+            scenario.count(item_types::OFFICE_WORKER) as isize,
+            scenario.count(item_types::BONE),
+            scenario.count(item_types::TRASHCAN),
+        );
+        sim
+    }
+
+    /// Applies spawns and despawns queued with `commands()` since the last tick.
+    pub fn flush_commands(&self) {
+        let mut wrld = world_mut();
+        commands().run_on(&mut wrld);
+        wrld.flush();
+    }
+
+    pub fn submit(&mut self, command: Command) {
+        self.queued.push(command);
+    }
+
+    fn apply_commands(&mut self) {
+        for command in std::mem::take(&mut self.queued) {
+            self.recorded.push(RecordedCommand {
+                tick: self.tick,
+                command,
+            });
+            self.apply(command);
+        }
+    }
+
+    fn apply(&mut self, command: Command) {
+        let building = &mut self.reality.building;
+        match command {
+            Command::DogOrder(ps) => self.dog_order = Some(ps),
+            Command::Occupy(ps) => building.occupy_ps(&ps),
+            Command::Deoccupy(ps) => building.deoccupy_ps(&ps),
+            Command::RedirectDogs(_) | Command::MoveDogsTo(_) => {
+                let wrld = world();
+                let mut queried = wrld.query::<&dog::Dog>();
+                let mut dogs = queried.iter().collect::<Vec<_>>();
+                dogs.sort_by_key(|(entity, _)| *entity);
+                for (_entity, dog) in dogs {
+                    let mut sanity = dog.sa.sanity.lock();
+                    match command {
+                        Command::RedirectDogs(dir) => sanity.start_move_direction(dir, building),
+                        Command::MoveDogsTo(ps) => sanity.intend_go_to_shared(ps),
+                        _ => {}
+                    }
+                }
+            }
+            Command::Edit {
+                brush,
+                ps,
+                workplace,
+            } => match editor::edit(self, brush, ps, workplace) {
+                Ok(report) => println!("{:?} at {:?}: {}", brush, ps, report),
+                Err(e) => println!("ERROR: cannot edit the map: {}", e),
+            },
+        }
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        self.flush_commands();
+        self.reality.reservations.clear();
+        self.reality.timetable.advance(self.tick);
+        self.apply_commands();
+        self.registry.initialize_all(&mut self.reality);
+        update_bones(&mut self.reality);
+        update_all_actors(self, dt);
+        self.reality.paths.process(&self.reality.building, self.tick);
+        self.reality.time.tick(dt);
+        update_communication(&mut self.reality);
+        self.dog_order = None;
+        self.tick += 1;
+    }
+
+    pub fn run(&mut self, ticks: u64, dt: f32) {
+        for _ in 0..ticks {
+            self.step(dt);
+        }
+        self.flush_commands();
+    }
+
+    /// Hash of where every agent is, handy to check that two runs ended up in the same world.
+    pub fn fingerprint(&self) -> u64 {
+        let mut agents: Vec<(Entity, Ps)> = all_actor_cells()
+            .into_iter()
+            .map(|(ps, cell)| (cell.entity, ps))
+            .collect();
+        agents.sort_by_key(|(entity, _)| *entity);
+
+        let mut hasher = DefaultHasher::new();
+        for (entity, ps) in agents {
+            entity.to_bits().hash(&mut hasher);
+            (ps.x, ps.y).hash(&mut hasher);
+            // ground floor hashes as before multi-floor maps, so old fingerprints still match
+            if ps.floor != 0 {
+                ps.floor.hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+}
+
+pub fn update_init<T: Initializable + Component>(reality: &mut Reality) {
+    for (entity, (obj, transform)) in world().query::<(&mut T, &mut Transform)>().iter() {
+        if !obj.is_initialized() {
+            obj.initialize(&entity, transform, reality)
+        }
+    }
+}
+
+pub fn update_bones(reality: &mut Reality) {
+    for (entity, (_obj, transform)) in world().query::<(&mut Bone, &mut Transform)>().iter() {
+        let mut handles = reality.carriables.lock();
+        let handle = handles.get(&entity).unwrap();
+        transform.position = handle.get_exact_pos();
+        if handle.consumed {
+            commands().despawn(entity);
+            handles.remove(&entity);
+            reality.persistence.get_mut().remove(entity);
+            create_bones(1, &reality.building, &mut reality.rng.lock())
+        }
+    }
+}
+
+/// Indexes every map entity from scratch, for when the world changed all at once:
+/// a save was restored, the map was reloaded or edited.
+pub fn rebuild_persistence(reality: &mut Reality) {
+    let persistence = reality.persistence.get_mut();
+    persistence.clear();
+
+    for handle in reality.carriables.lock().values() {
+        if !handle.consumed {
+            let cell = PersistentCell::new(handle.item_id, handle.item_type, CARRIABLE, handle.offset);
+            persistence.put(handle.get_ps(), cell);
+        }
+    }
+    for handle in reality.interactive.lock().values() {
+        let cell = PersistentCell::new(handle.item_id, handle.item_type, INTERACTIVE, handle.get_offset());
+        persistence.put(handle.get_ps(), cell);
+    }
+    for (ps, cell) in all_actor_cells() {
+        persistence.put(ps, cell);
+    }
+}
+
+/// Every actor type in the world, in the order they think.
+pub fn update_all_actors(sim: &mut Simulation, dt: f32) {
+    for actor_type in ACTOR_TYPES.iter() {
+        (actor_type.update)(sim, dt);
+    }
+}
+
+fn all_actor_cells() -> Vec<(Ps, PersistentCell)> {
+    ACTOR_TYPES
+        .iter()
+        .flat_map(|actor_type| (actor_type.cells)())
+        .collect()
+}
+
+/// Every actor of every type in the order they think, with its mind unlocked.
+pub fn for_each_actor(mut f: impl FnMut(Entity, &mut Sanity)) {
+    for actor_type in ACTOR_TYPES.iter() {
+        (actor_type.visit)(&mut f);
+    }
+}
+
+pub fn visit_actors<A: Actor>(f: &mut Visitor<'_>) {
+    let wrld = world();
+    let mut queried = wrld.query::<&A>();
+    let mut actors = queried.iter().collect::<Vec<_>>();
+    actors.sort_by_key(|(entity, _)| *entity);
+    for (entity, actor) in actors {
+        f(entity, &mut actor.sa().sanity.lock());
+    }
+}
+
+/// Initialized actors of one type with the cell each of them stands on.
+pub fn actor_cells<A: Actor>() -> Vec<(Ps, PersistentCell)> {
+    world()
+        .query::<&A>()
+        .iter()
+        .filter(|(_, actor)| actor.is_initialized())
+        .map(|(entity, actor)| {
+            let cell = PersistentCell::new(entity, A::CLASS, CREATURE, actor.sa().get_offset());
+            (actor.sa().get_ps(), cell)
+        })
+        .collect()
+}
+
+/// Thinks for every actor of type `A` (in parallel unless deterministic),
+/// then moves them one by one in a shuffled order.
+pub fn update_actors<A: Actor>(sim: &mut Simulation, dt: f32) {
+    let wrld = world();
+    let mut queried = wrld.query::<(&mut A, &mut Transform, &mut Communicator, &mut Floor)>();
+    let mut items = queried.iter().collect::<Vec<_>>();
+    let reality = &sim.reality;
+    let dog_order = sim.dog_order;
+
+    let think = |data: (Entity, (&mut A, &mut Transform, &mut Communicator, &mut Floor))| {
+        let (entity, (actor, _, communication, _)) = data;
+        if !actor.is_initialized() {
+            return;
+        }
+        let sa = actor.sa_mut();
+        sa.sanity.lock().move_direction_if_can(dt);
+        if let Some(order) = dog_order {
+            sa.sanity.lock().intend_go_to_shared(order);
+        }
+        let intention_result = sa
+            .sanity
+            .lock()
+            .think_intention_level_if_not_moving(entity, reality);
+        sa.think_routine_level(intention_result, reality, entity, communication, dt);
+    };
+
+    if sim.deterministic {
+        items.sort_by_key(|(entity, _)| *entity);
+        items.into_iter().for_each(think);
+    } else {
+        items.into_par_iter().for_each(think);
+    }
+
+    let mut items_again = queried.iter().collect::<Vec<_>>();
+
+    items_again.sort_by_key(|(entity, _)| *entity);
+    sim.reality.rng.lock().shuffle(&mut items_again);
+
+    for (entity, (actor, transform, communication, floor)) in items_again.into_iter() {
+        transform.position = actor.sa().get_exact_pos();
+        communication.ps = actor.sa().get_ps();
+        floor.0 = communication.ps.floor;
+
+        let mut sanity = actor.sa().sanity.lock();
+
+        if sanity.carrier.has_anything() {
+            let ps_offset = sanity.mv.as_ps_offset_container();
+            sanity
+                .carrier
+                .update_positions(&sim.reality.carriables, &ps_offset)
+        }
+
+        let reality = &mut sim.reality;
+        sanity.think_movement_level_if_not_moving(
+            entity,
+            &mut reality.building,
+            &mut reality.reservations,
+            &mut reality.timetable,
+            reality.persistence.get_mut(),
+        );
+    }
+}
+
+pub fn update_communication(reality: &mut Reality) {
+    let mut floors = reality.comm_map.lock();
+    for map in floors.iter_mut() {
+        map.reset(comfy::HashSet::new());
+    }
+    for (entity, communication) in world().query::<&Communicator>().iter() {
+        communication.mark_position_on_map(entity, &mut floors)
+    }
+}
@@ -0,0 +1,312 @@
+// spacetime plans the next few steps of an agent in cells and ticks against what the others
+// already planned (windowed cooperative A*), so agents make way for where everybody is going
+// to be instead of walking into each other and waiting it out
+
+use comfy::{Entity, HashMap};
+use pathfinding::prelude::astar;
+use serde::{Deserialize, Serialize};
+
+use crate::{building::Building, core::position::Ps, simulation::TICK_DT, worldmap::Walker};
+
+/// Steps planned and held at once.
+pub const WINDOW: usize = 8;
+/// The next window is planned when this few planned steps are left.
+pub const REPLAN_AT: usize = WINDOW / 2;
+/// A window may take this many times longer than walking its steps, waits included.
+const HORIZON: u64 = 3;
+/// Ticks a cell stays held past the planned ones, agents don't arrive to the tick.
+const SLACK: u64 = 2;
+// plans count time in half steps, a diagonal step takes three: a bit more than its √2, so
+// agents arrive early rather than late and there are few different ticks to plan over
+const STRAIGHT: u64 = 2;
+const DIAGONAL: u64 = 3;
+
+/// A cell held by an agent from one tick to another, both included.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Hold {
+    #[serde(with = "crate::core::serialization::entity")]
+    pub entity: Entity,
+    pub ps: Ps,
+    pub from: u64,
+    pub to: u64,
+}
+
+impl Hold {
+    fn overlaps(&self, from: u64, to: u64) -> bool {
+        self.from <= to && from <= self.to
+    }
+}
+
+/// Cells agents are going to stand on during the next ticks.
+///
+/// Plans are made one agent at a time in the movement phase, whoever plans later walks
+/// around the ones planned before.
+#[derive(Debug, Default)]
+pub struct Timetable {
+    now: u64,
+    cells: HashMap<Ps, Vec<Hold>>,
+    // cells each agent holds
+    held: HashMap<Entity, Vec<Ps>>,
+}
+
+impl Timetable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Moves the clock to `tick`, holds that ended before it are forgotten.
+    pub fn advance(&mut self, tick: u64) {
+        self.now = tick;
+        self.cells.retain(|_, holds| {
+            holds.retain(|hold| hold.to >= tick);
+            !holds.is_empty()
+        });
+    }
+
+    /// Nobody but `entity` holds `ps` between the ticks.
+    pub fn is_free(&self, entity: Entity, ps: Ps, from: u64, to: u64) -> bool {
+        self.cells.get(&ps).is_none_or(|holds| {
+            holds
+                .iter()
+                .all(|hold| hold.entity == entity || !hold.overlaps(from, to))
+        })
+    }
+
+    /// `entity` has planned to be on `ps` at some point.
+    pub fn holds(&self, entity: Entity, ps: Ps) -> bool {
+        self.cells
+            .get(&ps)
+            .is_some_and(|holds| holds.iter().any(|hold| hold.entity == entity))
+    }
+
+    pub fn reserve(&mut self, hold: Hold) {
+        self.cells.entry(hold.ps).or_default().push(hold);
+        self.held.entry(hold.entity).or_default().push(hold.ps);
+    }
+
+    /// Holds every cell of a plan made by `plan_window`, `start` until it is left.
+    pub fn reserve_plan(&mut self, entity: Entity, start: Ps, steps: &[(Ps, u64)], step: u64) {
+        let mut from = self.now;
+        let mut here = start;
+        for (next, leave) in steps.iter() {
+            self.reserve(Hold {
+                entity,
+                ps: here,
+                from,
+                to: leave + SLACK,
+            });
+            from = *leave;
+            here = *next;
+        }
+        self.reserve(Hold {
+            entity,
+            ps: here,
+            from,
+            to: from + 2 * step + SLACK,
+        });
+    }
+
+    /// Forgets everything `entity` planned.
+    pub fn release(&mut self, entity: Entity) {
+        let Some(cells) = self.held.remove(&entity) else {
+            return;
+        };
+        for ps in cells {
+            if let Some(holds) = self.cells.get_mut(&ps) {
+                holds.retain(|hold| hold.entity != entity);
+                if holds.is_empty() {
+                    self.cells.remove(&ps);
+                }
+            }
+        }
+    }
+
+    /// Forgets what everybody planned, the clock stays.
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.held.clear();
+    }
+
+    /// Every hold in a stable order, for saving.
+    pub fn all_holds(&self) -> Vec<Hold> {
+        let mut holds: Vec<Hold> = self.cells.values().flatten().copied().collect();
+        holds.sort_by_key(|hold| (hold.entity, hold.ps.floor, hold.ps.x, hold.ps.y, hold.from));
+        holds
+    }
+
+    /// Holds saved with `all_holds` on top of the current ones.
+    pub fn restore(&mut self, holds: Vec<Hold>) {
+        for hold in holds {
+            self.reserve(hold);
+        }
+    }
+}
+
+/// Ticks one step takes at `speed` cells per second.
+pub fn step_ticks(speed: f32) -> u64 {
+    (1.0 / (speed * TICK_DT)).ceil().max(1.0) as u64
+}
+
+/// Steps from `start` to `goal` on the same floor with the tick each of them starts on,
+/// waiting in place where the way is held. `None` when the window can't get there in time.
+pub fn plan_window(
+    building: &Building,
+    timetable: &Timetable,
+    entity: Entity,
+    walker: Walker,
+    start: Ps,
+    goal: Ps,
+    step: u64,
+) -> Option<Vec<(Ps, u64)>> {
+    let now = timetable.now();
+    let deadline = STRAIGHT * HORIZON * WINDOW as u64;
+    let tick = |halves: u64| now + halves * step / STRAIGHT;
+    // agents that planned nothing stay where they are for the whole window
+    let open = |ps: Ps| {
+        let cell = building.get_pos(&ps);
+        !cell.status.occupied
+            && cell
+                .status
+                .occupant
+                .is_none_or(|occupant| occupant == entity || timetable.holds(occupant, ps))
+    };
+
+    // without diagonals a corner is turned with two straight steps
+    let corner = if building.movement().diagonal {
+        DIAGONAL
+    } else {
+        2 * STRAIGHT
+    };
+    // half steps to the goal at the least
+    let left = |ps: &Ps| ps.octile_distance(&goal, STRAIGHT as u32, corner as u32) as u64;
+
+    let successors = |&(ps, halves): &(Ps, u64)| {
+        let around = building
+            .steps_around(&ps, walker, false)
+            .into_iter()
+            .filter(|p| open(*p));
+        std::iter::once(ps)
+            .chain(around)
+            .map(|p| {
+                let took = if p.x != ps.x && p.y != ps.y {
+                    DIAGONAL
+                } else {
+                    STRAIGHT
+                };
+                (p, halves + took)
+            })
+            // no way to the goal by the deadline from there
+            .filter(|(p, next)| next + left(p) <= deadline)
+            .filter(|(p, next)| timetable.is_free(entity, *p, tick(halves), tick(*next) + SLACK))
+            .map(|(p, next)| ((p, next), next - halves))
+            .collect::<Vec<_>>()
+    };
+    let heuristic = |(ps, _): &(Ps, u64)| left(ps);
+    let (states, _) = astar(&(start, 0), successors, heuristic, |(ps, _)| *ps == goal)?;
+
+    let steps = states
+        .windows(2)
+        .filter(|pair| pair[0].0 != pair[1].0)
+        .map(|pair| (pair[1].0, tick(pair[0].1)))
+        .collect();
+    Some(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::building::testing::{agent, ps};
+
+    // a corridor with one pocket to step aside into
+    fn corridor() -> Building {
+        Building::from_rows(&["#########", "#.......#", "######.##", "#########"])
+    }
+
+    // holds of different agents on the same cell never overlap in time
+    fn assert_no_double_booking(timetable: &Timetable) {
+        for (ps, holds) in timetable.cells.iter() {
+            for (i, a) in holds.iter().enumerate() {
+                for b in holds[i + 1..].iter() {
+                    assert!(
+                        a.entity == b.entity || !a.overlaps(b.from, b.to),
+                        "{:?} is held by two agents at once: {:?} and {:?}",
+                        ps,
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn agents_yield_in_a_corridor() {
+        let building = corridor();
+        let mut timetable = Timetable::new();
+        let (a, b) = (agent(1), agent(2));
+        let step = 4;
+
+        // both walk the whole corridor, each to where the other starts
+        let east = plan_window(
+            &building,
+            &timetable,
+            a,
+            Walker::Dog,
+            ps(1, 1),
+            ps(7, 1),
+            step,
+        )
+        .expect("the corridor is free");
+        timetable.reserve_plan(a, ps(1, 1), &east, step);
+        let west = plan_window(
+            &building,
+            &timetable,
+            b,
+            Walker::Dog,
+            ps(7, 1),
+            ps(1, 1),
+            step,
+        )
+        .expect("there is a pocket to wait in");
+        timetable.reserve_plan(b, ps(7, 1), &west, step);
+
+        assert_eq!(east.last().map(|(p, _)| *p), Some(ps(7, 1)));
+        assert_eq!(west.last().map(|(p, _)| *p), Some(ps(1, 1)));
+        // the one planning later makes way, there is no way past without the pocket
+        assert!(west.iter().any(|(p, _)| *p == ps(6, 2)));
+        assert_no_double_booking(&timetable);
+    }
+
+    #[test]
+    fn planned_cells_are_not_booked_twice() {
+        let building = Building::from_rows(&["......", "......", "......", "......"]);
+        let mut timetable = Timetable::new();
+        let step = 4;
+        let trips = [
+            (ps(0, 0), ps(5, 3)),
+            (ps(5, 3), ps(0, 0)),
+            (ps(0, 3), ps(5, 0)),
+            (ps(5, 0), ps(0, 3)),
+            (ps(2, 0), ps(2, 3)),
+        ];
+        for (id, (start, goal)) in trips.into_iter().enumerate() {
+            let entity = agent(id as u64);
+            let plan = plan_window(
+                &building,
+                &timetable,
+                entity,
+                Walker::Dog,
+                start,
+                goal,
+                step,
+            )
+            .expect("the room is big enough for everybody");
+            timetable.reserve_plan(entity, start, &plan, step);
+        }
+        assert_no_double_booking(&timetable);
+    }
+}
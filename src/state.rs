@@ -5,50 +5,108 @@ use crate::{
         carriable::carriableitem::CarriableItems, interactive::InteractiveObjects,
         messaging::MessagingHosts,
     },
-    core::{anycellmap::AnyCellmap, position::Ps},
+    building::Building,
+    core::{anycellmap::AnyCellmap, position::Ps, rng::WorldRng},
+    editor::MapEditor,
     gameplay::gametime::Time,
+    hotreload::MapWatcher,
+    pathqueue::PathQueue,
     persistence::Persistence,
+    reservation::Reservations,
+    savegame::WorldSnapshot,
+    scenario::Scenario,
+    simulation::{FixedTimestep, Simulation},
+    spacetime::Timetable,
+    tiledreader::DecorTile,
     worldmap::Cellmap,
 };
 
 pub struct Reality {
-    pub cellmap: Cellmap,
+    pub building: Building,
     pub carriables: Arc<CarriableItems>,
     pub messaging: Arc<MessagingHosts>,
     pub interactive: Arc<InteractiveObjects>,
-    pub comm_map: Arc<Mutex<AnyCellmap<HashSet<Entity>>>>,
-    pub persistence: Persistence,
+    /// Who is where for communication, one map per floor.
+    pub comm_map: Arc<Mutex<Vec<AnyCellmap<HashSet<Entity>>>>>,
+    pub persistence: Mutex<Persistence>,
+    /// Paths agents asked for and are waiting on.
+    pub paths: PathQueue,
+    pub reservations: Reservations,
+    /// Cells agents planned to walk over the next ticks.
+    pub timetable: Timetable,
     pub time: Time,
+    pub rng: Mutex<WorldRng>,
 }
 
 impl Reality {
-    pub fn new(cellmap: Cellmap) -> Self {
-        let wh = cellmap.wh_i32();
+    pub fn new(building: Building, seed: u64) -> Self {
+        let comm_map = communication_maps(&building);
+        let persistence = Persistence::new(building.sizes());
         Self {
-            cellmap,
+            building,
             carriables: Arc::new(Mutex::new(HashMap::new())),
             messaging: Arc::new(Mutex::new(HashMap::new())),
             interactive: Arc::new(Mutex::new(HashMap::new())),
-            persistence: Persistence::new(),
+            persistence: Mutex::new(persistence),
+            paths: PathQueue::new(),
+            reservations: Reservations::new(),
+            timetable: Timetable::new(),
             time: Time::new(16 * 60),
-            comm_map: Arc::new(Mutex::new(AnyCellmap::new(&HashSet::new(), wh.0, wh.1))),
+            comm_map: Arc::new(Mutex::new(comm_map)),
+            rng: Mutex::new(WorldRng::new(seed)),
         }
     }
+
+    /// Swaps the building for another one and gives back the old one,
+    /// whatever is sized after the map follows the new one.
+    pub fn replace_building(&mut self, building: Building) -> Building {
+        *self.comm_map.lock() = communication_maps(&building);
+        self.persistence.get_mut().resize(building.sizes());
+        std::mem::replace(&mut self.building, building)
+    }
+}
+
+fn communication_maps(building: &Building) -> Vec<AnyCellmap<HashSet<Entity>>> {
+    building
+        .floors()
+        .iter()
+        .map(|floor| {
+            let wh = floor.wh_i32();
+            AnyCellmap::new(&HashSet::new(), wh.0, wh.1)
+        })
+        .collect()
 }
 
 pub struct WorldState {
-    pub reality: Reality,
+    pub sim: Simulation,
+    pub scenario: Scenario,
+    pub clock: FixedTimestep,
+    /// World as it was when the current replay recording started.
+    pub replay_start: Option<WorldSnapshot>,
+    /// Reloads the map when a designer saves it.
+    pub map_watcher: MapWatcher,
+    /// Set while paused to run exactly one tick on the next frame.
+    pub step_requested: bool,
     pub x: i32,
     pub y: i32,
     pub selected_cell: Ps,
     pub selected: bool,
+    /// Floor shown in the window, clicks land on it.
+    pub floor: usize,
     pub initialized: bool,
     pub entities_initialized: bool,
-    pub dog_order: Option<Ps>,
     pub paused: bool,
+    /// Edit mode, clicks change the map instead of selecting cells.
+    pub editor: MapEditor,
+    /// Decorations of every floor as loaded, kept to save the edited map with them.
+    pub decor: Vec<AnyCellmap<DecorTile>>,
 }
 
 impl WorldState {
+    pub fn shown_floor(&self) -> &Cellmap {
+        self.sim.reality.building.floor(self.floor)
+    }
+
     pub fn select_cell(&mut self, position: Ps) {
         self.selected_cell = position;
         self.selected = true;
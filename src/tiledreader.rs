@@ -1,67 +1,458 @@
-use comfy::{ivec2, num_traits::ToPrimitive};
-use std::io::Cursor;
-use tiled::{Loader, PropertyValue, Tileset};
+use comfy::{ivec2, num_traits::ToPrimitive, vec2, HashMap, IVec2, Vec2};
+use std::{
+    fmt, fs,
+    io::{self, Cursor},
+    path::{Path, PathBuf},
+};
+use tiled::{
+    ChunkData, Layer, LayerTile, LayerType, Loader, ObjectShape, PropertyValue, TileLayer,
+};
 
 use crate::{
-    core::anycellmap::AnyCellmap,
-    utils::basic::get_file_name,
-    worldmap::{Cell, Cellmap, TileReference},
+    behavior::item_types,
+    building::{Building, Connector},
+    core::{
+        animation::{BasicTileAnimation, TileAnimation, TileFrame},
+        anycellmap::AnyCellmap,
+        position::Ps,
+    },
+    generator::GenerateError,
+    scenario::DEFAULT_MAP,
+    utils::{
+        basic::get_file_name,
+        fileutils::{normalize_path, read_tar},
+    },
+    worldmap::{
+        Cell, Cellmap, ItemSpawner, MapObjects, SpawnPoint, TileReference, Zone, BASE_COST,
+    },
 };
 
+// classes that block the cell unless the tile says otherwise with a "passable" property
 const IMPASSABLE_TILES: [&'static str; 3] = ["block", "wall", "conputer"];
 
-pub struct MyTiledReader;
+pub const BG_LAYER: usize = 0;
+pub const CELL_LAYER: usize = 1;
+pub const TOP_LAYER: usize = 2;
+
+const ARCHIVE_EXTENSION: &str = "tar";
+
+pub const ZONE_CLASS: &str = "zone";
+const SPAWNED_CREATURES: [&str; 2] = [item_types::DOG, item_types::OFFICE_WORKER];
+const SPAWNED_ITEMS: [&str; 2] = [item_types::BONE, item_types::TRASHCAN];
+
+#[derive(Debug)]
+pub enum MapLoadError {
+    Io(PathBuf, io::Error),
+    Tiled(tiled::Error),
+    /// The archive has no map in it, or more than one and none was named.
+    NoMap(PathBuf),
+    /// An infinite map without a single tile.
+    EmptyMap,
+    MissingLayer(usize),
+    WrongLayerKind {
+        index: usize,
+        expected: &'static str,
+        found: &'static str,
+    },
+    UnknownTile {
+        tileset: String,
+        tile: u32,
+    },
+    BadAnimation {
+        tileset: String,
+        tile: u32,
+        reason: String,
+    },
+    BadTileProperty {
+        tileset: String,
+        tile: u32,
+        reason: String,
+    },
+    BadObject {
+        id: u32,
+        name: String,
+        reason: String,
+    },
+    Generator(GenerateError),
+}
+
+impl fmt::Display for MapLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapLoadError::Io(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            MapLoadError::Tiled(e) => write!(f, "{}", e),
+            MapLoadError::NoMap(path) => {
+                write!(f, "cannot tell which map to load from {}", path.display())
+            }
+            MapLoadError::EmptyMap => write!(f, "map has no tiles"),
+            MapLoadError::MissingLayer(index) => write!(f, "map has no layer {}", index),
+            MapLoadError::WrongLayerKind {
+                index,
+                expected,
+                found,
+            } => write!(
+                f,
+                "layer {} should be a {}, not a {}",
+                index, expected, found
+            ),
+            MapLoadError::UnknownTile { tileset, tile } => {
+                write!(f, "tile {} is not in tileset '{}'", tile, tileset)
+            }
+            MapLoadError::BadAnimation {
+                tileset,
+                tile,
+                reason,
+            } => write!(
+                f,
+                "tile {} of tileset '{}' has a broken animation: {}",
+                tile, tileset, reason
+            ),
+            MapLoadError::BadTileProperty {
+                tileset,
+                tile,
+                reason,
+            } => write!(f, "tile {} of tileset '{}': {}", tile, tileset, reason),
+            MapLoadError::BadObject { id, name, reason } => {
+                write!(f, "object {} '{}': {}", id, name, reason)
+            }
+            MapLoadError::Generator(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MapLoadError {}
+
+/// Serves map files either from a directory or from files held in memory, like the insides
+/// of an uncompressed tar archive, paths are relative to the directory or the archive root.
+pub enum MyTiledReader {
+    Dir(PathBuf),
+    Archive(HashMap<PathBuf, Vec<u8>>),
+}
+
+impl MyTiledReader {
+    pub fn archive(path: &Path) -> io::Result<Self> {
+        Ok(MyTiledReader::Archive(read_tar(&fs::read(path)?)?))
+    }
+
+    /// Maps available in the archive, sorted.
+    pub fn maps(&self) -> Vec<PathBuf> {
+        match self {
+            MyTiledReader::Dir(_) => Vec::new(),
+            MyTiledReader::Archive(files) => {
+                let mut maps: Vec<PathBuf> = files
+                    .keys()
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "tmx"))
+                    .cloned()
+                    .collect();
+                maps.sort();
+                maps
+            }
+        }
+    }
+}
 
 impl tiled::ResourceReader for MyTiledReader {
-    type Resource = Cursor<&'static [u8]>;
-    type Error = std::io::Error;
-
-    // really dumb example implementation that just keeps resources in memory
-    fn read_from(
-        &mut self,
-        path: &std::path::Path,
-    ) -> std::result::Result<Self::Resource, Self::Error> {
-        if path == std::path::Path::new("my_map.tmx") {
-            Ok(Cursor::new(include_bytes!(concat!(
-                env!("CARGO_MANIFEST_DIR"),
-                "/assets/unnamed.tmx"
-            ))))
-        } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "file not found",
-            ))
+    type Resource = Cursor<Vec<u8>>;
+    type Error = io::Error;
+
+    fn read_from(&mut self, path: &Path) -> std::result::Result<Self::Resource, Self::Error> {
+        match self {
+            MyTiledReader::Dir(root) => fs::read(root.join(path)).map(Cursor::new),
+            MyTiledReader::Archive(files) => files
+                .get(&normalize_path(path))
+                .cloned()
+                .map(Cursor::new)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} is not in the archive", path.display()),
+                    )
+                }),
         }
     }
 }
 
-pub fn read_tilemap_default() -> tiled::Map {
-    let mut loader = Loader::new();
+pub fn read_tilemap_default() -> Result<tiled::Map, MapLoadError> {
+    read_tilemap(Path::new(DEFAULT_MAP))
+}
+
+/// Reads a map from disk. A path going through a `.tar` file, like `maps.tar/office.tmx`,
+/// is read from inside the archive, and the archive alone works if it holds a single map.
+pub fn read_tilemap(path: &Path) -> Result<tiled::Map, MapLoadError> {
+    let (reader, map_path) = match split_archive_path(path) {
+        Some((archive, inside)) => {
+            let reader = MyTiledReader::archive(&archive)
+                .map_err(|e| MapLoadError::Io(archive.clone(), e))?;
+            let inside = if inside.as_os_str().is_empty() {
+                match reader.maps().as_slice() {
+                    [single] => single.clone(),
+                    _ => return Err(MapLoadError::NoMap(archive)),
+                }
+            } else {
+                inside
+            };
+            (reader, inside)
+        }
+        None => {
+            let dir = path.parent().unwrap_or(Path::new("")).to_owned();
+            let file = path.file_name().map(PathBuf::from).unwrap_or_default();
+            (MyTiledReader::Dir(dir), file)
+        }
+    };
+    let mut loader = Loader::with_cache_and_reader(tiled::DefaultResourceCache::new(), reader);
+    loader.load_tmx_map(&map_path).map_err(MapLoadError::Tiled)
+}
+
+/// Decorations and cells of every floor in a map file, from the layers the game expects.
+/// A map with top-level layer groups has a floor per group, from the ground up,
+/// and the layers inside a group are laid out like those of a map without groups.
+pub fn load_map(path: &Path) -> Result<Vec<(AnyCellmap<DecorTile>, Cellmap)>, MapLoadError> {
+    map_floors(&read_tilemap(path)?)
+}
+
+/// Floors of a map that only exists in memory, like a generated one. `name` stands for
+/// its path, files the map refers to can't be read.
+pub fn load_map_text(
+    name: &str,
+    text: &str,
+) -> Result<Vec<(AnyCellmap<DecorTile>, Cellmap)>, MapLoadError> {
+    let files = HashMap::from_iter([(PathBuf::from(name), text.as_bytes().to_vec())]);
+    let mut loader = Loader::with_cache_and_reader(
+        tiled::DefaultResourceCache::new(),
+        MyTiledReader::Archive(files),
+    );
     let map = loader
-        .load_tmx_map(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/level0.tmx"))
-        .unwrap();
-    return map;
+        .load_tmx_map(Path::new(name))
+        .map_err(MapLoadError::Tiled)?;
+    map_floors(&map)
+}
+
+fn map_floors(map: &tiled::Map) -> Result<Vec<(AnyCellmap<DecorTile>, Cellmap)>, MapLoadError> {
+    let bounds = MapBounds::of(map)?;
+    floor_layers(map)
+        .iter()
+        .map(|layers| {
+            let decor = create_decorations_map(layers, bounds, BG_LAYER, TOP_LAYER)?;
+            let cellmap = create_cellmap(map, layers, bounds, CELL_LAYER)?;
+            Ok((decor, cellmap))
+        })
+        .collect()
+}
+
+/// Building stacked from the floors of `paths`, the first map is at the bottom.
+pub fn load_building(
+    paths: &[PathBuf],
+) -> Result<(Vec<AnyCellmap<DecorTile>>, Building), MapLoadError> {
+    stack_floors(Vec::new(), paths)
+}
+
+/// Building with `below` at the bottom and the floors of `paths` stacked on top of it.
+pub fn stack_floors(
+    below: Vec<(AnyCellmap<DecorTile>, Cellmap)>,
+    paths: &[PathBuf],
+) -> Result<(Vec<AnyCellmap<DecorTile>>, Building), MapLoadError> {
+    let mut decor = Vec::new();
+    let mut floors = Vec::new();
+    for loaded in std::iter::once(Ok(below)).chain(paths.iter().map(|path| load_map(path))) {
+        for (floor_decor, cellmap) in loaded? {
+            decor.push(floor_decor);
+            floors.push(cellmap);
+        }
+    }
+    Ok((decor, Building::new(floors)))
+}
+
+fn floor_layers<'map>(map: &'map tiled::Map) -> Vec<Vec<Layer<'map>>> {
+    let groups: Vec<Vec<Layer>> = map
+        .layers()
+        .filter_map(|layer| layer.as_group_layer())
+        .map(|group| group.layers().collect())
+        .collect();
+    if groups.is_empty() {
+        vec![map.layers().collect()]
+    } else {
+        groups
+    }
+}
+
+// layers of the map with the insides of groups in place of the groups
+fn all_layers<'map>(map: &'map tiled::Map) -> Vec<Layer<'map>> {
+    map.layers()
+        .flat_map(|layer| match layer.as_group_layer() {
+            Some(group) => group.layers().collect(),
+            None => vec![layer],
+        })
+        .collect()
+}
+
+/// File on disk the map at `path` is read from, the archive for maps inside one.
+pub fn map_file(path: &Path) -> PathBuf {
+    match split_archive_path(path) {
+        Some((archive, _)) => archive,
+        None => path.to_owned(),
+    }
+}
+
+// the archive is the first file along the path with a .tar extension
+fn split_archive_path(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    path.ancestors()
+        .find(|ancestor| {
+            ancestor
+                .extension()
+                .is_some_and(|ext| ext == ARCHIVE_EXTENSION)
+                && ancestor.is_file()
+        })
+        .map(|archive| {
+            let inside = path.strip_prefix(archive).unwrap_or(Path::new(""));
+            (archive.to_owned(), inside.to_owned())
+        })
+}
+
+fn tile_layer<'map>(layers: &[Layer<'map>], index: usize) -> Result<TileLayer<'map>, MapLoadError> {
+    let layer = layers.get(index).ok_or(MapLoadError::MissingLayer(index))?;
+    let found = match layer.layer_type() {
+        LayerType::Tiles(tiles) => return Ok(tiles),
+        LayerType::Objects(_) => "object layer",
+        LayerType::Image(_) => "image layer",
+        LayerType::Group(_) => "group layer",
+    };
+    Err(MapLoadError::WrongLayerKind {
+        index,
+        expected: "tile layer",
+        found,
+    })
+}
+
+/// Part of the map, in Tiled tile coordinates, that becomes the cellmap.
+/// Finite maps are taken whole, infinite ones are cut to the chunks that have tiles.
+#[derive(Debug, Clone, Copy)]
+pub struct MapBounds {
+    /// Top left tile, where Tiled has its (0, 0) on finite maps.
+    pub origin: IVec2,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl MapBounds {
+    pub fn of(map: &tiled::Map) -> Result<Self, MapLoadError> {
+        if !map.infinite() {
+            return Ok(Self {
+                origin: ivec2(0, 0),
+                width: map.width as i32,
+                height: map.height as i32,
+            });
+        }
+        let (chunk_w, chunk_h) = (ChunkData::WIDTH as i32, ChunkData::HEIGHT as i32);
+        let mut corners: Option<(IVec2, IVec2)> = None;
+        for layer in all_layers(map) {
+            if let Some(TileLayer::Infinite(layer)) = layer.as_tile_layer() {
+                for ((cx, cy), chunk) in layer.chunks() {
+                    for (x, y) in (0..chunk_w).flat_map(|x| (0..chunk_h).map(move |y| (x, y))) {
+                        if chunk.get_tile(x, y).is_none() {
+                            continue;
+                        }
+                        let tile = ivec2(cx * chunk_w + x, cy * chunk_h + y);
+                        corners = Some(match corners {
+                            Some((min, max)) => (min.min(tile), max.max(tile)),
+                            None => (tile, tile),
+                        });
+                    }
+                }
+            }
+        }
+        let (min, max) = corners.ok_or(MapLoadError::EmptyMap)?;
+        Ok(Self {
+            origin: min,
+            width: max.x - min.x + 1,
+            height: max.y - min.y + 1,
+        })
+    }
+
+    // y goes up in the game and down in Tiled
+    fn tile_at<'map>(&self, layer: &TileLayer<'map>, x: i32, y: i32) -> Option<LayerTile<'map>> {
+        layer.get_tile(self.origin.x + x, self.origin.y + self.height - y - 1)
+    }
+}
+
+// ids are per tileset, so every tile is looked up in the tileset it was placed from
+fn tile_data<'map>(tile: &LayerTile<'map>) -> Result<tiled::Tile<'map>, MapLoadError> {
+    tile.get_tile().ok_or_else(|| MapLoadError::UnknownTile {
+        tileset: tile.get_tileset().name.clone(),
+        tile: tile.id(),
+    })
+}
+
+// animation made in the Tiled editor, or else the "animated" property with "frames" frames
+// laid out in the tile's own atlas
+fn tile_animation(
+    tile: &LayerTile,
+    tdata: &tiled::Tile,
+) -> Result<Option<TileAnimation>, MapLoadError> {
+    let tileset = tile.get_tileset();
+    let broken = |reason: String| MapLoadError::BadAnimation {
+        tileset: tileset.name.clone(),
+        tile: tile.id(),
+        reason,
+    };
+    if let Some(frames) = &tdata.animation {
+        if frames.is_empty() {
+            return Err(broken("no frames".to_owned()));
+        }
+        let frames = frames
+            .iter()
+            .map(|frame| {
+                let image = tileset
+                    .get_tile(frame.tile_id)
+                    .and_then(|frame_tile| frame_tile.image.clone())
+                    .and_then(|image| image.source.to_str().and_then(get_file_name))
+                    .ok_or_else(|| {
+                        broken(format!(
+                            "frame tile {} is missing or has no image",
+                            frame.tile_id
+                        ))
+                    })?;
+                Ok(TileFrame {
+                    image,
+                    duration_ms: frame.duration,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        return Ok(Some(TileAnimation::Frames {
+            key: format!("{}:{}", tileset.name, tile.id()),
+            frames,
+        }));
+    }
+    if !tdata.properties.contains_key("animated") {
+        return Ok(None);
+    }
+    match tdata.properties.get("frames") {
+        Some(PropertyValue::IntValue(frames)) if *frames > 0 => Ok(Some(TileAnimation::Atlas(
+            BasicTileAnimation::new(*frames, 0.1),
+        ))),
+        Some(PropertyValue::IntValue(frames)) => Err(broken(format!("{} frames", frames))),
+        Some(other) => Err(broken(format!("frames should be an int, got {:?}", other))),
+        None => Err(broken("no frames property".to_owned())),
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct DecorTile {
     pub bg: Option<String>,
     pub top: Option<String>,
-    pub animated_bg: Option<crate::core::animation::BasicTileAnimation>,
-    pub animated_top: Option<crate::core::animation::BasicTileAnimation>,
+    pub animated_bg: Option<TileAnimation>,
+    pub animated_top: Option<TileAnimation>,
 }
 
 pub fn create_decorations_map(
-    map: &tiled::Map,
+    layers: &[Layer],
+    bounds: MapBounds,
     index_bg: usize,
     index_top: usize,
-) -> AnyCellmap<DecorTile> {
-    let layer_bg = map.get_layer(index_bg).unwrap().as_tile_layer().unwrap();
-    let layer_top = map.get_layer(index_top).unwrap().as_tile_layer().unwrap();
-    let base_tileset: &Tileset = map.tilesets().last().unwrap();
+) -> Result<AnyCellmap<DecorTile>, MapLoadError> {
+    let layer_bg = tile_layer(layers, index_bg)?;
+    let layer_top = tile_layer(layers, index_top)?;
 
-    let max_x: i32 = layer_bg.height().unwrap().try_into().unwrap();
-    let max_y: i32 = layer_bg.width().unwrap().try_into().unwrap();
+    let (max_x, max_y) = (bounds.width, bounds.height);
     let default_decor_tile = DecorTile {
         bg: None,
         top: None,
@@ -71,95 +462,60 @@ pub fn create_decorations_map(
     let mut map = AnyCellmap::new(&default_decor_tile, max_x, max_y);
     for y in 0..max_y {
         for x in 0..max_x {
-            if let Some(tile) = layer_bg.get_tile(x, max_y - y - 1) {
-                let index = tile.id();
-                let tdata = base_tileset.get_tile(index).unwrap();
-                let image = &tdata.image.as_ref();
-                if let Some(img) = image {
-                    map.get_xy_mut(x, y).bg =
-                        img.source.to_str().map(|f| get_file_name(f)).flatten();
-                    if tdata.properties.contains_key("animated") {
-                        // ohohoho, we have an aniimated tile here!
-                        if let PropertyValue::IntValue(frames) =
-                            tdata.properties.get("frames").unwrap()
-                        {
-                            map.get_xy_mut(x, y).animated_bg = Some(
-                                crate::core::animation::BasicTileAnimation::new(*frames, 0.1),
-                            );
-                        }
-                    }
-                }
-            }
-            if let Some(tile) = layer_top.get_tile(x, max_y - y - 1) {
-                let index = tile.id();
-                let tdata = base_tileset.get_tile(index).unwrap();
-                let image = &tdata.image.as_ref();
-                if let Some(img) = image {
-                    map.get_xy_mut(x, y).top =
-                        img.source.to_str().map(|f| get_file_name(f)).flatten();
-                    if tdata.properties.contains_key("animated") {
-                        // ohohoho, we have an aniimated tile here!
-                        if let PropertyValue::IntValue(frames) =
-                            tdata.properties.get("frames").unwrap()
-                        {
-                            map.get_xy_mut(x, y).animated_top = Some(
-                                crate::core::animation::BasicTileAnimation::new(*frames, 0.1),
-                            );
-                        }
-                    }
-                }
-            }
+            let (bg, animated_bg) = decor_image(bounds.tile_at(&layer_bg, x, y))?;
+            let (top, animated_top) = decor_image(bounds.tile_at(&layer_top, x, y))?;
+            let decor = map.get_xy_mut(x, y);
+            decor.bg = bg;
+            decor.animated_bg = animated_bg;
+            decor.top = top;
+            decor.animated_top = animated_top;
         }
     }
-    map
+    Ok(map)
 }
 
-pub fn create_cellmap(map: tiled::Map, index: usize) -> Cellmap {
-    let layer0 = map.get_layer(index).unwrap().as_tile_layer().unwrap();
-
-    let base_tileset: &Tileset = map.tilesets().last().unwrap();
-    for tileset in map.tilesets() {
-        println!("{:?}", tileset);
-        // base_tileset = tileset;
+// image file name and animation of a decoration tile, nothing where there is nothing to draw
+fn decor_image(
+    tile: Option<LayerTile>,
+) -> Result<(Option<String>, Option<TileAnimation>), MapLoadError> {
+    let Some(tile) = tile else {
+        return Ok((None, None));
+    };
+    let tdata = tile_data(&tile)?;
+    match tdata.image.as_ref() {
+        Some(img) => Ok((
+            img.source.to_str().and_then(get_file_name),
+            tile_animation(&tile, &tdata)?,
+        )),
+        None => Ok((None, None)),
     }
-    let max_x: i32 = layer0.height().unwrap().try_into().unwrap();
-    let max_y: i32 = layer0.width().unwrap().try_into().unwrap();
+}
+
+pub fn create_cellmap(
+    map: &tiled::Map,
+    layers: &[Layer],
+    bounds: MapBounds,
+    index: usize,
+) -> Result<Cellmap, MapLoadError> {
+    let layer0 = tile_layer(layers, index)?;
+
+    let (max_x, max_y) = (bounds.width, bounds.height);
     let total = (max_x * max_y).to_usize().unwrap();
     let mut vec = crate::utils::create_vec::<Cell>(total);
     for y in 0..max_y {
         for x in 0..max_x {
             let position = (x, y);
-            let tile_opt = layer0.get_tile(x, max_y - y - 1);
-            let (tileref, passable): (Option<TileReference>, bool) = match tile_opt {
+            let tileref = match &bounds.tile_at(&layer0, x, y) {
                 Some(tile) => {
                     let index = tile.id();
-                    let tdata = base_tileset.get_tile(index).unwrap();
+                    let tdata = tile_data(tile)?;
                     let image = &tdata.image.as_ref();
                     let h = image.map(|f| f.height).unwrap_or(48);
                     let w = image.map(|f| f.width).unwrap_or(48);
-                    let data = tile.get_tile().unwrap().properties.clone();
-                    let klass = tile
-                        .get_tile()
-                        .unwrap()
-                        .user_type
-                        .clone()
-                        .unwrap_or("".to_string());
-                    let passable = !crate::utils::is_string_in_array(&klass, &IMPASSABLE_TILES);
-                    let tr = TileReference {
-                        animated: if tdata.properties.contains_key("animated") {
-                            // ohohoho, we have an aniimated tile here!
-                            if let PropertyValue::IntValue(frames) =
-                                tdata.properties.get("frames").unwrap()
-                            {
-                                Some(crate::core::animation::BasicTileAnimation::new(
-                                    *frames, 0.1,
-                                ))
-                            } else {
-                                None
-                            }
-                        } else {
-                            None
-                        },
+                    let data = tdata.properties.clone();
+                    let klass = tdata.user_type.clone().unwrap_or("".to_string());
+                    Some(TileReference {
+                        animated: tile_animation(tile, &tdata)?,
                         size: ivec2(w, h),
                         klass,
                         tile_image: get_file_name(
@@ -171,21 +527,177 @@ pub fn create_cellmap(map: tiled::Map, index: usize) -> Cellmap {
                         )
                         .unwrap(),
                         tile_index: index,
+                        tileset: tile.get_tileset().name.clone(),
                         tile_name: image
                             .map(|f| f.source.to_str())
                             .flatten()
                             .unwrap_or("wtf.png")
                             .to_owned(),
                         props: data,
-                    };
-                    (Some(tr), passable)
+                    })
                 }
-                None => (None, true),
+                None => None,
             };
             let cell_index = y * max_x + x;
-            let cell = Cell::new(position, passable, tileref);
-            vec[cell_index.to_usize().unwrap()] = Some(cell);
+            vec[cell_index.to_usize().unwrap()] = Some(tile_cell(position, tileref)?);
         }
     }
-    return Cellmap::new(vec, max_x, max_y);
+    let mut cellmap = Cellmap::new(vec, max_x, max_y).with_origin(bounds.origin);
+    cellmap.objects = read_objects(map, layers, &cellmap)?;
+    Ok(cellmap)
+}
+
+/// Cell holding `tile`, or plain floor without one, with the walking rules of the tile.
+pub fn tile_cell(position: (i32, i32), tile: Option<TileReference>) -> Result<Cell, MapLoadError> {
+    let mut cell = Cell::new(position, true, None);
+    if let Some(tile) = &tile {
+        cell.passable = !crate::utils::is_string_in_array(&tile.klass, &IMPASSABLE_TILES);
+        cell.connector = Connector::from_class(&tile.klass);
+        apply_walking_props(&mut cell, tile)?;
+    }
+    cell.reference = tile;
+    Ok(cell)
+}
+
+// walking rules from tile properties: "passable" overrides the guess made from the class,
+// "cost" is relative to a plain floor and "dog_passable" / "human_passable" close the cell to one kind
+fn apply_walking_props(cell: &mut Cell, tile: &TileReference) -> Result<(), MapLoadError> {
+    let broken = |reason: String| MapLoadError::BadTileProperty {
+        tileset: tile.tileset.clone(),
+        tile: tile.tile_index,
+        reason,
+    };
+    let flag = |name: &str| match tile.props.get(name) {
+        Some(PropertyValue::BoolValue(value)) => Ok(Some(*value)),
+        Some(other) => Err(broken(format!(
+            "{} should be a bool, got {:?}",
+            name, other
+        ))),
+        None => Ok(None),
+    };
+    if let Some(passable) = flag("passable")? {
+        cell.passable = passable;
+    }
+    cell.dog_passable = flag("dog_passable")?.unwrap_or(true);
+    cell.human_passable = flag("human_passable")?.unwrap_or(true);
+    match float_prop(&tile.props, "cost").map_err(&broken)? {
+        Some(cost) if cost > 0.0 => {
+            cell.cost = ((cost * BASE_COST as f32).round() as u32).max(1);
+        }
+        Some(cost) => return Err(broken(format!("cost has to be positive, got {}", cost))),
+        None => {}
+    }
+    Ok(())
+}
+
+/// Objects from every object layer of a floor: creatures on points become spawns, items become
+/// item spawners and rectangles without a class (or with "zone") become named zones.
+pub fn read_objects(
+    map: &tiled::Map,
+    layers: &[Layer],
+    cellmap: &Cellmap,
+) -> Result<MapObjects, MapLoadError> {
+    let tile_size = vec2(map.tile_width as f32, map.tile_height as f32);
+    let mut objects = MapObjects::default();
+    for layer in layers.iter().copied() {
+        let Some(layer) = layer.as_object_layer() else {
+            continue;
+        };
+        for object in layer.objects() {
+            let broken = |reason: String| MapLoadError::BadObject {
+                id: object.id(),
+                name: object.name.clone(),
+                reason,
+            };
+            let cell_of = |point: Vec2| {
+                let tile = (point / tile_size).floor();
+                cellmap
+                    .tiled_to_ps(ivec2(tile.x as i32, tile.y as i32))
+                    .ok_or_else(|| broken("outside of the map".to_owned()))
+            };
+            let class = object.user_type.as_str();
+            match item_types::from_name(class) {
+                Some(item_type) if SPAWNED_CREATURES.contains(&item_type) => {
+                    objects.spawns.push(SpawnPoint {
+                        item_type,
+                        name: Some(object.name.clone()).filter(|name| !name.is_empty()),
+                        ps: cell_of(object_anchor(&object))?,
+                        speed: float_prop(&object.properties, "speed").map_err(broken)?,
+                    })
+                }
+                Some(item_type) if SPAWNED_ITEMS.contains(&item_type) => {
+                    let count = int_prop(&object.properties, "count").map_err(broken)?;
+                    let radius = int_prop(&object.properties, "radius").map_err(broken)?;
+                    objects.item_spawners.push(ItemSpawner {
+                        item_type,
+                        ps: cell_of(object_anchor(&object))?,
+                        count: count.unwrap_or(1),
+                        radius: radius.unwrap_or(0),
+                    })
+                }
+                _ if class.is_empty() || class == ZONE_CLASS => {
+                    let ObjectShape::Rect { width, height } = object.shape else {
+                        return Err(broken("zones have to be rectangles".to_owned()));
+                    };
+                    if object.name.is_empty() {
+                        return Err(broken("zones need a name".to_owned()));
+                    }
+                    // the last tile the rectangle covers, not the one after it
+                    let inner = (tile_size * 0.5).min(vec2(width, height) * 0.5);
+                    let top_left = cell_of(vec2(object.x, object.y))?;
+                    let bottom_right = cell_of(vec2(object.x + width, object.y + height) - inner)?;
+                    objects.zones.push(Zone {
+                        name: object.name.clone(),
+                        from: Ps {
+                            x: top_left.x,
+                            y: bottom_right.y,
+                            floor: top_left.floor,
+                        },
+                        to: Ps {
+                            x: bottom_right.x,
+                            y: top_left.y,
+                            floor: top_left.floor,
+                        },
+                    })
+                }
+                _ => return Err(broken(format!("nothing to spawn for class '{}'", class))),
+            }
+        }
+    }
+    Ok(objects)
+}
+
+// the point an object stands on: tile objects hang up from their bottom left corner,
+// other shapes count from their center
+fn object_anchor(object: &tiled::Object) -> Vec2 {
+    let at = vec2(object.x, object.y);
+    match object.shape {
+        ObjectShape::Rect { width, height } if object.tile_data().is_some() => {
+            at + vec2(width, -height) * 0.5
+        }
+        ObjectShape::Rect { width, height } | ObjectShape::Ellipse { width, height } => {
+            at + vec2(width, height) * 0.5
+        }
+        _ => at,
+    }
+}
+
+fn float_prop(properties: &tiled::Properties, name: &str) -> Result<Option<f32>, String> {
+    match properties.get(name) {
+        Some(PropertyValue::FloatValue(value)) => Ok(Some(*value)),
+        Some(PropertyValue::IntValue(value)) => Ok(Some(*value as f32)),
+        Some(other) => Err(format!("{} should be a number, got {:?}", name, other)),
+        None => Ok(None),
+    }
+}
+
+fn int_prop(properties: &tiled::Properties, name: &str) -> Result<Option<usize>, String> {
+    match properties.get(name) {
+        Some(PropertyValue::IntValue(value)) if *value >= 0 => Ok(Some(*value as usize)),
+        Some(other) => Err(format!(
+            "{} should be a whole number, got {:?}",
+            name, other
+        )),
+        None => Ok(None),
+    }
 }
@@ -0,0 +1,468 @@
+// tiledwriter turns maps back into Tiled files, the building the game runs on with all its
+// edits as well as generated layouts; what it writes reads back into the same world
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use comfy::IVec2;
+use tiled::PropertyValue;
+
+use crate::{
+    building::Building,
+    core::{animation::TileAnimation, anycellmap::AnyCellmap},
+    tiledreader::{DecorTile, ZONE_CLASS},
+    worldmap::{MapObjects, TileReference},
+    RES_I32,
+};
+
+const ASSETS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets");
+
+/// Tile as it goes into the written tileset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileDef {
+    pub image: String,
+    pub size: IVec2,
+    pub class: String,
+    /// Sorted by name, so the same tile is written once.
+    pub properties: Vec<(String, PropertyValue)>,
+    /// Tiled animation: image of every frame and how long it stays, in milliseconds.
+    pub frames: Vec<(String, u32)>,
+}
+
+impl TileDef {
+    pub fn new(image: &str, size: IVec2) -> Self {
+        Self {
+            image: image.to_owned(),
+            size,
+            class: String::new(),
+            properties: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn with_class(mut self, class: &str) -> Self {
+        self.class = class.to_owned();
+        self
+    }
+
+    pub fn with_property(mut self, name: &str, value: PropertyValue) -> Self {
+        self.properties.retain(|(other, _)| other != name);
+        self.properties.push((name.to_owned(), value));
+        self.properties.sort_by(|a, b| a.0.cmp(&b.0));
+        self
+    }
+
+    fn with_animation(mut self, animation: Option<&TileAnimation>) -> Self {
+        match animation {
+            Some(TileAnimation::Frames { frames, .. }) => {
+                self.frames = frames
+                    .iter()
+                    .map(|frame| (frame.image.clone(), frame.duration_ms))
+                    .collect();
+                self
+            }
+            Some(TileAnimation::Atlas(atlas)) => self
+                .with_property("animated", PropertyValue::BoolValue(true))
+                .with_property("frames", PropertyValue::IntValue(atlas.steps)),
+            None => self,
+        }
+    }
+
+    /// The tile a cell of the map was read from.
+    pub fn of_reference(tile: &TileReference) -> Self {
+        let mut def = Self::new(&tile.tile_image, tile.size).with_class(&tile.klass);
+        for (name, value) in tile.props.iter() {
+            def = def.with_property(name, value.clone());
+        }
+        def.with_animation(tile.animated.as_ref())
+    }
+
+    /// Decoration tile, they are all of the map's tile size.
+    pub fn of_decor(image: &str, animation: Option<&TileAnimation>) -> Self {
+        Self::new(image, IVec2::splat(RES_I32)).with_animation(animation)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Shape {
+    Point,
+    Rect { width: f32, height: f32 },
+}
+
+#[derive(Debug, Clone)]
+struct ObjectDef {
+    name: String,
+    class: String,
+    x: f32,
+    y: f32,
+    shape: Shape,
+    properties: Vec<(String, PropertyValue)>,
+}
+
+#[derive(Debug)]
+struct FloorLayers {
+    bg: Vec<u32>,
+    cells: Vec<u32>,
+    top: Vec<u32>,
+    objects: Vec<ObjectDef>,
+}
+
+/// Map of one or more floors of the same size, with its tileset made of whatever tiles
+/// the floors use. Several floors are written as layer groups.
+#[derive(Debug)]
+pub struct TiledWriter {
+    width: usize,
+    height: usize,
+    tiles: Vec<TileDef>,
+    floors: Vec<FloorLayers>,
+}
+
+impl TiledWriter {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            tiles: Vec::new(),
+            floors: Vec::new(),
+        }
+    }
+
+    /// Gid of the tile, the tile joins the tileset the first time it is asked for.
+    /// No tile is gid 0, an empty cell.
+    pub fn gid(&mut self, tile: Option<TileDef>) -> u32 {
+        let Some(tile) = tile else {
+            return 0;
+        };
+        // frames are tiles of their own
+        for (image, _) in tile.frames.iter() {
+            self.gid(Some(TileDef::of_decor(image, None)));
+        }
+        let index = match self.tiles.iter().position(|known| *known == tile) {
+            Some(index) => index,
+            None => {
+                self.tiles.push(tile);
+                self.tiles.len() - 1
+            }
+        };
+        index as u32 + 1
+    }
+
+    /// Adds a floor from gids in Tiled order, rows from the top.
+    pub fn add_floor(&mut self, bg: Vec<u32>, cells: Vec<u32>, top: Vec<u32>) {
+        self.floors.push(FloorLayers {
+            bg,
+            cells,
+            top,
+            objects: Vec::new(),
+        });
+    }
+
+    /// Puts the objects of the last added floor on its object layer.
+    pub fn add_objects(&mut self, objects: &MapObjects) {
+        let height = self.height;
+        let Some(floor) = self.floors.last_mut() else {
+            return;
+        };
+        let tile = RES_I32 as f32;
+        let center = |ps: crate::core::position::Ps| {
+            (
+                (ps.x as f32 + 0.5) * tile,
+                ((height - 1 - ps.y) as f32 + 0.5) * tile,
+            )
+        };
+        for spawn in objects.spawns.iter() {
+            let (x, y) = center(spawn.ps);
+            let mut properties = Vec::new();
+            if let Some(speed) = spawn.speed {
+                properties.push(("speed".to_owned(), PropertyValue::FloatValue(speed)));
+            }
+            floor.objects.push(ObjectDef {
+                name: spawn.name.clone().unwrap_or_default(),
+                class: spawn.item_type.to_owned(),
+                x,
+                y,
+                shape: Shape::Point,
+                properties,
+            });
+        }
+        for spawner in objects.item_spawners.iter() {
+            let (x, y) = center(spawner.ps);
+            floor.objects.push(ObjectDef {
+                name: String::new(),
+                class: spawner.item_type.to_owned(),
+                x,
+                y,
+                shape: Shape::Point,
+                properties: vec![
+                    (
+                        "count".to_owned(),
+                        PropertyValue::IntValue(spawner.count as i32),
+                    ),
+                    (
+                        "radius".to_owned(),
+                        PropertyValue::IntValue(spawner.radius as i32),
+                    ),
+                ],
+            });
+        }
+        for zone in objects.zones.iter() {
+            floor.objects.push(ObjectDef {
+                name: zone.name.clone(),
+                class: ZONE_CLASS.to_owned(),
+                x: zone.from.x as f32 * tile,
+                y: (height - 1 - zone.to.y) as f32 * tile,
+                shape: Shape::Rect {
+                    width: (zone.to.x - zone.from.x + 1) as f32 * tile,
+                    height: (zone.to.y - zone.from.y + 1) as f32 * tile,
+                },
+                properties: Vec::new(),
+            });
+        }
+    }
+
+    /// The map as Tiled writes it, image paths start with `images`.
+    pub fn to_tmx(&self, images: &str) -> String {
+        let mut next_layer = 1;
+        let mut next_object = 1;
+        let mut tmx = String::new();
+        tmx.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let mut body = String::new();
+        for (number, floor) in self.floors.iter().enumerate() {
+            let grouped = self.floors.len() > 1;
+            let indent = if grouped { "  " } else { " " };
+            if grouped {
+                body.push_str(&format!(
+                    " <group id=\"{}\" name=\"floor {}\">\n",
+                    next_layer, number
+                ));
+                next_layer += 1;
+            }
+            for (name, gids) in [("bg", &floor.bg), ("cells", &floor.cells), ("top", &floor.top)] {
+                body.push_str(&format!(
+                    "{}<layer id=\"{}\" name=\"{}\" width=\"{}\" height=\"{}\">\n{} <data encoding=\"csv\">\n",
+                    indent, next_layer, name, self.width, self.height, indent
+                ));
+                next_layer += 1;
+                let rows: Vec<String> = gids
+                    .chunks(self.width)
+                    .map(|row| {
+                        row.iter()
+                            .map(|gid| gid.to_string())
+                            .collect::<Vec<_>>()
+                            .join(",")
+                    })
+                    .collect();
+                body.push_str(&rows.join(",\n"));
+                body.push_str(&format!("\n</data>\n{}</layer>\n", indent));
+            }
+            if !floor.objects.is_empty() {
+                body.push_str(&format!(
+                    "{}<objectgroup id=\"{}\" name=\"objects\">\n",
+                    indent, next_layer
+                ));
+                next_layer += 1;
+                for object in floor.objects.iter() {
+                    body.push_str(&object_xml(object, next_object, indent));
+                    next_object += 1;
+                }
+                body.push_str(&format!("{}</objectgroup>\n", indent));
+            }
+            if grouped {
+                body.push_str(" </group>\n");
+            }
+        }
+        tmx.push_str(&format!(
+            "<map version=\"1.10\" tiledversion=\"1.10.2\" orientation=\"orthogonal\" \
+             renderorder=\"right-down\" width=\"{}\" height=\"{}\" tilewidth=\"{}\" \
+             tileheight=\"{}\" infinite=\"0\" nextlayerid=\"{}\" nextobjectid=\"{}\">\n",
+            self.width, self.height, RES_I32, RES_I32, next_layer, next_object
+        ));
+        tmx.push_str(&self.tileset_xml(images));
+        tmx.push_str(&body);
+        tmx.push_str("</map>\n");
+        tmx
+    }
+
+    fn tileset_xml(&self, images: &str) -> String {
+        let tallest = self.tiles.iter().map(|tile| tile.size.y).max();
+        let mut xml = format!(
+            " <tileset firstgid=\"1\" name=\"base\" tilewidth=\"{}\" tileheight=\"{}\" \
+             tilecount=\"{}\" columns=\"0\">\n  <grid orientation=\"orthogonal\" width=\"1\" height=\"1\"/>\n",
+            RES_I32,
+            tallest.unwrap_or(RES_I32).max(RES_I32),
+            self.tiles.len()
+        );
+        for (id, tile) in self.tiles.iter().enumerate() {
+            if tile.class.is_empty() {
+                xml.push_str(&format!("  <tile id=\"{}\">\n", id));
+            } else {
+                xml.push_str(&format!(
+                    "  <tile id=\"{}\" type=\"{}\">\n",
+                    id,
+                    escape(&tile.class)
+                ));
+            }
+            xml.push_str(&properties_xml(&tile.properties, "   "));
+            xml.push_str(&format!(
+                "   <image width=\"{}\" height=\"{}\" source=\"{}\"/>\n",
+                tile.size.x,
+                tile.size.y,
+                escape(&format!("{}{}", images, tile.image))
+            ));
+            if !tile.frames.is_empty() {
+                xml.push_str("   <animation>\n");
+                for (image, duration) in tile.frames.iter() {
+                    let frame = TileDef::of_decor(image, None);
+                    let frame_id = self.tiles.iter().position(|known| *known == frame);
+                    xml.push_str(&format!(
+                        "    <frame tileid=\"{}\" duration=\"{}\"/>\n",
+                        frame_id.unwrap_or(id),
+                        duration
+                    ));
+                }
+                xml.push_str("   </animation>\n");
+            }
+            xml.push_str("  </tile>\n");
+        }
+        xml.push_str(" </tileset>\n");
+        xml
+    }
+
+    /// Writes the map to `path`. Images are named relative to it when it is in the assets,
+    /// by their full path otherwise.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_tmx(&image_prefix(path)))
+    }
+}
+
+/// Writer holding every floor of the building with its decorations and map objects.
+pub fn building_writer(
+    building: &Building,
+    decor: &[AnyCellmap<DecorTile>],
+) -> io::Result<TiledWriter> {
+    let (width, height) = building.ground().wh_usize();
+    if building.sizes().iter().any(|size| *size != (width, height)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "floors of different sizes can't go into one map",
+        ));
+    }
+    let mut writer = TiledWriter::new(width, height);
+    for (floor, cellmap) in building.floors().iter().enumerate() {
+        let decor = decor.get(floor);
+        let (mut bg, mut cells, mut top) = (Vec::new(), Vec::new(), Vec::new());
+        for row in 0..height {
+            for x in 0..width {
+                let y = height - 1 - row;
+                let cell = cellmap.get_xy(x, y);
+                cells.push(writer.gid(cell.reference.as_ref().map(TileDef::of_reference)));
+                let decor = decor.map(|decor| decor.get_xy(x, y));
+                bg.push(writer.gid(decor.and_then(|decor| {
+                    let image = decor.bg.as_ref()?;
+                    Some(TileDef::of_decor(image, decor.animated_bg.as_ref()))
+                })));
+                top.push(writer.gid(decor.and_then(|decor| {
+                    let image = decor.top.as_ref()?;
+                    Some(TileDef::of_decor(image, decor.animated_top.as_ref()))
+                })));
+            }
+        }
+        writer.add_floor(bg, cells, top);
+        writer.add_objects(&cellmap.objects);
+    }
+    Ok(writer)
+}
+
+// images are found next to maps in the assets, anywhere else they need the whole path
+fn image_prefix(path: &Path) -> String {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let assets = PathBuf::from(ASSETS_DIR);
+    match (dir.canonicalize(), assets.canonicalize()) {
+        (Ok(dir), Ok(assets)) if dir == assets => String::new(),
+        _ => format!("{}/", ASSETS_DIR),
+    }
+}
+
+/// Prefix of images for maps that are read from memory.
+pub fn assets_prefix() -> String {
+    format!("{}/", ASSETS_DIR)
+}
+
+fn object_xml(object: &ObjectDef, id: usize, indent: &str) -> String {
+    let mut xml = format!(
+        "{} <object id=\"{}\" name=\"{}\" type=\"{}\" x=\"{}\" y=\"{}\"",
+        indent,
+        id,
+        escape(&object.name),
+        escape(&object.class),
+        object.x,
+        object.y
+    );
+    if let Shape::Rect { width, height } = object.shape {
+        xml.push_str(&format!(" width=\"{}\" height=\"{}\"", width, height));
+    }
+    let inner_indent = format!("{}  ", indent);
+    let properties = properties_xml(&object.properties, &inner_indent);
+    match object.shape {
+        Shape::Rect { .. } if properties.is_empty() => xml.push_str("/>\n"),
+        Shape::Rect { .. } => {
+            xml.push_str(">\n");
+            xml.push_str(&properties);
+            xml.push_str(&format!("{} </object>\n", indent));
+        }
+        Shape::Point => {
+            xml.push_str(">\n");
+            xml.push_str(&properties);
+            xml.push_str(&format!("{}<point/>\n{} </object>\n", inner_indent, indent));
+        }
+    }
+    xml
+}
+
+fn properties_xml(properties: &[(String, PropertyValue)], indent: &str) -> String {
+    if properties.is_empty() {
+        return String::new();
+    }
+    let mut xml = format!("{}<properties>\n", indent);
+    for (name, value) in properties {
+        let (kind, value) = match value {
+            PropertyValue::BoolValue(value) => ("bool", value.to_string()),
+            PropertyValue::FloatValue(value) => ("float", value.to_string()),
+            PropertyValue::IntValue(value) => ("int", value.to_string()),
+            PropertyValue::ColorValue(color) => (
+                "color",
+                format!(
+                    "#{:02x}{:02x}{:02x}{:02x}",
+                    color.alpha, color.red, color.green, color.blue
+                ),
+            ),
+            PropertyValue::StringValue(value) => ("string", value.clone()),
+            PropertyValue::FileValue(value) => ("file", value.clone()),
+            PropertyValue::ObjectValue(value) => ("object", value.to_string()),
+        };
+        xml.push_str(&format!(
+            "{} <property name=\"{}\" type=\"{}\" value=\"{}\"/>\n",
+            indent,
+            escape(name),
+            kind,
+            escape(&value)
+        ));
+    }
+    xml.push_str(&format!("{}</properties>\n", indent));
+    xml
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
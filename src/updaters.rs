@@ -1,21 +1,22 @@
+use crate::behavior::actor::{Actor, ACTOR_TYPES};
 use crate::behavior::creatures::{Direction, PsOffsetProvider};
-use crate::behavior::messaging::communication::{self, Communicator};
-use crate::behavior::routing::PathfindRouter;
-use crate::behavior::{dog, sanity};
+use crate::behavior::messaging::communication::Communicator;
+use crate::building::Floor;
+use crate::editor::{self, Brush};
 use crate::core::anycellmap::AnyCellmap;
-use crate::core::Initializable;
-use crate::gameplay::ent::bed::Bed;
+use crate::core::position::Ps;
 use crate::gameplay::ent::conputer::Conputer;
 use crate::gameplay::ent::officeworker::OfficeWorker;
 use crate::gameplay::humanclothes::{BodyClothesLookPart, EyesLookPart, HairLookPart};
-use crate::initializers::create_bones;
+use crate::replay::{Command, Replay};
+use crate::savegame;
+use crate::simulation::TICK_DT;
 use crate::state::WorldState;
 use crate::ui::statusbar::Statusbar;
-use comfy::hecs::{Component, With};
+use std::path::Path;
 use comfy::{
-    commands, draw_rect, draw_rect_outline, is_key_pressed, rand, splat, vec2, AnimatedSprite,
-    Entity, HashSet, IntoParallelIterator, Lazy, Mutex, ParallelIterator, Sprite, TextParams, BLUE,
-    GREEN, ORANGE_RED, RED, SEA_GREEN, WHITE,
+    commands, draw_rect, draw_rect_outline, ivec2, is_key_pressed, splat, vec2, AnimatedSprite,
+    Entity, HashMap, Lazy, Mutex, Sprite, TextParams, BLUE, GREEN, ORANGE_RED, SEA_GREEN, WHITE,
 };
 use comfy::{
     is_key_down, is_mouse_button_pressed, main_camera_mut, num_traits::ToPrimitive, world,
@@ -25,7 +26,7 @@ use comfy::{
 pub static GLOBAL_HEATMAP: Lazy<Mutex<AnyCellmap<f64>>> =
     Lazy::new(|| Mutex::new(AnyCellmap::new(&0.0, 0, 0)));
 
-use crate::{Bone, Player, Selection, TrashCan, RES_I32};
+use crate::{Player, Selection, RES_I32};
 
 pub fn update_camera(state: &mut WorldState, _c: &mut EngineContext, dt: f32) {
     for (_, (_, transform)) in world().query::<(&Player, &mut Transform)>().iter() {
@@ -57,16 +58,33 @@ pub fn update_camera(state: &mut WorldState, _c: &mut EngineContext, dt: f32) {
 
         if is_key_pressed(KeyCode::F) {
             state.paused = !state.paused;
+            state.clock.reset();
             println!("Paused: {}", state.paused)
         }
 
+        if is_key_pressed(KeyCode::Period) && state.paused {
+            state.step_requested = true;
+            println!("Step to tick {}", state.sim.tick + 1)
+        }
+
+        if is_key_pressed(KeyCode::Equals) {
+            state.clock.faster();
+            println!("Speed: {}x", state.clock.multiplier())
+        }
+
+        if is_key_pressed(KeyCode::Minus) {
+            state.clock.slower();
+            println!("Speed: {}x", state.clock.multiplier())
+        }
+
         if is_mouse_button_pressed(MouseButton::Right) {
             let mousepad = comfy::mouse_world();
             let x = (mousepad.x / 1.0).round().to_i32().unwrap();
             let y = (mousepad.y / 1.0).round().to_i32().unwrap();
             println!("Clicked right: x: {}   y: {}", x, y);
-            if state.reality.cellmap.within_bounds(x, y) {
-                state.dog_order = Some((x, y).into())
+            if state.shown_floor().within_bounds(x, y) {
+                let ps = Ps::from((x, y)).with_floor(state.floor);
+                state.sim.submit(Command::DogOrder(ps))
             }
         }
 
@@ -74,17 +92,130 @@ pub fn update_camera(state: &mut WorldState, _c: &mut EngineContext, dt: f32) {
     }
 }
 
-pub fn update_bones(state: &mut WorldState, _c: &mut EngineContext, _dt: f32) {
-    for (entity, (_obj, transform)) in world().query::<(&mut Bone, &mut Transform)>().iter() {
-        let mut handles = state.reality.carriables.lock();
-        let handle = handles.get(&entity).unwrap();
-        transform.position = handle.get_exact_pos();
-        if handle.consumed {
-            commands().despawn(entity);
-            handles.remove(&entity);
-            create_bones(1, &state.reality.cellmap)
+const QUICKSAVE_PATH: &str = "saves/quicksave.json";
+
+pub fn update_savegame(state: &mut WorldState, c: &mut EngineContext) {
+    if is_key_pressed(KeyCode::F5) {
+        match savegame::save(&state.sim, Path::new(QUICKSAVE_PATH)) {
+            Ok(()) => println!("Saved to {} at {}", QUICKSAVE_PATH, state.sim.reality.time),
+            Err(e) => println!("ERROR: cannot save: {}", e),
+        }
+    }
+    if is_key_pressed(KeyCode::F9) {
+        match crate::load_world(state, c, Path::new(QUICKSAVE_PATH)) {
+            Ok(()) => println!("Loaded {} at {}", QUICKSAVE_PATH, state.sim.reality.time),
+            Err(e) => println!("ERROR: cannot load: {}", e),
+        }
+    }
+}
+
+pub fn update_map_reload(state: &mut WorldState, c: &mut EngineContext, dt: f32) {
+    if state.map_watcher.poll(dt) {
+        if let Err(e) = crate::reload_map(state, c) {
+            // a half-finished map is common while editing, keep the old one until the next save
+            println!("ERROR: cannot reload the map: {}", e);
+        }
+    }
+}
+
+const EDITED_MAP_PATH: &str = "saves/edited.tmx";
+
+/// Tab toggles edit mode. While editing the number keys pick the brush, the arrows turn
+/// the workplace of conputers, left click paints and F2 saves the map.
+pub fn update_editor(state: &mut WorldState) {
+    if is_key_pressed(KeyCode::Tab) {
+        state.editor.active = !state.editor.active;
+        println!("Edit mode: {}", state.editor.active);
+    }
+    if !state.editor.active {
+        return;
+    }
+
+    let brush_keys = [
+        KeyCode::Num1,
+        KeyCode::Num2,
+        KeyCode::Num3,
+        KeyCode::Num4,
+        KeyCode::Num5,
+        KeyCode::Num6,
+    ];
+    for (key, brush) in brush_keys.into_iter().zip(Brush::ALL) {
+        if is_key_pressed(key) {
+            state.editor.brush = brush;
+            println!("Brush: {:?}", brush);
+        }
+    }
+    let facings = [
+        (KeyCode::Up, ivec2(0, 1)),
+        (KeyCode::Down, ivec2(0, -1)),
+        (KeyCode::Left, ivec2(-1, 0)),
+        (KeyCode::Right, ivec2(1, 0)),
+    ];
+    for (key, workplace) in facings {
+        if is_key_pressed(key) {
+            state.editor.workplace = workplace;
+            println!("Conputer workplace: {}", workplace);
+        }
+    }
+
+    if is_mouse_button_pressed(MouseButton::Left) {
+        let mousepad = comfy::mouse_world();
+        let x = (mousepad.x / 1.0).round().to_i32().unwrap();
+        let y = (mousepad.y / 1.0).round().to_i32().unwrap();
+        if state.shown_floor().within_bounds(x, y) {
+            let ps = Ps::from((x, y)).with_floor(state.floor);
+            state.sim.submit(state.editor.command(ps));
+        }
+    }
+
+    if is_key_pressed(KeyCode::F2) {
+        match editor::save(&state.sim, &state.decor, Path::new(EDITED_MAP_PATH)) {
+            Ok(()) => println!("Map saved to {}", EDITED_MAP_PATH),
+            Err(e) => println!("ERROR: cannot save the map: {}", e),
         }
     }
+
+    comfy::draw_text(
+        &format!(
+            "edit: {:?}  workplace: {}",
+            state.editor.brush, state.editor.workplace
+        ),
+        vec2(0.0, -2.0),
+        WHITE,
+        comfy::TextAlign::Center,
+    );
+}
+
+const REPLAY_PATH: &str = "saves/replay.json";
+pub const CRASH_REPLAY_PATH: &str = "saves/crash-replay.json";
+
+pub fn update_replay(state: &mut WorldState) {
+    if is_key_pressed(KeyCode::F6) {
+        save_replay(state, Path::new(REPLAY_PATH), state.sim.tick);
+    }
+}
+
+pub fn save_replay(state: &WorldState, path: &Path, end_tick: u64) {
+    let Some(start) = &state.replay_start else {
+        println!("Nothing to replay yet");
+        return;
+    };
+    let replay = Replay::new(
+        start.clone(),
+        &state.sim,
+        &state.scenario.name,
+        TICK_DT,
+        end_tick,
+    );
+    match replay.save(path) {
+        Ok(()) => println!(
+            "Replay of ticks {}..{} saved to {}",
+            replay.start.tick,
+            end_tick,
+            path.display()
+        ),
+        Err(e) => println!("ERROR: cannot save replay: {}", e),
+    }
 }
 
 pub fn update_conputers(state: &mut WorldState, _c: &mut EngineContext, _dt: f32) {
@@ -92,7 +223,7 @@ pub fn update_conputers(state: &mut WorldState, _c: &mut EngineContext, _dt: f32
         .query::<(&mut Conputer, &mut AnimatedSprite)>()
         .iter()
     {
-        let handles = state.reality.interactive.lock();
+        let handles = state.sim.reality.interactive.lock();
         let handle = handles.get(&obj.handle_position).unwrap();
         if handle.used_by.is_none() {
             if obj.use_animation_playing {
@@ -116,27 +247,6 @@ pub fn update_conputers(state: &mut WorldState, _c: &mut EngineContext, _dt: f32
     }
 }
 
-pub fn update_init<T: Initializable + Component>(
-    state: &mut WorldState,
-    _c: &mut EngineContext,
-    _dt: f32,
-) {
-    for (entity, (obj, transform)) in world().query::<(&mut T, &mut Transform)>().iter() {
-        if !obj.is_initialized() {
-            obj.initialize(&entity, transform, &mut state.reality)
-        }
-    }
-}
-
-pub fn update_initializable_all(state: &mut WorldState, c: &mut EngineContext, dt: f32) {
-    update_init::<TrashCan>(state, c, dt);
-    update_init::<Bone>(state, c, dt);
-    update_init::<Conputer>(state, c, dt);
-    update_init::<Bed>(state, c, dt);
-    update_init::<dog::Dog>(state, c, dt);
-    update_init::<OfficeWorker>(state, c, dt);
-}
-
 pub fn update_selection(state: &mut WorldState, _c: &mut EngineContext, _dt: f32) {
     for (entity, (_, transform)) in world().query::<(&Selection, &mut Transform)>().iter() {
         if !state.selected {
@@ -144,15 +254,15 @@ pub fn update_selection(state: &mut WorldState, _c: &mut EngineContext, _dt: f32
         }
         if state.selected
             && state
-                .reality
-                .cellmap
+                .shown_floor()
                 .within_bounds(state.selected_cell.x, state.selected_cell.y)
         {
             transform.position = state.selected_cell.into();
         }
     }
 
-    if is_mouse_button_pressed(MouseButton::Left) {
+    // in edit mode clicks paint the map
+    if is_mouse_button_pressed(MouseButton::Left) && !state.editor.active {
         if !state.selected {
             commands().spawn((
                 Sprite::new("selectionhd.png", vec2(1.0, 1.0), 10, WHITE)
@@ -165,26 +275,30 @@ pub fn update_selection(state: &mut WorldState, _c: &mut EngineContext, _dt: f32
         let x = (mousepad.x / 1.0).round().to_i32().unwrap();
         let y = (mousepad.y / 1.0).round().to_i32().unwrap();
         println!("Clicked: x: {}   y: {}", x, y);
-        if !state.reality.cellmap.within_bounds(x, y) {
+        let clicked = Ps::from((x, y)).with_floor(state.floor);
+        if !state.shown_floor().within_bounds(x, y) {
             state.deselect_cell()
         } else {
-            if state.reality.cellmap.pos_within_bounds(state.selected_cell) {
-                state.reality.cellmap.deoccupy_ps(&state.selected_cell);
-            }
-            state.select_or_deselect_cell((x, y).into());
             if state
+                .sim
                 .reality
-                .cellmap
+                .building
+                .pos_within_bounds(state.selected_cell)
+            {
+                state.sim.submit(Command::Deoccupy(state.selected_cell));
+            }
+            state.select_or_deselect_cell(clicked);
+            if state
+                .shown_floor()
                 .within_bounds(state.selected_cell.x, state.selected_cell.y)
             {
                 let cell = state
-                    .reality
-                    .cellmap
+                    .shown_floor()
                     .get_xy(state.selected_cell.x, state.selected_cell.y);
                 println!("Cell info: {:?}", cell);
                 // make this point occupied
                 if state.selected {
-                    state.reality.cellmap.occupy_xy(x, y)
+                    state.sim.submit(Command::Occupy(clicked))
                 }
             }
         }
@@ -199,157 +313,107 @@ pub fn update_selection(state: &mut WorldState, _c: &mut EngineContext, _dt: f32
     }
 }
 
-pub fn update_dogs(state: &mut WorldState, _c: &mut EngineContext, dt: f32) {
-    let wrld = world();
-    let mut queried = wrld.query::<(
-        &mut dog::Dog,
-        &mut Transform,
-        &mut AnimatedSprite,
-        &mut Communicator,
-    )>();
-    let items = queried.iter().collect::<Vec<_>>();
-
-    if !state.paused {
-        items.into_par_iter().for_each(|data| {
-            let (entity, (dog, _, _anim, comm)) = data;
-            if !dog.initialized {
-                return;
-            }
-            dog.sa.sanity.lock().move_direction_if_can(dt);
-            if let Some(order) = state.dog_order {
-                dog.sa.sanity.lock().intend_go_to(order);
-            }
-            let intention_result = dog
-                .sa
-                .sanity
-                .lock()
-                .think_intention_level_if_not_moving(entity, &state.reality);
-            dog.sa
-                .think_routine_level(intention_result, &state.reality, entity, comm, dt);
-        });
-    }
-
-    let mut items_again = queried.iter().collect::<Vec<_>>();
-
-    comfy::ChooseRandom::shuffle(&mut items_again);
-
-    for (_entity, (dog, transform, anim, comm)) in items_again.into_iter() {
-        transform.position = dog.get_exact_pos();
-        comm.ps = dog.sa.get_ps();
-
-        if state.paused {
-            if dog.sa.get_ps() == state.selected_cell && state.selected {
-                let mut text_params = TextParams::default();
-                text_params.color = WHITE;
-                text_params.font.size = 8.0;
-                comfy::draw_text_ex(
-                    &format!("dog: {:?}", dog),
-                    transform.position,
-                    comfy::TextAlign::TopLeft,
-                    text_params,
-                );
-
-                let tgt = dog.sa.sanity.lock().mv.current_move_path.target.clone();
-
-                if let Some(target) = tgt {
-                    draw_rect_outline(
-                        vec2(target.x.to_f32().unwrap(), target.y.to_f32().unwrap()),
-                        splat(0.9),
-                        0.6,
-                        comfy::RED,
-                        1,
-                    );
-                }
-
-                for step in dog
-                    .sa
-                    .sanity
-                    .lock()
-                    .mv
-                    .current_move_path
-                    .calculated_steps
-                    .iter()
-                {
-                    draw_rect_outline(
-                        vec2(step.x.to_f32().unwrap(), step.y.to_f32().unwrap()),
-                        splat(0.7),
-                        0.1,
-                        ORANGE_RED,
-                        1,
-                    );
-                }
-            }
+pub fn update_dogs(state: &mut WorldState, _c: &mut EngineContext, _dt: f32) {
+    let redirect = [
+        (KeyCode::Up, Direction::Up),
+        (KeyCode::Down, Direction::Down),
+        (KeyCode::Left, Direction::Left),
+        (KeyCode::Right, Direction::Right),
+    ];
+    for (key, dir) in redirect {
+        // in edit mode the arrows turn conputers
+        if is_key_pressed(key) && !state.editor.active {
+            state.sim.submit(Command::RedirectDogs(dir));
+            println!("Dogs redirected: {:?}", dir);
         }
+    }
+
+    if is_key_pressed(KeyCode::P) {
+        state.sim.submit(Command::MoveDogsTo((12, 8).into()));
+        println!("Dogs ordered to move");
+    }
+
+}
+
+/// Presentation of every actor type: debug drawing while paused and the per-type view hooks.
+pub fn update_actors(state: &WorldState) {
+    for actor_type in ACTOR_TYPES.iter() {
+        (actor_type.view)(state);
+    }
+}
 
-        if is_key_pressed(KeyCode::Up) {
-            let dir = Direction::Up;
-            dog.sa
-                .sanity
-                .lock()
-                .start_move_direction(dir, &state.reality.cellmap);
-            println!("Dog redirected: {:?}", dog);
-        }
-        if is_key_pressed(KeyCode::Down) {
-            let dir = Direction::Down;
-            dog.sa
-                .sanity
-                .lock()
-                .start_move_direction(dir, &state.reality.cellmap);
-            println!("Dog redirected: {:?}", dog);
-        }
-        if is_key_pressed(KeyCode::Left) {
-            let dir = Direction::Left;
-            dog.sa
-                .sanity
-                .lock()
-                .start_move_direction(dir, &state.reality.cellmap);
-            println!("Dog redirected: {:?}", dog);
-        }
-        if is_key_pressed(KeyCode::Right) {
-            let dir = Direction::Right;
-            dog.sa
-                .sanity
-                .lock()
-                .start_move_direction(dir, &state.reality.cellmap);
-            println!("Dog redirected: {:?}", dog);
-        }
-
-        if is_key_pressed(KeyCode::P) {
-            dog.sa
-                .sanity
-                .lock()
-                .move_to_ps(&state.reality.cellmap, (12, 8).into());
-            println!("Dog ordered to move: {:?}", dog);
+pub fn update_actor_views<A: Actor>(state: &WorldState) {
+    for (entity, actor) in world().query::<&A>().iter() {
+        if state.paused && state.selected && actor.sa().get_ps() == state.selected_cell {
+            draw_actor_debug(actor);
         }
 
         if is_key_pressed(KeyCode::I) {
-            println!("Dog status: {:?}", dog);
+            println!("Status: {:?}", actor);
         }
 
-        let mut sanity = dog.sa.sanity.lock();
+        actor.update_view(entity, state.paused);
+    }
+}
 
-        if sanity.carrier.has_anything() {
-            let ps_offset = sanity.mv.as_ps_offset_container();
-            sanity
-                .carrier
-                .update_positions(&state.reality.carriables, &ps_offset)
-        }
+fn draw_actor_debug<A: Actor>(actor: &A) {
+    let text_params = TextParams {
+        color: WHITE,
+        font: comfy::egui::FontId::monospace(8.0),
+        ..Default::default()
+    };
+    comfy::draw_text_ex(
+        &format!("{}: {:?}", A::CLASS, actor),
+        actor.sa().get_exact_pos(),
+        comfy::TextAlign::TopLeft,
+        text_params,
+    );
 
-        if !state.paused {
-            sanity.think_movement_level_if_not_moving(&mut state.reality.cellmap);
-            if sanity.mv.movement.loc.direction == Some(Direction::Left) {
-                anim.play("idle_left")
-            } else if sanity.mv.movement.loc.direction == Some(Direction::Right) {
-                anim.play("idle")
-            }
-        }
+    actor.draw_debug();
+
+    let sanity = actor.sa().sanity.lock();
+    let path = &sanity.mv.current_move_path;
+
+    if let Some(target) = path.target {
+        draw_rect_outline(
+            vec2(target.x.to_f32().unwrap(), target.y.to_f32().unwrap()),
+            splat(0.9),
+            0.6,
+            comfy::RED.alpha(0.6),
+            4,
+        );
+    }
+
+    // still waiting for the path to be found
+    if let Some(destination) = sanity.mv.waiting_for_path() {
+        draw_rect_outline(
+            vec2(destination.x.to_f32().unwrap(), destination.y.to_f32().unwrap()),
+            splat(0.9),
+            0.6,
+            comfy::YELLOW.alpha(0.6),
+            4,
+        );
+    }
+
+    for step in path.calculated_steps.iter() {
+        draw_rect_outline(
+            vec2(step.x.to_f32().unwrap(), step.y.to_f32().unwrap()),
+            splat(0.7),
+            0.1,
+            ORANGE_RED,
+            4,
+        );
     }
-    // print!("  ");
-    state.dog_order = None;
 }
 
-pub fn update_statusbars() {
-    for (_entity, (bars, transform)) in world().query::<(&mut Statusbar, &mut Transform)>().iter() {
+pub fn update_statusbars(state: &WorldState) {
+    for (_entity, (bars, transform, communicator)) in world()
+        .query::<(&mut Statusbar, &mut Transform, Option<&Communicator>)>()
+        .iter()
+    {
+        if communicator.is_some_and(|communicator| communicator.ps.floor != state.floor) {
+            continue;
+        }
         let mut i: f32 = 0.0;
         let pos = transform.position;
         for (_, bar) in bars.bars.iter() {
@@ -373,17 +437,6 @@ pub fn update_statusbars() {
     }
 }
 
-pub fn update_communication(state: &mut WorldState) {
-    let mut map = state.reality.comm_map.lock();
-    map.reset(HashSet::new());
-    for (entity, (communication, transform)) in world()
-        .query::<(&mut Communicator, &mut Transform)>()
-        .iter()
-    {
-        communication.mark_position_on_map(entity, &mut map)
-    }
-}
-
 pub fn update_human_looks() {
     let world = world();
     for (_entity, (part, transform)) in world.query::<(&mut EyesLookPart, &mut Transform)>().iter()
@@ -431,10 +484,10 @@ pub fn update_human_looks() {
 
 pub fn update_heatmap(state: &mut WorldState, _c: &mut EngineContext, _dt: f32) {
     let mut heatmap = GLOBAL_HEATMAP.lock();
-    for (index, item) in state.reality.cellmap.map.iter().enumerate() {
+    for (index, item) in state.shown_floor().map.iter().enumerate() {
         let pos = item.position;
 
-        if state.paused && item.status.occupied {
+        if state.paused && item.is_occupied() {
             draw_rect_outline(
                 vec2(pos.x.to_f32().unwrap(), pos.y.to_f32().unwrap()),
                 splat(1.0),
@@ -443,7 +496,7 @@ pub fn update_heatmap(state: &mut WorldState, _c: &mut EngineContext, _dt: f32)
                 1,
             );
         } else {
-            if item.status.occupied {
+            if item.is_occupied() {
                 heatmap.map[index] += 0.002;
             }
         }
@@ -462,149 +515,102 @@ pub fn update_heatmap(state: &mut WorldState, _c: &mut EngineContext, _dt: f32)
     }
 }
 
-pub fn update_time(state: &mut WorldState, _c: &mut EngineContext, dt: f32) {
-    if !state.paused {
-        state.reality.time.tick(dt);
-    }
-
+pub fn update_time(state: &mut WorldState, _c: &mut EngineContext, _dt: f32) {
     comfy::draw_text(
-        &format!("time: {}", state.reality.time),
+        &format!(
+            "time: {}  speed: {}x{}",
+            state.sim.reality.time,
+            state.clock.multiplier(),
+            if state.paused { " (paused)" } else { "" }
+        ),
         Vec2::ZERO,
         WHITE,
         comfy::TextAlign::Center,
     );
 }
 
-pub fn update_sane_objects(state: &mut WorldState, _c: &mut EngineContext, dt: f32) {
-    let wrld = world();
-    let mut queried = wrld.query::<(
-        &mut OfficeWorker,
-        &mut Transform,
-        &mut Statusbar,
-        &mut Communicator,
-    )>();
-    let items = queried.iter().collect::<Vec<_>>();
-
-    if !state.paused {
-        items.into_par_iter().for_each(|data| {
-            let (entity, (actor, _, statusbar, communication)) = data;
-            if !actor.initialized {
-                return;
-            }
-            actor.sa.sanity.lock().move_direction_if_can(dt);
-            if let Some(order) = state.dog_order {
-                actor.sa.sanity.lock().intend_go_to(order);
-            }
-            let intention_result = actor
-                .sa
-                .sanity
-                .lock()
-                .think_intention_level_if_not_moving(entity, &state.reality);
-            actor.sa.think_routine_level(
-                intention_result,
-                &state.reality,
-                entity,
-                &communication,
-                dt,
-            );
-
-            statusbar.show(
-                "steps".to_owned(),
-                actor
-                    .sa
-                    .sanity
-                    .lock()
-                    .mv
-                    .current_move_path
-                    .calculated_steps
-                    .len() as f32,
-                100.0,
-            );
-
-            statusbar.show(
-                "visible".to_owned(),
-                actor.sa.routine.visible_entities.len() as f32,
-                5.0,
-            )
-        });
+/// Page Up and Page Down walk between the floors, only the shown floor is drawn.
+pub fn update_floor(state: &mut WorldState) {
+    let count = state.sim.reality.building.floor_count();
+    if count < 2 {
+        return;
     }
 
-    let mut items_again = queried.iter().collect::<Vec<_>>();
-
-    comfy::ChooseRandom::shuffle(&mut items_again);
+    let mut shown = state.floor;
+    if is_key_pressed(KeyCode::PageUp) {
+        shown = (shown + 1).min(count - 1);
+    }
+    if is_key_pressed(KeyCode::PageDown) {
+        shown = shown.saturating_sub(1);
+    }
+    if shown != state.floor {
+        state.floor = shown;
+        // the selection and the heat belong to the floor that is hidden now
+        state.deselect_cell();
+        let (w, h) = state.shown_floor().wh_i32();
+        GLOBAL_HEATMAP.lock().reset_and_resize(0.0, w, h);
+        println!("Floor {}/{}", shown + 1, count);
+    }
 
-    for (_entity, (dog, transform, _, communication)) in items_again.into_iter() {
-        transform.position = dog.sa.get_exact_pos();
-        communication.ps = dog.sa.get_ps();
+    comfy::draw_text(
+        &format!("floor: {}/{}", state.floor + 1, count),
+        vec2(0.0, -1.0),
+        WHITE,
+        comfy::TextAlign::Center,
+    );
+    update_floor_visibility(state.floor);
+}
 
-        let mut sanity = dog.sa.sanity.lock();
+// hides everything that is not on the shown floor: map entities know their floor,
+// creatures know it from where they stand and body parts from the creature they belong to
+fn update_floor_visibility(shown: usize) {
+    let wrld = world();
+    let alpha = |floor: usize| if floor == shown { 1.0 } else { 0.0 };
 
-        if sanity.carrier.has_anything() {
-            let ps_offset = sanity.mv.as_ps_offset_container();
-            sanity
-                .carrier
-                .update_positions(&state.reality.carriables, &ps_offset)
+    let creatures: HashMap<Entity, usize> = wrld
+        .query::<&Communicator>()
+        .iter()
+        .map(|(entity, communicator)| (entity, communicator.ps.floor))
+        .collect();
+    let floor_of = |entity: Entity, floor: Option<&Floor>| {
+        floor
+            .map(|floor| floor.0)
+            .or_else(|| creatures.get(&entity).copied())
+    };
+
+    for (entity, (sprite, floor)) in wrld.query::<(&mut Sprite, Option<&Floor>)>().iter() {
+        if let Some(floor) = floor_of(entity, floor) {
+            sprite.color.a = alpha(floor);
         }
-
-        if !state.paused {
-            sanity.think_movement_level_if_not_moving(&mut state.reality.cellmap);
+    }
+    for (entity, (sprite, floor)) in wrld.query::<(&mut AnimatedSprite, Option<&Floor>)>().iter() {
+        if let Some(floor) = floor_of(entity, floor) {
+            sprite.color.a = alpha(floor);
         }
     }
-    // print!("  ");
-    state.dog_order = None;
-}
-
-pub fn update_sane_objects_pause(state: &mut WorldState) {
-    let wrld = world();
-    for (_entity, actor) in world().query::<&OfficeWorker>().iter() {
-        if state.paused {
-            if actor.sa.get_ps() == state.selected_cell && state.selected {
-                let mut text_params = TextParams::default();
-                text_params.color = WHITE;
-                text_params.font.size = 8.0;
-                comfy::draw_text_ex(
-                    &format!("dog: {:?}", actor),
-                    actor.sa.get_exact_pos(),
-                    comfy::TextAlign::TopLeft,
-                    text_params,
-                );
-
-                for item in actor.sa.routine.visible_entities.iter() {
-                    let mut item = wrld.query_one::<&Transform>(*item).unwrap();
-                    let deref = item.get().unwrap();
-                    draw_rect_outline(deref.position, vec2(1.0, 1.0), 0.3, RED, 200);
-                }
-
-                let tgt = actor.sa.sanity.lock().mv.current_move_path.target.clone();
 
-                if let Some(target) = tgt {
-                    draw_rect_outline(
-                        vec2(target.x.to_f32().unwrap(), target.y.to_f32().unwrap()),
-                        splat(0.9),
-                        0.6,
-                        comfy::RED.alpha(0.6),
-                        4,
-                    );
-                }
-
-                for step in actor
-                    .sa
-                    .sanity
-                    .lock()
-                    .mv
-                    .current_move_path
-                    .calculated_steps
-                    .iter()
-                {
-                    draw_rect_outline(
-                        vec2(step.x.to_f32().unwrap(), step.y.to_f32().unwrap()),
-                        splat(0.7),
-                        0.1,
-                        ORANGE_RED,
-                        4,
-                    );
-                }
-            }
+    let mut parts: Vec<(Entity, Entity)> = Vec::new();
+    parts.extend(
+        wrld.query::<&EyesLookPart>()
+            .iter()
+            .map(|(e, p)| (e, p.ent)),
+    );
+    parts.extend(
+        wrld.query::<&BodyClothesLookPart>()
+            .iter()
+            .map(|(e, p)| (e, p.ent)),
+    );
+    parts.extend(
+        wrld.query::<&HairLookPart>()
+            .iter()
+            .map(|(e, p)| (e, p.ent)),
+    );
+    for (part, owner) in parts {
+        let Some(floor) = creatures.get(&owner) else {
+            continue;
+        };
+        if let Ok(mut sprite) = wrld.get::<&mut Sprite>(part) {
+            sprite.color.a = alpha(*floor);
         }
     }
 }
@@ -1,11 +1,12 @@
 use comfy::{Arc, HashMap, Mutex};
+use serde::{Deserialize, Serialize};
 
 // Type alias for the hashmap
 pub type PrimitiveHashMap = Arc<Mutex<HashMap<String, PrimitiveValue>>>;
 
 // Define an enum to represent primitive values
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum PrimitiveValue {
     Integer(i32),
     Float(f64),
@@ -1,5 +1,7 @@
+use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{self, BufRead};
+use std::path::{Component, Path, PathBuf};
 
 pub fn read_file_lines(path: &str) -> io::Result<Vec<String>> {
     // Open the file in read-only mode with error handling
@@ -39,3 +41,62 @@ pub fn list_assets_subdirectory(dir_path: &str) -> Result<Vec<String>, std::io::
     let path = env!("CARGO_MANIFEST_DIR").to_owned() + &format!("/assets/{}", { dir_path });
     list_files_in_directory(&path)
 }
+
+/// Drops `.` and resolves `..` without touching the filesystem, so the same file
+/// always ends up under the same key no matter how it was referenced.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Normal(part) => normalized.push(part),
+            Component::RootDir | Component::Prefix(_) => {}
+        }
+    }
+    normalized
+}
+
+/// Regular files of an uncompressed tar archive by their path inside it.
+pub fn read_tar(bytes: &[u8]) -> io::Result<HashMap<PathBuf, Vec<u8>>> {
+    const BLOCK: usize = 512;
+    let broken =
+        |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("broken tar: {}", what));
+    let text = |field: &[u8]| {
+        let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+        String::from_utf8_lossy(&field[..end]).into_owned()
+    };
+
+    let mut files = HashMap::new();
+    let mut offset = 0;
+    while offset + BLOCK <= bytes.len() {
+        let header = &bytes[offset..offset + BLOCK];
+        // two zero blocks mark the end, one is enough to stop
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        let size_field = text(&header[124..136]);
+        let size = usize::from_str_radix(size_field.trim(), 8)
+            .map_err(|_| broken("bad entry size"))?;
+        let start = offset + BLOCK;
+        let end = start
+            .checked_add(size)
+            .filter(|end| *end <= bytes.len())
+            .ok_or_else(|| broken("entry runs past the end"))?;
+
+        let mut name = PathBuf::new();
+        // the prefix field only means that in posix archives, gnu ones keep times there
+        if &header[257..263] == b"ustar\0" {
+            name.push(text(&header[345..500]));
+        }
+        name.push(text(&header[0..100]));
+        let kind = header[156];
+        if kind == b'0' || kind == 0 {
+            files.insert(normalize_path(&name), bytes[start..end].to_vec());
+        }
+        offset = start + size.div_ceil(BLOCK) * BLOCK;
+    }
+    Ok(files)
+}
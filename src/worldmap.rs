@@ -1,18 +1,40 @@
-use crate::core::animation::BasicTileAnimation;
+use crate::building::Connector;
+use crate::core::animation::TileAnimation;
 use crate::core::position::{Ps, XYprovider};
+use crate::core::rng::WorldRng;
+use crate::hierarchy::Hierarchy;
 use comfy::{num_traits::ToPrimitive, Itertools};
-use comfy::{HashMap, IVec2, RandomRange};
+use comfy::{Entity, HashMap, IVec2};
+use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
 use tiled::PropertyValue;
 
-#[derive(Debug)]
+/// Cost of walking into a plain floor cell, the tile "cost" property is a multiple of it.
+pub const BASE_COST: u32 = 10;
+
+/// What a diagonal step into a cell costs when a straight one costs `cost`, √2 times more.
+pub fn diagonal_cost(cost: u32) -> u32 {
+    cost * 1414 / 1000
+}
+
+/// Who is walking, some cells are open only to dogs or only to humans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Walker {
+    Dog,
+    Human,
+}
+
+#[derive(Debug, Clone)]
 pub struct TileReference {
+    /// Id within `tileset`, ids of different tilesets overlap.
     pub tile_index: u32,
+    pub tileset: String,
     pub klass: String,
     pub tile_name: String,
     pub tile_image: String,
     pub props: HashMap<String, PropertyValue>,
     pub size: IVec2,
-    pub animated: Option<BasicTileAnimation>,
+    pub animated: Option<TileAnimation>,
 }
 
 impl TileReference {
@@ -41,16 +63,102 @@ impl TileReference {
             None
         }
     }
+
+    pub fn int_prop(&self, name: &str) -> Option<i32> {
+        self.props.get(name).and_then(Self::extract_int_value)
+    }
+}
+
+/// Creature placed on the map by hand instead of at a random cell.
+#[derive(Debug, Clone)]
+pub struct SpawnPoint {
+    pub item_type: &'static str,
+    pub name: Option<String>,
+    pub ps: Ps,
+    pub speed: Option<f32>,
+}
+
+/// Puts `count` items within `radius` cells of `ps` when the world is populated.
+#[derive(Debug, Clone)]
+pub struct ItemSpawner {
+    pub item_type: &'static str,
+    pub ps: Ps,
+    pub count: usize,
+    pub radius: usize,
+}
+
+/// Named area of the map, both corners included.
+#[derive(Debug, Clone)]
+pub struct Zone {
+    pub name: String,
+    pub from: Ps,
+    pub to: Ps,
+}
+
+impl Zone {
+    pub fn contains(&self, ps: Ps) -> bool {
+        ps.floor == self.from.floor
+            && ps.x >= self.from.x
+            && ps.x <= self.to.x
+            && ps.y >= self.from.y
+            && ps.y <= self.to.y
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = Ps> + '_ {
+        let floor = self.from.floor;
+        (self.from.y..=self.to.y)
+            .flat_map(move |y| (self.from.x..=self.to.x).map(move |x| Ps { x, y, floor }))
+    }
+}
+
+/// What the map's object layers put into the world.
+#[derive(Debug, Clone, Default)]
+pub struct MapObjects {
+    pub spawns: Vec<SpawnPoint>,
+    pub item_spawners: Vec<ItemSpawner>,
+    pub zones: Vec<Zone>,
+}
+
+impl MapObjects {
+    pub fn zone(&self, name: &str) -> Option<&Zone> {
+        self.zones.iter().find(|zone| zone.name == name)
+    }
+
+    pub fn zones_at(&self, ps: Ps) -> impl Iterator<Item = &Zone> {
+        self.zones.iter().filter(move |zone| zone.contains(ps))
+    }
+
+    fn move_to_floor(&mut self, floor: usize) {
+        for spawn in self.spawns.iter_mut() {
+            spawn.ps.floor = floor;
+        }
+        for spawner in self.item_spawners.iter_mut() {
+            spawner.ps.floor = floor;
+        }
+        for zone in self.zones.iter_mut() {
+            zone.from.floor = floor;
+            zone.to.floor = floor;
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct CellStatus {
+    /// Blocked by something that is not an agent, like the player's selection.
     pub occupied: bool,
+    /// Agent standing in the cell or walking into it.
+    pub occupant: Option<Entity>,
 }
 
 #[derive(Debug)]
 pub struct Cell {
     pub passable: bool,
+    /// What walking into the cell costs, `BASE_COST` for a plain floor.
+    pub cost: u32,
+    pub dog_passable: bool,
+    pub human_passable: bool,
+    /// Stairs or elevator to the floors above and below.
+    pub connector: Option<Connector>,
     pub reference: Option<TileReference>,
     pub position: Ps,
     pub status: CellStatus,
@@ -58,7 +166,10 @@ pub struct Cell {
 
 impl CellStatus {
     fn new() -> CellStatus {
-        return CellStatus { occupied: false };
+        CellStatus {
+            occupied: false,
+            occupant: None,
+        }
     }
 }
 
@@ -66,6 +177,10 @@ impl Cell {
     pub fn new(position: (i32, i32), passable: bool, reference: Option<TileReference>) -> Cell {
         Cell {
             passable: passable,
+            cost: BASE_COST,
+            dog_passable: true,
+            human_passable: true,
+            connector: None,
             reference: reference,
             position: position.into(),
             status: CellStatus::new(),
@@ -80,14 +195,51 @@ impl Cell {
         self.status.occupied = false;
     }
 
+    pub fn is_occupied(&self) -> bool {
+        self.status.occupied || self.status.occupant.is_some()
+    }
+
+    /// Free to walk into for `entity`, the cell it already holds counts as free.
+    pub fn is_free_for(&self, entity: Entity) -> bool {
+        self.passable
+            && !self.status.occupied
+            && self.status.occupant.is_none_or(|occupant| occupant == entity)
+    }
+
     pub fn is_passable(&self, concern_occupied: bool) -> bool {
         if concern_occupied {
-            return self.passable && !self.status.occupied;
+            self.passable && !self.is_occupied()
         } else {
-            return self.passable;
+            self.passable
         }
     }
 
+    pub fn is_passable_for(&self, walker: Walker, concern_occupied: bool) -> bool {
+        self.is_passable(concern_occupied)
+            && match walker {
+                Walker::Dog => self.dog_passable,
+                Walker::Human => self.human_passable,
+            }
+    }
+
+    /// Same for anyone walking, no matter what the tile looks like.
+    pub fn walks_like(&self, other: &Cell) -> bool {
+        self.passable == other.passable
+            && self.cost == other.cost
+            && self.dog_passable == other.dog_passable
+            && self.human_passable == other.human_passable
+            && self.connector == other.connector
+    }
+
+    /// Hashes what `walks_like` compares.
+    pub fn hash_walking<H: Hasher>(&self, state: &mut H) {
+        self.passable.hash(state);
+        self.cost.hash(state);
+        self.dog_passable.hash(state);
+        self.human_passable.hash(state);
+        self.connector.hash(state);
+    }
+
     pub fn get_tile_name(&self) -> Option<String> {
         match &self.reference {
             Some(refer) => Some(refer.tile_image.to_string()),
@@ -101,17 +253,120 @@ pub struct Cellmap {
     pub map: Vec<Cell>,
     width: usize,
     height: usize,
+    /// Tiled coordinates of the top left cell, not zero for maps cut out of an infinite one.
+    origin: IVec2,
+    floor: usize,
+    pub objects: MapObjects,
+    // cheapest cell, keeps the A* heuristic from overestimating
+    min_cost: u32,
+    // clusters for long routes, they are found again when their cells change
+    hierarchy: Hierarchy,
+    // counts changes to the cells, whatever is found over them knows when it is stale
+    revision: u64,
 }
 
 type DumpCellClosure = dyn Fn(&Cell) -> String;
 
 impl Cellmap {
     pub fn new(vec: Vec<Option<Cell>>, width: i32, height: i32) -> Cellmap {
+        let map = vec.into_iter().map(|x| x.unwrap()).collect_vec();
+        let min_cost = map.iter().map(|cell| cell.cost).min().unwrap_or(BASE_COST);
+        let (width, height) = (width.to_usize().unwrap(), height.to_usize().unwrap());
         Cellmap {
-            map: vec.into_iter().map(|x| x.unwrap()).collect_vec(),
-            width: width.to_usize().unwrap(),
-            height: height.to_usize().unwrap(),
+            map,
+            min_cost,
+            hierarchy: Hierarchy::new(width, height),
+            revision: 0,
+            width,
+            height,
+            origin: IVec2::ZERO,
+            floor: 0,
+            objects: MapObjects::default(),
+        }
+    }
+
+    pub fn with_origin(mut self, origin: IVec2) -> Cellmap {
+        self.origin = origin;
+        self
+    }
+
+    /// Puts the map on a floor of the building, with everything placed on it.
+    pub fn with_floor(mut self, floor: usize) -> Cellmap {
+        self.floor = floor;
+        for cell in self.map.iter_mut() {
+            cell.position.floor = floor;
         }
+        self.objects.move_to_floor(floor);
+        self
+    }
+
+    pub fn floor(&self) -> usize {
+        self.floor
+    }
+
+    pub fn min_cost(&self) -> u32 {
+        self.min_cost
+    }
+
+    /// What stepping from `from` into the cell `to` next to it costs, across a corner or not.
+    pub fn step_cost(&self, from: &Ps, to: &Ps) -> u32 {
+        let cost = self.get_pos(to).cost;
+        if from.x != to.x && from.y != to.y {
+            diagonal_cost(cost)
+        } else {
+            cost
+        }
+    }
+
+    pub fn hierarchy(&self) -> &Hierarchy {
+        &self.hierarchy
+    }
+
+    /// Grows every time walking over a cell changes.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Changes what walking into the cell costs, use this instead of writing `Cell::cost`.
+    pub fn set_cost(&mut self, pos: &Ps, cost: u32) {
+        self.get_pos_mut(pos).cost = cost;
+        self.hierarchy.invalidate(pos);
+        self.revision += 1;
+        self.min_cost = self.map.iter().map(|cell| cell.cost).min().unwrap_or(BASE_COST);
+    }
+
+    /// Puts `cell` in place of the one at `pos`. A cell blocked by the player stays blocked,
+    /// agents have to take the cell again.
+    pub fn replace_cell(&mut self, pos: &Ps, mut cell: Cell) {
+        let old = self.get_pos_mut(pos);
+        cell.position = old.position;
+        cell.status.occupied = old.status.occupied;
+        *old = cell;
+        self.hierarchy.invalidate(pos);
+        self.revision += 1;
+        self.min_cost = self.map.iter().map(|cell| cell.cost).min().unwrap_or(BASE_COST);
+    }
+
+    pub fn origin(&self) -> IVec2 {
+        self.origin
+    }
+
+    /// Tile coordinates Tiled uses for the cell, y grows down there.
+    pub fn ps_to_tiled(&self, pos: Ps) -> IVec2 {
+        IVec2::new(
+            self.origin.x + pos.x as i32,
+            self.origin.y + self.height as i32 - 1 - pos.y as i32,
+        )
+    }
+
+    pub fn tiled_to_ps(&self, tile: IVec2) -> Option<Ps> {
+        let x = tile.x - self.origin.x;
+        let y = self.height as i32 - 1 - (tile.y - self.origin.y);
+        self.within_bounds(x, y).then(|| Ps {
+            x: x as usize,
+            y: y as usize,
+            floor: self.floor,
+        })
     }
 
     pub fn occupy_ps(&mut self, pos: &Ps) {
@@ -122,11 +377,6 @@ impl Cellmap {
         self.get_pos_mut(pos).deoccupy();
     }
 
-    pub fn move_occupy_ps(&mut self, pos_from: &Ps, pos_to: &Ps) {
-        self.get_pos_mut(pos_from).deoccupy();
-        self.get_pos_mut(pos_to).occupy();
-    }
-
     pub fn occupy_xy<T>(&mut self, x: T, y: T)
     where
         T: TryInto<usize> + Copy,
@@ -210,17 +460,54 @@ impl Cellmap {
         return (self.width, self.height);
     }
 
-    pub fn pick_random_passable_ps(&self) -> Ps {
+    pub fn pick_random_passable_ps(&self, rng: &mut WorldRng) -> Ps {
         // UNSAFE!!! Not guaranteed to ever finish
         loop {
-            let x = usize::gen_range(0, self.width);
-            let y = usize::gen_range(0, self.height);
-            if self.get_xy(x, y).is_passable(true) {
-                return Ps { x, y };
+            let x = rng.gen_range(0, self.width);
+            let y = rng.gen_range(0, self.height);
+            let cell = self.get_xy(x, y);
+            if cell.is_passable(true) {
+                return cell.position;
             }
         }
     }
 
+    /// Random passable cell at most `radius` cells away on each axis, `center` if there is none.
+    pub fn pick_passable_ps_near(&self, center: Ps, radius: usize, rng: &mut WorldRng) -> Ps {
+        let (w, h) = self.wh_usize();
+        let xs = center.x.saturating_sub(radius)..=(center.x + radius).min(w - 1);
+        let ys = center.y.saturating_sub(radius)..=(center.y + radius).min(h - 1);
+        let candidates = ys
+            .flat_map(|y| {
+                xs.clone().map(move |x| Ps {
+                    x,
+                    y,
+                    floor: center.floor,
+                })
+            })
+            .filter(|ps| self.get_pos(ps).is_passable(true))
+            .collect_vec();
+        rng.choose(&candidates).copied().unwrap_or(center)
+    }
+
+    /// Free cell closest to `from` that `walker` can stand on, `from` itself may be out of bounds.
+    pub fn nearest_free_ps(&self, from: Ps, walker: Walker) -> Option<Ps> {
+        let (w, h) = self.wh_usize();
+        let floor = self.floor;
+        let from = Ps {
+            x: from.x.min(w - 1),
+            y: from.y.min(h - 1),
+            floor,
+        };
+        (0..w.max(h)).find_map(|radius| {
+            let xs = from.x.saturating_sub(radius)..=(from.x + radius).min(w - 1);
+            let ys = from.y.saturating_sub(radius)..=(from.y + radius).min(h - 1);
+            ys.flat_map(|y| xs.clone().map(move |x| Ps { x, y, floor }))
+                .filter(|ps| self.get_pos(ps).is_passable_for(walker, true))
+                .min_by_key(|ps| ps.manhattan_distance(&from))
+        })
+    }
+
     pub fn print(&self, dump_cell_closure: &DumpCellClosure) {
         let mut counter = 0;
         for i in 0..self.map.len() {
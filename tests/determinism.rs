@@ -0,0 +1,88 @@
+// the same seed on the same map has to play out the same, whatever ran before it
+
+use std::path::Path;
+
+use comfy::{commands, world_mut, Mutex};
+use crowdx::{
+    building::Building,
+    core::rng::WorldRng,
+    replay::{Command, Replay},
+    scenario::Scenario,
+    simulation::{Simulation, TICK_DT},
+};
+
+const SEED: u64 = 42;
+const TICKS: u64 = 300;
+
+// every run uses the world everybody shares, one at a time
+static WORLD: Mutex<()> = Mutex::new(());
+
+// the default scenario in a world of its own
+fn scenario() -> (Scenario, Building) {
+    {
+        let mut wrld = world_mut();
+        commands().run_on(&mut wrld);
+        wrld.clear();
+    }
+    let scenario = Scenario::load(Path::new("scenarios/default.json")).unwrap();
+    let (_, building) = scenario.load_building().unwrap();
+    (scenario, building)
+}
+
+// fingerprint of the default scenario after `TICKS`
+fn run(seed: u64) -> u64 {
+    let (scenario, building) = scenario();
+    let mut sim = Simulation::from_scenario(&scenario, building, seed);
+    sim.deterministic = true;
+    sim.run(TICKS, TICK_DT);
+    assert_eq!(sim.tick, TICKS);
+    sim.fingerprint()
+}
+
+#[test]
+fn same_seed_plays_out_the_same() {
+    let _world = WORLD.lock();
+    assert_eq!(run(SEED), run(SEED));
+}
+
+#[test]
+fn replay_recorded_in_the_window_plays_out_the_same() {
+    let _world = WORLD.lock();
+    // set up like the window does: no fixed seed, recording from the first tick on
+    let (scenario, building) = scenario();
+    let mut sim = Simulation::from_scenario(&scenario, building, WorldRng::from_entropy().seed());
+    assert!(!sim.deterministic);
+    let start = sim.start_recording();
+    // in parallel it plays out the same only as long as the threads happen to take turns
+    assert!(sim.deterministic);
+    let mut rng = WorldRng::new(SEED);
+    while sim.tick < TICKS {
+        if sim.tick % 50 == 10 {
+            let ps = sim
+                .reality
+                .building
+                .ground()
+                .pick_random_passable_ps(&mut rng);
+            sim.submit(Command::DogOrder(ps));
+            sim.submit(Command::Occupy(ps));
+        }
+        if sim.tick % 50 == 30 {
+            let ps = sim
+                .reality
+                .building
+                .ground()
+                .pick_random_passable_ps(&mut rng);
+            sim.submit(Command::MoveDogsTo(ps));
+        }
+        sim.step(TICK_DT);
+    }
+    sim.flush_commands();
+    let recorded = sim.fingerprint();
+    let replay = Replay::new(start, &sim, &scenario.name, TICK_DT, TICKS);
+    drop(sim);
+
+    let (scenario, building) = self::scenario();
+    let replayed = replay.play(&scenario.name, building).unwrap();
+    assert_eq!(replayed.tick, TICKS);
+    assert_eq!(replayed.fingerprint(), recorded);
+}